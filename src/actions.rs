@@ -0,0 +1,147 @@
+use anyhow::{anyhow, Result};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::grpc_client::chat::Action;
+
+/// Shared writer to the shell's PTY
+pub type PtyWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// Something the agent asks the wrapper to do in the shell
+/// Never performed without an explicit confirmation from the user
+#[derive(Debug, Clone, PartialEq)]
+pub enum AgentAction {
+    /// Run a shell command
+    RunCommand(String),
+
+    /// Type a command at the prompt without running it, the user reviews it and presses Enter
+    InsertCommand(String),
+
+    /// Export an environment variable in the shell
+    SetEnv { key: String, value: String },
+
+    /// Open a file in the user's editor
+    OpenFile(String),
+}
+
+impl AgentAction {
+    /// Convert the action received over gRPC, None for unknown or malformed actions
+    pub fn from_proto(action: &Action) -> Option<Self> {
+        let value = action.value.trim();
+        // A newline or any other control character would run (or edit) more than the confirmation
+        // shows: a second command after `\r`, a line cleared by Ctrl+U, an escape sequence...
+        if value.is_empty() || value.chars().any(char::is_control) {
+            return None;
+        }
+
+        match action.kind.as_str() {
+            "run_command" => Some(AgentAction::RunCommand(value.to_string())),
+            "insert_command" => Some(AgentAction::InsertCommand(value.to_string())),
+            "set_env" => {
+                let (key, value) = value.split_once('=')?;
+                let valid_key = !key.is_empty()
+                    && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    && !key.starts_with(|c: char| c.is_ascii_digit());
+                valid_key.then(|| AgentAction::SetEnv {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+            }
+            "open_file" => Some(AgentAction::OpenFile(value.to_string())),
+            _ => None,
+        }
+    }
+
+    /// Short description shown in the confirmation prompt
+    pub fn describe(&self) -> String {
+        match self {
+            AgentAction::RunCommand(command) => format!("Exécuter: {}", command),
+            AgentAction::InsertCommand(command) => format!("Taper dans l'invite (sans exécuter): {}", command),
+            AgentAction::SetEnv { key, value } => format!("Définir: {}={}", key, value),
+            AgentAction::OpenFile(path) => format!("Ouvrir: {}", path),
+        }
+    }
+
+    /// Whether the action does something once confirmed, rather than only filling the prompt
+    pub fn runs(&self) -> bool {
+        !matches!(self, AgentAction::InsertCommand(_))
+    }
+
+    /// Shell input performing the action, including the trailing carriage return
+    /// An inserted command has none: Ctrl+U clears a half-typed line, then the command is typed
+    pub fn to_shell_input(&self) -> String {
+        match self {
+            AgentAction::RunCommand(command) => format!("{}\r", command),
+            AgentAction::InsertCommand(command) => format!("\x15{}", command),
+            AgentAction::SetEnv { key, value } => format!("export {}={}\r", key, shell_quote(value)),
+            AgentAction::OpenFile(path) => format!("${{EDITOR:-vi}} {}\r", shell_quote(path)),
+        }
+    }
+
+    /// Write the action to the shell
+    pub fn execute(&self, writer: &PtyWriter) -> Result<()> {
+        let mut w = writer.lock().map_err(|_| anyhow!("PTY writer lock poisoned"))?;
+        w.write_all(self.to_shell_input().as_bytes())?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+/// Single-quote a value for the shell
+pub(crate) fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(kind: &str, value: &str) -> Action {
+        Action {
+            kind: kind.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_from_proto() {
+        assert_eq!(
+            AgentAction::from_proto(&action("run_command", "ls -la")),
+            Some(AgentAction::RunCommand("ls -la".to_string()))
+        );
+        assert_eq!(
+            AgentAction::from_proto(&action("set_env", "TARGET=10.0.0.1")),
+            Some(AgentAction::SetEnv {
+                key: "TARGET".to_string(),
+                value: "10.0.0.1".to_string()
+            })
+        );
+        assert_eq!(AgentAction::from_proto(&action("set_env", "1BAD=x")), None);
+        assert_eq!(AgentAction::from_proto(&action("format_disk", "/")), None);
+        assert_eq!(AgentAction::from_proto(&action("run_command", "  ")), None);
+        assert_eq!(
+            AgentAction::from_proto(&action("insert_command", "git push --force-with-lease")),
+            Some(AgentAction::InsertCommand("git push --force-with-lease".to_string()))
+        );
+        assert_eq!(AgentAction::from_proto(&action("insert_command", "ls\nrm -rf x")), None);
+        assert_eq!(AgentAction::from_proto(&action("run_command", "ls\rrm -rf ~")), None);
+        assert_eq!(AgentAction::from_proto(&action("run_command", "ls\x1b[2K")), None);
+        assert_eq!(AgentAction::from_proto(&action("set_env", "A=1\nrm -rf ~")), None);
+    }
+
+    #[test]
+    fn test_shell_input_quotes_values() {
+        let set_env = AgentAction::SetEnv {
+            key: "MSG".to_string(),
+            value: "it's $HOME".to_string(),
+        };
+        assert_eq!(set_env.to_shell_input(), "export MSG='it'\\''s $HOME'\r");
+
+        let open = AgentAction::OpenFile("notes.md".to_string());
+        assert_eq!(open.to_shell_input(), "${EDITOR:-vi} 'notes.md'\r");
+
+        let insert = AgentAction::InsertCommand("make deploy".to_string());
+        assert_eq!(insert.to_shell_input(), "\x15make deploy");
+        assert!(!insert.runs());
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Largest file that can be attached to a chat message
+pub const MAX_ATTACHMENT_BYTES: u64 = 64 * 1024;
+
+/// Bytes read from the end of a file for a tail, however many lines were asked
+pub const MAX_TAIL_BYTES: u64 = 16 * 1024;
+
+/// Lines attached from a command's output file by default
+pub const DEFAULT_TAIL_LINES: usize = 50;
+
+/// Read a file to attach as chat context, formatted with a `# File:` header
+/// Errors are user-facing messages (missing file, permissions, too large, binary)
+pub fn read_attachment(path: &str) -> Result<String, String> {
+    let resolved = expand_home(path);
+
+    let metadata = std::fs::metadata(&resolved).map_err(|e| describe_io_error(path, e.kind()))?;
+    if !metadata.is_file() {
+        return Err(format!("{} n'est pas un fichier", path));
+    }
+    if metadata.len() > MAX_ATTACHMENT_BYTES {
+        return Err(format!(
+            "{} est trop volumineux ({} Ko, max {} Ko)",
+            path,
+            metadata.len() / 1024,
+            MAX_ATTACHMENT_BYTES / 1024
+        ));
+    }
+
+    let bytes = std::fs::read(&resolved).map_err(|e| describe_io_error(path, e.kind()))?;
+    if is_binary(&bytes) {
+        return Err(format!("{} semble être un fichier binaire", path));
+    }
+    let content = String::from_utf8(bytes).map_err(|_| format!("{} n'est pas en UTF-8", path))?;
+
+    Ok(format!("# File: {}\n{}", path, content))
+}
+
+/// Last `lines` lines of a file, formatted with a `# File:` header
+/// Only the end of a large file is read (`MAX_TAIL_BYTES`), errors are user-facing messages
+pub fn read_tail(path: &Path, lines: usize) -> Result<String, String> {
+    let name = path.display().to_string();
+    let mut file = std::fs::File::open(path).map_err(|e| describe_io_error(&name, e.kind()))?;
+    let len = file.metadata().map_err(|e| describe_io_error(&name, e.kind()))?.len();
+    let start = len.saturating_sub(MAX_TAIL_BYTES);
+    file.seek(SeekFrom::Start(start)).map_err(|e| describe_io_error(&name, e.kind()))?;
+
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).map_err(|e| describe_io_error(&name, e.kind()))?;
+    if is_binary(&bytes) {
+        return Err(format!("{} semble être un fichier binaire", name));
+    }
+
+    let text = String::from_utf8_lossy(&bytes);
+    let mut kept: Vec<&str> = text.lines().collect();
+    // Reading from the middle of the file starts with a partial line
+    if start > 0 && kept.len() > 1 {
+        kept.remove(0);
+    }
+    let kept = &kept[kept.len().saturating_sub(lines)..];
+    Ok(format!("# File: {} (last {} lines)\n{}\n", name, kept.len(), kept.join("\n")))
+}
+
+/// File a shell command writes its output to, guessed from its text (`> file`, `>> file`,
+/// `| tee file`), the last one wins
+/// Descriptor duplications (`2>&1`) and /dev/null are not files worth reading
+pub fn redirect_target(command: &str) -> Option<String> {
+    let mut target = None;
+    let mut tokens = command.split_whitespace();
+    while let Some(token) = tokens.next() {
+        let file = if token == "tee" {
+            // First argument that isn't an option (tee -a log)
+            tokens.find(|arg| !arg.starts_with('-'))
+        } else {
+            match token.trim_start_matches(['&', '1', '2']).strip_prefix('>') {
+                None => None,
+                Some(rest) => match rest.trim_start_matches('>') {
+                    "" => tokens.next(),
+                    rest => Some(rest),
+                },
+            }
+        };
+        if let Some(file) = file.map(|f| f.trim_matches(['"', '\'', ';']))
+            && !file.is_empty()
+            && !file.starts_with('&')
+            && file != "/dev/null"
+        {
+            target = Some(file.to_string());
+        }
+    }
+    target
+}
+
+/// Expand a leading `~/` to the home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Files with NUL bytes near the start are treated as binary
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(8192).any(|&b| b == 0)
+}
+
+fn describe_io_error(path: &str, kind: ErrorKind) -> String {
+    match kind {
+        ErrorKind::NotFound => format!("Fichier introuvable: {}", path),
+        ErrorKind::PermissionDenied => format!("Permission refusée: {}", path),
+        _ => format!("Impossible de lire {} ({:?})", path, kind),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, content: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("petoncle-attach-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_attach_text_file() {
+        let path = temp_file("config.txt", b"port = 8080\n");
+        let attached = read_attachment(&path).unwrap();
+        assert_eq!(attached, format!("# File: {}\nport = 8080\n", path));
+    }
+
+    #[test]
+    fn test_attach_rejects_binary_and_missing() {
+        let path = temp_file("blob.bin", &[0x7f, b'E', b'L', b'F', 0, 0, 1]);
+        assert!(read_attachment(&path).unwrap_err().contains("binaire"));
+        assert!(read_attachment("/nonexistent/petoncle").unwrap_err().contains("introuvable"));
+    }
+
+    #[test]
+    fn test_redirect_target() {
+        assert_eq!(redirect_target("make > build.log 2>&1").as_deref(), Some("build.log"));
+        assert_eq!(redirect_target("echo x >>notes.txt").as_deref(), Some("notes.txt"));
+        assert_eq!(redirect_target("cargo test 2>&1 | tee -a test.log").as_deref(), Some("test.log"));
+        assert_eq!(redirect_target("./run &> '/tmp/run.log'").as_deref(), Some("/tmp/run.log"));
+        assert_eq!(redirect_target("find / -name x 2>/dev/null"), None);
+        assert_eq!(redirect_target("ls -la"), None);
+        // Numbers in arguments aren't redirections
+        assert_eq!(redirect_target("head -n 12 file"), None);
+    }
+
+    #[test]
+    fn test_read_tail() {
+        let lines: Vec<String> = (1..=5000).map(|i| format!("ligne {}", i)).collect();
+        let path = temp_file("build.log", lines.join("\n").as_bytes());
+        let tail = read_tail(Path::new(&path), 3).unwrap();
+        assert_eq!(tail, format!("# File: {} (last 3 lines)\nligne 4998\nligne 4999\nligne 5000\n", path));
+
+        // Only the end of the file is read, and never from the middle of a line
+        let tail = read_tail(Path::new(&path), 100_000).unwrap();
+        assert!(tail.len() as u64 <= MAX_TAIL_BYTES + 100);
+        assert!(tail.lines().nth(1).unwrap().starts_with("ligne "));
+
+        assert!(read_tail(Path::new("/nonexistent/petoncle.log"), 3).unwrap_err().contains("introuvable"));
+    }
+
+    #[test]
+    fn test_attach_rejects_oversized() {
+        let path = temp_file("big.txt", &vec![b'a'; MAX_ATTACHMENT_BYTES as usize + 1]);
+        assert!(read_attachment(&path).unwrap_err().contains("trop volumineux"));
+    }
+}
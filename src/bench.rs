@@ -0,0 +1,146 @@
+use anyhow::{Context, Result};
+use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use std::hint::black_box;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::capture::CommandCapture;
+use crate::hooks::{self, Shell};
+use crate::scrollback::Scrollback;
+
+/// Times each recorded session is replayed, to smooth out noise
+const ITERATIONS: u32 = 20;
+
+/// Scrollback size used while measuring (the default config value)
+const BENCH_SCROLLBACK_BYTES: usize = 100_000;
+
+/// Commands run by `--bench`: bulk output, many short commands, multi-byte text
+fn bench_script() -> Vec<String> {
+    let mut script = vec![
+        "seq 1 50000".to_string(),
+        "printf 'café 🎉 %.0s' {1..5000}; echo".to_string(),
+        "ls -la /usr/bin".to_string(),
+    ];
+    script.extend((0..200).map(|i| format!("echo run {}", i)));
+    script.push("exit".to_string());
+    script
+}
+
+/// `petoncle --bench`: record a scripted shell session through a PTY, then replay its output
+/// through a bare passthrough and through the capture path, and report the difference
+pub fn run(shell: Shell) -> Result<()> {
+    println!("⏱️  Enregistrement d'une session de référence...");
+    let chunks = record_session(shell)?;
+    let bytes: usize = chunks.iter().map(Vec::len).sum();
+
+    let cwd = PathBuf::from("/");
+    let passthrough = time_replay(&chunks, |chunk, replay| {
+        replay.stdout.extend_from_slice(chunk);
+    });
+    let mut commands = 0;
+    let capture = time_replay(&chunks, |chunk, replay| {
+        replay.capture.process_bytes(chunk, &cwd);
+        replay.scrollback.push(chunk);
+        replay.stdout.extend_from_slice(chunk);
+        commands = replay.capture.get_commands().len() + usize::from(replay.capture.current().is_some());
+    });
+
+    let per_chunk = capture.saturating_sub(passthrough) / chunks.len().max(1) as u32;
+    println!();
+    println!("Sortie enregistrée : {} octets en {} lectures, {} commandes capturées", bytes, chunks.len(), commands);
+    println!("Passthrough        : {:>10.2?} / session ({})", passthrough, throughput(bytes, passthrough));
+    println!("Avec capture       : {:>10.2?} / session ({})", capture, throughput(bytes, capture));
+    println!("Surcoût par lecture: {:>10.2?}", per_chunk);
+    Ok(())
+}
+
+/// What the output thread does with each read
+struct Replay {
+    capture: CommandCapture,
+    scrollback: Scrollback,
+
+    /// Stands in for the terminal
+    stdout: Vec<u8>,
+}
+
+/// Average time to push every chunk through `step`
+fn time_replay(chunks: &[Vec<u8>], mut step: impl FnMut(&[u8], &mut Replay)) -> Duration {
+    let mut total = Duration::ZERO;
+    for _ in 0..ITERATIONS {
+        let mut replay = Replay {
+            capture: CommandCapture::new(),
+            scrollback: Scrollback::new(BENCH_SCROLLBACK_BYTES),
+            stdout: Vec::new(),
+        };
+
+        let start = Instant::now();
+        for chunk in chunks {
+            step(black_box(chunk), &mut replay);
+        }
+        total += start.elapsed();
+        black_box(&replay);
+    }
+    total / ITERATIONS
+}
+
+fn throughput(bytes: usize, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    if secs == 0.0 {
+        return "∞".to_string();
+    }
+    format!("{:.1} Mo/s", bytes as f64 / secs / 1_000_000.0)
+}
+
+/// Run the script in a hooked shell and keep every read as it came out of the PTY
+/// HOME points to an empty directory so the user's own config doesn't skew the numbers
+fn record_session(shell: Shell) -> Result<Vec<Vec<u8>>> {
+    let pair = native_pty_system()
+        .openpty(PtySize {
+            rows: 24,
+            cols: 80,
+            pixel_width: 0,
+            pixel_height: 0,
+        })
+        .context("Failed to create PTY")?;
+
+    let dir = std::env::temp_dir().join(format!("petoncle-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).context("Failed to create bench dir")?;
+    hooks::write_hook_files(shell, &dir).context("Failed to write bench startup files")?;
+
+    let mut cmd = CommandBuilder::new(shell.program());
+    cmd.env("TERM", "xterm-256color");
+    cmd.env("HOME", &dir);
+    cmd.env("ZDOTDIR", &dir);
+    cmd.env_remove(hooks::USER_ZDOTDIR_VAR);
+    cmd.cwd(&dir);
+    let mut child = pair.slave.spawn_command(cmd).context("Failed to spawn bench shell")?;
+    drop(pair.slave);
+
+    let mut reader = pair.master.try_clone_reader()?;
+    let mut writer = pair.master.take_writer()?;
+    for line in bench_script() {
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\r")?;
+    }
+    writer.flush()?;
+
+    let mut chunks = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => chunks.push(buf[..n].to_vec()),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            // EIO once the shell has exited and the PTY closed
+            Err(_) => break,
+        }
+    }
+    child.wait().ok();
+    std::fs::remove_dir_all(&dir).ok();
+
+    if chunks.is_empty() {
+        anyhow::bail!("The bench shell produced no output");
+    }
+    Ok(chunks)
+}
@@ -0,0 +1,143 @@
+use anyhow::{Context, Result};
+use portable_pty::{Child, CommandBuilder, MasterPty, PtySize, PtySystem};
+use std::io::{self, Read, Write};
+use std::thread;
+use tracing::{debug, info, Span};
+
+/// Writer sending every byte to the lead shell and to the followers still alive (`--split N`)
+/// A follower that stops accepting input is dropped, only the lead failing is an error
+///
+/// The lead's output is displayed, captured and kept for the scrollback viewer as in a
+/// single-shell session. Followers' output is read and discarded: several shells' escape
+/// sequences written to one terminal would interleave into garbage, and tiling them needs a
+/// terminal emulator per pane. The chat overlay only pauses the lead's output, and agent
+/// actions go through this writer so every shell runs them.
+pub struct BroadcastWriter {
+    lead: Box<dyn Write + Send>,
+    followers: Vec<Box<dyn Write + Send>>,
+}
+
+impl BroadcastWriter {
+    pub fn new(lead: Box<dyn Write + Send>, followers: Vec<Box<dyn Write + Send>>) -> Self {
+        Self { lead, followers }
+    }
+}
+
+impl Write for BroadcastWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lead.write_all(buf)?;
+        self.followers.retain_mut(|follower| match follower.write_all(buf) {
+            Ok(()) => true,
+            Err(e) => {
+                info!("Follower shell input closed ({}), no longer mirrored", e);
+                false
+            }
+        });
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for follower in &mut self.followers {
+            follower.flush().ok();
+        }
+        self.lead.flush()
+    }
+}
+
+/// A shell mirroring the lead's input
+pub struct Follower {
+    child: Box<dyn Child + Send + Sync>,
+    master: Box<dyn MasterPty + Send>, // Keeps the PTY open for the shell
+}
+
+impl Follower {
+    /// Spawn a follower shell and the thread draining its output
+    /// Returns it with the writer to add to the broadcast
+    pub fn spawn(
+        pty_system: &dyn PtySystem,
+        size: PtySize,
+        cmd: CommandBuilder,
+        number: usize,
+    ) -> Result<(Self, Box<dyn Write + Send>)> {
+        let pair = pty_system.openpty(size).context("Failed to create PTY")?;
+        let child = pair.slave.spawn_command(cmd).context("Failed to spawn shell")?;
+        let writer = pair.master.take_writer()?;
+        let mut reader = pair.master.try_clone_reader()?;
+
+        let span = Span::current();
+        thread::spawn(move || {
+            let _entered = span.enter();
+            let mut buf = [0u8; 8192];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        debug!("Follower shell {} output closed: {}", number, e);
+                        break;
+                    }
+                }
+            }
+            info!("Follower shell {} exited", number);
+        });
+
+        Ok((Self { child, master: pair.master }, writer))
+    }
+
+    /// Follow the terminal size like the lead, a failure only affects this shell's layout
+    pub fn resize(&self, size: PtySize) {
+        if let Err(e) = self.master.resize(size) {
+            debug!("Failed to resize a follower PTY: {}", e);
+        }
+    }
+
+    /// End the shell along with the session
+    pub fn stop(mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            portable_pty::ChildKiller::kill(&mut *self.child).ok();
+            self.child.wait().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Writer recording into a shared buffer, or failing like a closed PTY
+    struct Recorder(Arc<Mutex<Vec<u8>>>, bool);
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.1 {
+                return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+            }
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_broadcast_survives_a_closed_follower() {
+        let lead = Arc::new(Mutex::new(Vec::new()));
+        let follower = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = BroadcastWriter::new(
+            Box::new(Recorder(lead.clone(), false)),
+            vec![Box::new(Recorder(follower.clone(), false)), Box::new(Recorder(Arc::default(), true))],
+        );
+
+        writer.write_all(b"ls\r").unwrap();
+        assert_eq!(writer.followers.len(), 1);
+        assert_eq!(*lead.lock().unwrap(), b"ls\r");
+        assert_eq!(*follower.lock().unwrap(), b"ls\r");
+
+        let mut writer = BroadcastWriter::new(Box::new(Recorder(Arc::default(), true)), Vec::new());
+        assert!(writer.write_all(b"x").is_err());
+    }
+}
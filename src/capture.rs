@@ -1,5 +1,11 @@
 use chrono::{DateTime, Local};
-use std::path::PathBuf;
+use ratatui::style::Color;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::fuzzy::fuzzy_match;
 
 /// A captured command with its execution context and output
 #[derive(Debug, Clone)]
@@ -16,8 +22,23 @@ pub struct CapturedCommand {
     /// Timestamp when command was entered
     pub timestamp: DateTime<Local>,
 
+    /// When the end marker arrived, None while the command runs (or if it never ended)
+    pub finished_at: Option<DateTime<Local>>,
+
     /// Working directory when command was executed
     pub working_dir: PathBuf,
+
+    /// Pinned by the user: never evicted and always sent as context
+    pub pinned: bool,
+
+    /// Free-form annotation from the user (e.g. "this is the repro step")
+    pub note: Option<String>,
+
+    /// The last chunk ended on a carriage return, whether it starts a newline isn't known yet
+    pending_cr: bool,
+
+    /// Where the last line of `output` starts, so a redraw doesn't search the whole output for it
+    line_start: usize,
 }
 
 impl CapturedCommand {
@@ -27,24 +48,241 @@ impl CapturedCommand {
             output: String::new(),
             exit_code: None,
             timestamp: Local::now(),
+            finished_at: None,
             working_dir,
+            pinned: false,
+            note: None,
+            pending_cr: false,
+            line_start: 0,
         }
     }
 
     /// Add output chunk to this command's output
+    /// A carriage return not followed by a newline sends the cursor back to the start of the
+    /// line, which the program then redraws (progress bars): only the last state of the line is kept
     pub fn append_output(&mut self, data: &str) {
-        self.output.push_str(data);
+        for (i, part) in data.split('\r').enumerate() {
+            if i > 0 {
+                self.pending_cr = true;
+            }
+            if part.is_empty() {
+                continue;
+            }
+            if std::mem::take(&mut self.pending_cr) {
+                if part.starts_with('\n') {
+                    self.output.push('\r');
+                } else {
+                    self.output.truncate(self.line_start);
+                }
+            }
+            if let Some(newline) = part.rfind('\n') {
+                self.line_start = self.output.len() + newline + 1;
+            }
+            self.output.push_str(part);
+        }
+    }
+
+    /// Keep a carriage return the output ended on, nothing will redraw the line anymore
+    fn flush_output(&mut self) {
+        if std::mem::take(&mut self.pending_cr) {
+            self.output.push('\r');
+        }
     }
 
     /// Set the exit code when command completes
     pub fn set_exit_code(&mut self, code: i32) {
+        self.flush_output();
         self.exit_code = Some(code);
+        self.finished_at = Some(Local::now());
+    }
+
+    /// How long the command ran, None until it finished
+    pub fn duration(&self) -> Option<chrono::Duration> {
+        self.finished_at.map(|finished| finished - self.timestamp)
     }
 
     /// Check if this command is complete (has exit code)
     pub fn is_complete(&self) -> bool {
         self.exit_code.is_some()
     }
+
+    /// Badge symbol and color for listings: ✓ success, ✗ failure, – still running
+    pub fn status_badge(&self) -> (&'static str, Color) {
+        match self.exit_code {
+            Some(0) => ("✓", Color::Green),
+            Some(_) => ("✗", Color::Red),
+            None => ("–", Color::DarkGray),
+        }
+    }
+
+    /// Output without terminal escape sequences (colors, cursor moves), for display
+    pub fn clean_output(&self) -> String {
+        strip_ansi(&self.output)
+    }
+
+    /// Format this command as a context entry for the agent, with the user's note if `with_note`
+    /// The output section is left out when there is none (or output capture is disabled)
+    pub fn to_context_entry(&self, with_note: bool) -> String {
+        let exit = match self.exit_code {
+            Some(code) => code.to_string(),
+            None => "en cours".to_string(),
+        };
+        let mut entry = format!(
+            "$ {}\n# cwd: {} | exit: {} | {}",
+            self.command,
+            self.working_dir.display(),
+            exit,
+            self.timestamp.format("%H:%M:%S"),
+        );
+        if let Some(note) = self.note.as_ref().filter(|_| with_note) {
+            entry.push_str(&format!("\n# note: {}", note));
+        }
+        let output = self.output.trim_end();
+        if !output.is_empty() {
+            entry.push('\n');
+            entry.push_str(output);
+        }
+        entry
+    }
+
+    /// Badge text including the exit code of failed commands (e.g. "✗ 127"),
+    /// 📌 when pinned and 🏷 when annotated
+    pub fn status_label(&self) -> String {
+        let (symbol, _) = self.status_badge();
+        let mut label = match self.exit_code {
+            Some(code) if code != 0 => format!("{} {}", symbol, code),
+            _ => symbol.to_string(),
+        };
+        if self.note.is_some() {
+            label = format!("🏷 {}", label);
+        }
+        if self.pinned {
+            label = format!("📌 {}", label);
+        }
+        label
+    }
+}
+
+/// Short duration label: "0.4s", "41s", "3m05", "1h02"
+pub fn format_duration(duration: chrono::Duration) -> String {
+    let millis = duration.num_milliseconds().max(0);
+    match millis {
+        0..=9_999 => format!("{:.1}s", millis as f64 / 1000.0),
+        10_000..=59_999 => format!("{}s", millis / 1000),
+        60_000..=3_599_999 => format!("{}m{:02}", millis / 60_000, millis / 1000 % 60),
+        _ => format!("{}h{:02}", millis / 3_600_000, millis / 60_000 % 60),
+    }
+}
+
+/// OSC 133;A BEL - the shell starts drawing its prompt
+const OSC_PROMPT_START: &str = "\x1b]133;A";
+
+/// OSC 133;B BEL - end of the prompt, what follows is the command line being typed
+const OSC_PROMPT_END: &str = "\x1b]133;B";
+
+/// OSC 133;C;<command> BEL - command about to execute
+const OSC_COMMAND_START: &str = "\x1b]133;C;";
+
+/// OSC 133;C BEL - command about to execute, as other shell integrations write it: the command
+/// is what was typed after 133;B
+const OSC_COMMAND_EXECUTED: &str = "\x1b]133;C";
+
+/// OSC 133;D;<exit code> BEL - command finished
+const OSC_COMMAND_END: &str = "\x1b]133;D;";
+
+/// Common start of every OSC 133 marker
+const OSC_133_PREFIX: &str = "\x1b]133;";
+
+/// OSC 7;file://<host><path> BEL - working directory of the shell
+const OSC_CWD: &str = "\x1b]7;";
+
+/// Bytes of recent output kept for prompt detection
+const PROMPT_BUFFER_BYTES: usize = 4096;
+
+/// Give up waiting for the BEL of a split marker past this size and treat it as output
+const MAX_PENDING_OSC: usize = 64 * 1024;
+
+/// Shell integration events kept for the capture debug panel
+const RECENT_EVENTS: usize = 50;
+
+/// A shell integration marker as the capture understood it, for the capture debug panel
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureEvent {
+    /// 133;A: the shell draws its prompt
+    PromptStart,
+
+    /// 133;B: the prompt is drawn, the user types a command
+    PromptEnd,
+
+    /// 133;C: a command starts
+    CommandStart(String),
+
+    /// 133;D: the running command ended with this exit code
+    CommandEnd(i32),
+
+    /// 133;D whose exit code couldn't be read, ignored
+    MalformedEnd(String),
+
+    /// OSC 7: the shell's working directory
+    WorkingDir(PathBuf),
+}
+
+/// Default number of finished commands kept in memory
+pub const DEFAULT_MAX_COMMANDS: usize = 500;
+
+/// Default longest command line kept from a 133;C marker, in bytes
+pub const DEFAULT_MAX_COMMAND_BYTES: usize = 4096;
+
+/// Appended to a command line cut at `max_command_bytes`
+const COMMAND_TRUNCATED_MARKER: &str = " […tronqué]";
+
+/// Policy choosing which captured commands are sent to the agent as context
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextStrategy {
+    /// The most recent commands
+    #[default]
+    RecentN,
+
+    /// Failed commands (non-zero exit) first, then the most recent ones
+    FailedFirst,
+
+    /// Only commands run in the current working directory
+    CurrentDirOnly,
+}
+
+/// Receives every captured command once it has finished
+/// Lets the capture be wired to any storage (log, database, socket) without knowing about it
+pub trait CommandSink: Send {
+    fn on_command(&mut self, cmd: &CapturedCommand);
+
+    /// Called as a running command produces output, for sinks that checkpoint long commands
+    fn on_progress(&mut self, _cmd: &CapturedCommand) {}
+}
+
+/// Programs listed in the most-run ranking of the stats
+const TOP_COMMANDS: usize = 5;
+
+/// Aggregate figures over the captured commands (`/stats`)
+/// Commands evicted past `max_commands` are no longer counted
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureStats {
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub unfinished: usize, // Running, or interrupted without an exit code
+    pub output_bytes: usize,
+    pub top_commands: Vec<(String, usize)>, // Program name and runs, most run first
+    pub session_duration: chrono::Duration,
+}
+
+/// Status shown wherever the user needs to know whether commands are recorded
+pub fn recording_indicator(recording: bool) -> &'static str {
+    if recording {
+        "● REC"
+    } else {
+        "○ capture en pause"
+    }
 }
 
 /// Manages the capture and storage of command executions
@@ -57,6 +295,49 @@ pub struct CommandCapture {
 
     /// Buffer for detecting prompts and commands in output
     output_buffer: String,
+
+    /// Sinks notified when a command finishes
+    sinks: Vec<Box<dyn CommandSink>>,
+
+    /// Start of a marker whose BEL terminator hasn't arrived yet (split across chunks)
+    pending_osc: String,
+
+    /// Finished commands kept before the oldest unpinned ones are evicted
+    max_commands: usize,
+
+    /// Commands evicted so far: each one shifted the 1-based numbers of the later ones
+    evicted: usize,
+
+    /// Leading bytes of a UTF-8 character cut off at the end of the previous read
+    pending_utf8: Vec<u8>,
+
+    /// Record command output, or only commands, exit codes and times (privacy)
+    capture_output: bool,
+
+    /// Send the user's notes along with the commands as agent context
+    notes_in_context: bool,
+
+    /// Paused: output isn't parsed or kept at all, no new command is recorded
+    recording: bool,
+
+    /// Start of the session, for the stats
+    started: DateTime<Local>,
+
+    /// Longest command line kept from a marker, a program printing a huge fake one can't
+    /// make it allocate and store megabytes
+    max_command_bytes: usize,
+
+    /// Working directory of the shell from its last OSC 7 report, None before the first one
+    current_dir: Option<PathBuf>,
+
+    /// Last markers parsed, oldest first, shown by the capture debug panel
+    events: VecDeque<(DateTime<Local>, CaptureEvent)>,
+
+    /// Between 133;A and 133;C: the prompt is on screen, what's printed belongs to no command
+    at_prompt: bool,
+
+    /// Printed between 133;B and 133;C: the echo of the command line being typed
+    typed_input: Option<String>,
 }
 
 impl CommandCapture {
@@ -65,74 +346,255 @@ impl CommandCapture {
             commands: Vec::new(),
             current_command: None,
             output_buffer: String::new(),
+            sinks: Vec::new(),
+            pending_osc: String::new(),
+            max_commands: DEFAULT_MAX_COMMANDS,
+            evicted: 0,
+            pending_utf8: Vec::new(),
+            capture_output: true,
+            notes_in_context: true,
+            recording: true,
+            started: Local::now(),
+            max_command_bytes: DEFAULT_MAX_COMMAND_BYTES,
+            current_dir: None,
+            events: VecDeque::with_capacity(RECENT_EVENTS),
+            at_prompt: false,
+            typed_input: None,
+        }
+    }
+
+    /// Cut command lines longer than `max_command_bytes`
+    pub fn with_max_command_bytes(mut self, max_command_bytes: usize) -> Self {
+        self.max_command_bytes = max_command_bytes;
+        self
+    }
+
+    /// Start the session with the capture paused
+    pub fn with_recording(mut self, recording: bool) -> Self {
+        self.recording = recording;
+        self
+    }
+
+    /// Last shell integration markers parsed, oldest first
+    pub fn recent_events(&self) -> impl Iterator<Item = &(DateTime<Local>, CaptureEvent)> {
+        self.events.iter()
+    }
+
+    fn record_event(&mut self, event: CaptureEvent) {
+        if self.events.len() == RECENT_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back((Local::now(), event));
+    }
+
+    /// Working directory of the shell as it last reported it (OSC 7)
+    pub fn current_dir(&self) -> Option<&Path> {
+        self.current_dir.as_deref()
+    }
+
+    /// Whether shell output is currently being captured
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Pause or resume the capture
+    /// Pausing keeps what was already captured but drops every partial state (marker, prompt
+    /// buffer), nothing typed or printed while paused ends up recorded after resuming
+    pub fn set_recording(&mut self, recording: bool) {
+        if !recording {
+            self.finalize_pending();
+            self.output_buffer.clear();
+            self.pending_osc.clear();
+            self.pending_utf8.clear();
+            self.at_prompt = false;
+            self.typed_input = None;
+        }
+        self.recording = recording;
+    }
+
+    /// Leave the user's notes out of the agent context
+    pub fn with_notes_in_context(mut self, notes_in_context: bool) -> Self {
+        self.notes_in_context = notes_in_context;
+        self
+    }
+
+    /// Stop recording command output, keeping commands, exit codes and times
+    pub fn with_output_capture(mut self, capture_output: bool) -> Self {
+        self.capture_output = capture_output;
+        self
+    }
+
+    /// Keep at most `max_commands` finished commands (more only if they are all pinned)
+    pub fn with_max_commands(mut self, max_commands: usize) -> Self {
+        self.max_commands = max_commands;
+        self
+    }
+
+    /// Register a sink notified whenever a command finishes
+    pub fn add_sink(&mut self, sink: Box<dyn CommandSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Hand the current command to every sink
+    fn notify_sinks(&mut self) {
+        if let Some(ref cmd) = self.current_command {
+            for sink in &mut self.sinks {
+                sink.on_command(cmd);
+            }
         }
     }
 
+    /// Process raw bytes read from the PTY
+    /// A character split between two reads is held back until the next one completes it,
+    /// invalid UTF-8 is replaced (U+FFFD) rather than dropping the whole chunk
+    pub fn process_bytes(&mut self, data: &[u8], working_dir: &std::path::Path) -> bool {
+        if !self.recording {
+            return false;
+        }
+        let mut bytes = std::mem::take(&mut self.pending_utf8);
+        bytes.extend_from_slice(data);
+
+        let split = incomplete_utf8_start(&bytes);
+        self.pending_utf8 = bytes.split_off(split);
+
+        let text = String::from_utf8_lossy(&bytes);
+        self.process_output(&text, working_dir)
+    }
+
     /// Process a chunk of output from the PTY
     /// Detects OSC 133 sequences for command tracking
     pub fn process_output(&mut self, data: &str, working_dir: &std::path::Path) -> bool {
+        if !self.recording {
+            return false;
+        }
         self.output_buffer.push_str(data);
 
-        // Check for OSC 133 sequences first (most reliable)
-        self.parse_osc_sequences(data, working_dir);
-
         // Keep buffer manageable (last 4KB should be enough for prompt detection)
-        if self.output_buffer.len() > 4096 {
-            self.output_buffer.drain(..self.output_buffer.len() - 4096);
+        // Trimmed once it doubles rather than on every chunk, so the drain cost is amortized
+        if self.output_buffer.len() > 2 * PROMPT_BUFFER_BYTES {
+            let mut cut = self.output_buffer.len() - PROMPT_BUFFER_BYTES;
+            while !self.output_buffer.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.output_buffer.drain(..cut);
         }
 
-        // If we have a current command, append output to it (filter out OSC sequences)
-        let clean_output = self.strip_osc_sequences(data);
-        if let Some(ref mut cmd) = self.current_command {
-            cmd.append_output(&clean_output);
-        }
+        // OSC 133 sequences delimit commands and attribute output to them (most reliable)
+        self.parse_osc_sequences(data, working_dir);
 
         // Fallback: Check if this looks like a new prompt
         self.detect_prompt()
     }
 
-    /// Parse OSC 133 sequences for shell integration
+    /// Parse OSC 133 sequences for shell integration, and OSC 7 for the working directory
+    /// Walks every A/B/C/D marker of the chunk in order, so fast consecutive commands (`a; b; c`)
+    /// are each captured with their own output and exit code, and the prompt drawn between
+    /// 133;A and 133;C never ends up in one
     fn parse_osc_sequences(&mut self, data: &str, working_dir: &std::path::Path) {
-        // OSC 133;C;command - Command about to execute
-        if let Some(start) = data.find("\x1b]133;C;") {
-            if let Some(end) = data[start..].find('\x07') {
-                let command = &data[start + 8..start + end];
-
-                // Start new command capture
-                if let Some(cmd) = self.current_command.take() {
-                    self.commands.push(cmd);
+        // Resume a marker left incomplete by the previous chunk
+        let mut text = std::mem::take(&mut self.pending_osc);
+        text.push_str(data);
+        let mut rest = text.as_str();
+
+        loop {
+            let Some(start) = find_marker(rest) else {
+                self.append_segment(rest);
+                break;
+            };
+
+            // Output preceding the marker belongs to the command running before it
+            self.append_segment(&rest[..start]);
+
+            let Some(len) = rest[start..].find('\x07') else {
+                // Terminator not received yet: wait for the next chunk
+                if rest.len() - start > MAX_PENDING_OSC {
+                    self.append_segment(&rest[start..]);
+                } else {
+                    self.pending_osc = rest[start..].to_string();
                 }
-                self.current_command = Some(CapturedCommand::new(command.to_string(), working_dir.to_path_buf()));
-            }
-        }
+                break;
+            };
 
-        // OSC 133;D;exitcode - Command finished
-        if let Some(start) = data.find("\x1b]133;D;") {
-            if let Some(end) = data[start..].find('\x07') {
-                let exit_code_str = &data[start + 8..start + end];
-                if let Ok(exit_code) = exit_code_str.parse::<i32>() {
-                    if let Some(ref mut cmd) = self.current_command {
-                        cmd.set_exit_code(exit_code);
+            // Markers and BEL are ASCII, so these slices always fall on character boundaries
+            let marker = &rest[start..start + len];
+            if let Some(command) = marker.strip_prefix(OSC_COMMAND_START) {
+                self.typed_input = None;
+                self.start_marked_command(command, working_dir);
+            } else if marker == OSC_COMMAND_EXECUTED {
+                let typed = self.typed_input.take().unwrap_or_default();
+                self.start_marked_command(&typed_command(&typed), working_dir);
+            } else if is_marker(marker, OSC_PROMPT_START) {
+                self.record_event(CaptureEvent::PromptStart);
+                self.at_prompt = true;
+                self.typed_input = None;
+            } else if is_marker(marker, OSC_PROMPT_END) {
+                self.record_event(CaptureEvent::PromptEnd);
+                self.typed_input = Some(String::new());
+            } else if let Some(uri) = marker.strip_prefix(OSC_CWD) {
+                if let Some(dir) = dir_from_file_uri(uri) {
+                    self.record_event(CaptureEvent::WorkingDir(dir.clone()));
+                    self.current_dir = Some(dir);
+                }
+            } else if let Some(code) = marker.strip_prefix(OSC_COMMAND_END) {
+                match code.parse::<i32>() {
+                    Ok(exit_code) => {
+                        self.record_event(CaptureEvent::CommandEnd(exit_code));
+                        self.finalize_command(exit_code);
+                    }
+                    Err(_) => {
+                        let shown = if code.len() > 32 { truncate_end(code, 32) } else { code.to_string() };
+                        self.record_event(CaptureEvent::MalformedEnd(shown));
                     }
                 }
             }
+
+            rest = &rest[start + len + 1..];
         }
     }
 
-    /// Strip OSC sequences from output to avoid polluting captured data
-    fn strip_osc_sequences(&self, data: &str) -> String {
-        let mut result = data.to_string();
+    /// Start the command of a 133;C marker, output is attributed to it from here on
+    fn start_marked_command(&mut self, command: &str, working_dir: &std::path::Path) {
+        let command = if command.len() > self.max_command_bytes {
+            warn!(
+                "Command marker of {} bytes cut to {} (max_command_bytes)",
+                command.len(),
+                self.max_command_bytes
+            );
+            truncate_end(command, self.max_command_bytes)
+        } else {
+            command.to_string()
+        };
+        self.record_event(CaptureEvent::CommandStart(command.clone()));
+        self.at_prompt = false;
+        // The shell's own report wins over the directory Petoncle was started in
+        let dir = self.current_dir.clone().unwrap_or_else(|| working_dir.to_path_buf());
+        self.start_command(command, dir);
+    }
 
-        // Remove OSC 133 sequences
-        while let Some(start) = result.find("\x1b]133;") {
-            if let Some(end) = result[start..].find('\x07') {
-                result.drain(start..start + end + 1);
-            } else {
-                break;
+    /// Append a chunk of output to the running command (none once it has finished or once the
+    /// shell draws its prompt); after 133;B it's the echo of the command line being typed
+    fn append_segment(&mut self, segment: &str) {
+        if segment.is_empty() {
+            return;
+        }
+        if let Some(ref mut typed) = self.typed_input {
+            if typed.len() < MAX_PENDING_OSC {
+                typed.push_str(segment);
+            }
+            return;
+        }
+        if self.at_prompt || !self.capture_output {
+            return;
+        }
+        let clean_output = strip_osc_sequences(segment);
+        if let Some(ref mut cmd) = self.current_command
+            && !cmd.is_complete()
+        {
+            cmd.append_output(&clean_output);
+            for sink in &mut self.sinks {
+                sink.on_progress(cmd);
             }
         }
-
-        result
     }
 
     /// Detect if the current buffer ends with a shell prompt
@@ -140,11 +602,14 @@ impl CommandCapture {
     fn detect_prompt(&self) -> bool {
         let trimmed = self.output_buffer.trim_end();
 
+        // Prompt endings include the trailing space, so look at the untrimmed last line
+        let prompt_line = self.output_buffer.rsplit('\n').next().unwrap_or("");
+
         if let Some(last_line) = trimmed.lines().last() {
             // Check for various prompt indicators
 
             // 1. Standard prompts ending with % or $
-            if last_line.ends_with("% ") || last_line.ends_with("$ ") {
+            if prompt_line.ends_with("% ") || prompt_line.ends_with("$ ") {
                 return true;
             }
 
@@ -157,7 +622,7 @@ impl CommandCapture {
             // 3. Prompts ending with special characters (λ, ❯, >, etc.)
             let prompt_endings = ["λ ", "❯ ", "> ", "→ ", "» ", "✗ "];
             for ending in &prompt_endings {
-                if last_line.ends_with(ending) {
+                if prompt_line.ends_with(ending) {
                     return true;
                 }
             }
@@ -177,7 +642,7 @@ impl CommandCapture {
     pub fn start_command(&mut self, command: String, working_dir: PathBuf) {
         // If there was a previous command, finalize it
         if let Some(cmd) = self.current_command.take() {
-            self.commands.push(cmd);
+            self.push_finished(cmd);
         }
 
         // Start new command capture
@@ -185,9 +650,112 @@ impl CommandCapture {
     }
 
     /// Finalize the current command with an exit code
+    /// A 133;D without a command running (the hook prints one at every prompt, an empty Enter
+    /// included) leaves the last command and the sinks alone
     pub fn finalize_command(&mut self, exit_code: i32) {
-        if let Some(ref mut cmd) = self.current_command {
-            cmd.set_exit_code(exit_code);
+        let Some(ref mut cmd) = self.current_command else {
+            return;
+        };
+        if cmd.is_complete() {
+            return;
+        }
+        cmd.set_exit_code(exit_code);
+        self.notify_sinks();
+    }
+
+    /// Move the current command into the list, even if it never got an exit code
+    /// Called when the shell exits so an interrupted command isn't lost (its status stays unknown)
+    pub fn finalize_pending(&mut self) {
+        if self.current_command.as_ref().is_some_and(|cmd| !cmd.is_complete()) {
+            self.notify_sinks();
+        }
+        if let Some(cmd) = self.current_command.take() {
+            self.push_finished(cmd);
+        }
+    }
+
+    /// Store a finished command, evicting the oldest unpinned ones past `max_commands`
+    fn push_finished(&mut self, mut cmd: CapturedCommand) {
+        cmd.flush_output();
+        self.commands.push(cmd);
+
+        let mut excess = self.commands.len().saturating_sub(self.max_commands);
+        if excess > 0 {
+            let before = self.commands.len();
+            self.commands.retain(|cmd| {
+                if excess > 0 && !cmd.pinned {
+                    excess -= 1;
+                    false
+                } else {
+                    true
+                }
+            });
+            self.evicted += before - self.commands.len();
+        }
+    }
+
+    /// Commands evicted past `max_commands` since the session started
+    pub fn evicted(&self) -> usize {
+        self.evicted
+    }
+
+    /// Pin or unpin the command at `index` (same numbering as `get`)
+    /// Returns the new pinned state, None when there is no such command
+    pub fn toggle_pin(&mut self, index: usize) -> Option<bool> {
+        let cmd = self.get_mut(index)?;
+        cmd.pinned = !cmd.pinned;
+        Some(cmd.pinned)
+    }
+
+    /// Set or clear (None) the note of a command (0-based, same numbering as `get`)
+    /// Returns false if there is no such command
+    pub fn set_note(&mut self, index: usize, note: Option<String>) -> bool {
+        match self.get_mut(index) {
+            Some(cmd) => {
+                cmd.note = note;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Finished commands first, then the running one
+    fn get_mut(&mut self, index: usize) -> Option<&mut CapturedCommand> {
+        let len = self.commands.len();
+        if index < len {
+            self.commands.get_mut(index)
+        } else if index == len {
+            self.current_command.as_mut()
+        } else {
+            None
+        }
+    }
+
+    /// Counts over the captured commands, the one running included
+    pub fn stats(&self) -> CaptureStats {
+        let commands: Vec<&CapturedCommand> = self.commands.iter().chain(self.current_command.as_ref()).collect();
+
+        let mut runs: Vec<(String, usize)> = Vec::new();
+        for cmd in &commands {
+            let Some(program) = cmd.command.split_whitespace().next() else {
+                continue;
+            };
+            match runs.iter_mut().find(|(name, _)| name == program) {
+                Some((_, count)) => *count += 1,
+                None => runs.push((program.to_string(), 1)),
+            }
+        }
+        runs.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        runs.truncate(TOP_COMMANDS);
+
+        CaptureStats {
+            total: commands.len(),
+            succeeded: commands.iter().filter(|cmd| cmd.exit_code == Some(0)).count(),
+            failed: commands.iter().filter(|cmd| matches!(cmd.exit_code, Some(code) if code != 0)).count(),
+            unfinished: commands.iter().filter(|cmd| cmd.exit_code.is_none()).count(),
+            output_bytes: commands.iter().map(|cmd| cmd.output.len()).sum(),
+            top_commands: runs,
+            session_duration: Local::now() - self.started,
         }
     }
 
@@ -201,29 +769,318 @@ impl CommandCapture {
         self.current_command.as_ref()
     }
 
+    /// Command at `index` in session order (0 = first), including the one still running
+    pub fn get(&self, index: usize) -> Option<&CapturedCommand> {
+        match index.cmp(&self.commands.len()) {
+            std::cmp::Ordering::Less => self.commands.get(index),
+            std::cmp::Ordering::Equal => self.current_command.as_ref(),
+            std::cmp::Ordering::Greater => None,
+        }
+    }
+
+    /// Commands matching `query` fuzzily (`gst` finds `git status`), best score first
+    /// Equal scores, and every command for an empty query, are listed most recent first
+    pub fn fuzzy_search(&self, query: &str) -> Vec<(i64, &CapturedCommand)> {
+        let mut results: Vec<(i64, &CapturedCommand)> = self
+            .commands
+            .iter()
+            .chain(self.current_command.as_ref())
+            .rev()
+            .filter_map(|cmd| {
+                if query.trim().is_empty() {
+                    return Some((0, cmd));
+                }
+                fuzzy_match(query, &cmd.command).map(|(score, _)| (score, cmd))
+            })
+            .collect();
+        // Stable: ties keep the most recent first
+        results.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        results
+    }
+
+    /// Position of a command borrowed from this capture (same numbering as `get`)
+    pub fn index_of(&self, cmd: &CapturedCommand) -> Option<usize> {
+        (0..self.len()).find(|&i| self.get(i).is_some_and(|c| std::ptr::eq(c, cmd)))
+    }
+
+    /// Number of captured commands, including the one still running
+    pub fn len(&self) -> usize {
+        self.commands.len() + usize::from(self.current_command.is_some())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Captured commands formatted as agent context, chosen by `strategy`
+    /// At most `max_commands` entries within `budget` bytes, returned oldest first;
+    /// the highest-priority entry is truncated if it alone exceeds the budget
+    pub fn recent_context(
+        &self,
+        strategy: ContextStrategy,
+        cwd: &Path,
+        max_commands: usize,
+        budget: usize,
+    ) -> Vec<String> {
+        let all: Vec<&CapturedCommand> = self.commands.iter().chain(self.current_command.iter()).collect();
+
+        // Candidates in priority order, newest first
+        let mut candidates: Vec<usize> = (0..all.len()).rev().collect();
+        match strategy {
+            ContextStrategy::RecentN => {}
+            ContextStrategy::FailedFirst => {
+                // Stable sort keeps recency order within failed and non-failed groups
+                candidates.sort_by_key(|&i| !matches!(all[i].exit_code, Some(code) if code != 0));
+            }
+            ContextStrategy::CurrentDirOnly => {
+                candidates.retain(|&i| all[i].pinned || all[i].working_dir == cwd);
+            }
+        }
+
+        // Pinned commands go first whatever the strategy, and don't count against max_commands
+        let (pinned, others): (Vec<usize>, Vec<usize>) = candidates.into_iter().partition(|&i| all[i].pinned);
+
+        let mut selected = Vec::new();
+        let mut used = 0;
+        for i in pinned.into_iter().chain(others.into_iter().take(max_commands)) {
+            let entry = all[i].to_context_entry(self.notes_in_context);
+            if used + entry.len() > budget {
+                if selected.is_empty() && budget > 0 {
+                    selected.push((i, truncate_start(&entry, budget)));
+                }
+                break;
+            }
+            used += entry.len();
+            selected.push((i, entry));
+        }
+
+        selected.sort_by_key(|(i, _)| *i);
+        selected.into_iter().map(|(_, entry)| entry).collect()
+    }
+
     /// Clear all captured commands (for testing or reset)
+    #[cfg(test)]
     pub fn clear(&mut self) {
         self.commands.clear();
         self.current_command = None;
         self.output_buffer.clear();
+        self.pending_osc.clear();
+        self.pending_utf8.clear();
+        self.at_prompt = false;
+        self.typed_input = None;
+    }
+}
+
+/// Strip OSC 133 sequences from output to avoid polluting captured data
+/// Single forward scan copying what lies between sequences; an unterminated one is kept as is
+fn strip_osc_sequences(data: &str) -> String {
+    let mut result = String::with_capacity(data.len());
+    let mut rest = data;
+
+    while let Some(start) = rest.find(OSC_133_PREFIX) {
+        let Some(len) = rest[start..].find('\x07') else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        rest = &rest[start + len + 1..];
+    }
+    result.push_str(rest);
+
+    result
+}
+
+/// Position of the next A/B/C/D marker (found in a single scan for their common prefix) or
+/// OSC 7 report, whichever comes first
+/// A marker cut off at the end of the text counts, the next chunk completes it
+fn find_marker(text: &str) -> Option<usize> {
+    let cwd = text.find(OSC_CWD);
+    let end = cwd.unwrap_or(text.len());
+    let mut offset = 0;
+    while let Some(pos) = text[offset..end].find(OSC_133_PREFIX) {
+        let start = offset + pos;
+        let kind = &text.as_bytes()[start + OSC_133_PREFIX.len()..];
+        if matches!(kind, [] | [b'A'..=b'D'] | [b'A'..=b'D', b';' | b'\x07', ..]) {
+            return Some(start);
+        }
+        offset = start + OSC_133_PREFIX.len();
+    }
+    if cwd.is_some() {
+        return cwd;
+    }
+
+    // "\x1b]13" at the very end may be the start of a marker
+    text.rfind('\x1b').filter(|&start| {
+        let tail = &text[start..];
+        OSC_133_PREFIX.starts_with(tail) || OSC_CWD.starts_with(tail)
+    })
+}
+
+/// Whether `marker` is `kind`, bare or followed by parameters (`\x1b]133;A;aid=1`)
+fn is_marker(marker: &str, kind: &str) -> bool {
+    marker.strip_prefix(kind).is_some_and(|params| params.is_empty() || params.starts_with(';'))
+}
+
+/// Command line typed between 133;B and 133;C, as it ended up on screen: escape sequences left
+/// out, backspaces applied, and only the last line kept (line editors redraw it as it's edited)
+fn typed_command(raw: &str) -> String {
+    let mut last = String::new();
+    let mut line = String::new();
+    for c in strip_ansi(raw).chars() {
+        match c {
+            '\r' | '\n' => {
+                if !line.trim().is_empty() {
+                    last = std::mem::take(&mut line);
+                }
+                line.clear();
+            }
+            '\x08' => {
+                line.pop();
+            }
+            c if c.is_control() => {}
+            c => line.push(c),
+        }
+    }
+    if !line.trim().is_empty() {
+        last = line;
+    }
+    last.trim().to_string()
+}
+
+/// Directory of an OSC 7 `file://host/path` report, percent-escapes decoded
+fn dir_from_file_uri(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let path = &rest[rest.find('/')?..];
+
+    let raw = path.as_bytes();
+    let mut bytes = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        let hex = raw
+            .get(i + 1..i + 3)
+            .filter(|hex| raw[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit));
+        match hex {
+            Some(hex) => {
+                bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+                i += 3;
+            }
+            None => {
+                bytes.push(raw[i]);
+                i += 1;
+            }
+        }
+    }
+    Some(PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+/// Where a UTF-8 character cut off at the end of `bytes` starts (`bytes.len()` when none is)
+pub(crate) fn incomplete_utf8_start(bytes: &[u8]) -> usize {
+    for back in 1..=bytes.len().min(3) {
+        let index = bytes.len() - back;
+        let byte = bytes[index];
+        if byte & 0xC0 == 0x80 {
+            // Continuation byte, the lead byte is further back
+            continue;
+        }
+        let needed = match byte {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 1,
+        };
+        return if needed > back { index } else { bytes.len() };
+    }
+    bytes.len()
+}
+
+/// Remove ANSI escape sequences (CSI, OSC and two-byte escapes) from terminal output
+pub fn strip_ansi(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters until a final byte in @..~
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC: until BEL or ST (ESC \)
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // Other escapes are two characters long
+            _ => {}
+        }
+    }
+
+    result
+}
+
+/// Keep the first `max_bytes` of a string (on a char boundary), marking the cut
+fn truncate_end(text: &str, max_bytes: usize) -> String {
+    let mut end = max_bytes.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &text[..end], COMMAND_TRUNCATED_MARKER)
+}
+
+/// Keep the last `max_bytes` of a string (on a char boundary), marking the cut
+pub fn truncate_start(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
     }
+    let mut start = text.len() - max_bytes;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("[…tronqué]\n{}", &text[start..])
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Command lines and exit codes, in the order the sink was notified
+    type Recorded = Arc<Mutex<Vec<(String, Option<i32>)>>>;
+
+    /// Sink recording every command it's notified about
+    struct RecordingSink(Recorded);
+
+    impl CommandSink for RecordingSink {
+        fn on_command(&mut self, cmd: &CapturedCommand) {
+            self.0.lock().unwrap().push((cmd.command.clone(), cmd.exit_code));
+        }
+    }
 
     #[test]
     fn test_prompt_detection() {
         let mut capture = CommandCapture::new();
+        let cwd = PathBuf::from("/home/user");
 
         // Test zsh prompt
-        assert!(!capture.process_output("some output\n"));
-        assert!(capture.process_output("user@host:~/projects % "));
+        assert!(!capture.process_output("some output\n", &cwd));
+        assert!(capture.process_output("user@host:~/projects % ", &cwd));
 
         // Test simple prompt
         capture.clear();
-        assert!(capture.process_output("~ % "));
+        assert!(capture.process_output("~ % ", &cwd));
     }
 
     #[test]
@@ -232,10 +1089,500 @@ mod tests {
         let cwd = PathBuf::from("/home/user");
 
         capture.start_command("ls -la".to_string(), cwd.clone());
-        capture.process_output("total 32\ndrwxr-xr-x  5 user\n");
+        capture.process_output("total 32\ndrwxr-xr-x  5 user\n", &cwd);
         capture.finalize_command(0);
 
         assert_eq!(capture.current().unwrap().exit_code, Some(0));
         assert!(capture.current().unwrap().output.contains("total 32"));
     }
+
+    #[test]
+    fn test_finalize_pending_keeps_unfinished_command() {
+        let mut capture = CommandCapture::new();
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        capture.add_sink(Box::new(RecordingSink(recorded.clone())));
+
+        capture.start_command("sleep 100".to_string(), PathBuf::from("/tmp"));
+        capture.finalize_pending();
+
+        assert!(capture.current().is_none());
+        assert_eq!(capture.get_commands().len(), 1);
+        assert_eq!(capture.get_commands()[0].command, "sleep 100");
+        assert_eq!(capture.get_commands()[0].exit_code, None);
+        assert_eq!(*recorded.lock().unwrap(), vec![("sleep 100".to_string(), None)]);
+
+        // Nothing left to flush
+        capture.finalize_pending();
+        assert_eq!(capture.get_commands().len(), 1);
+    }
+
+    #[test]
+    fn test_pinned_commands_survive_eviction() {
+        let mut capture = CommandCapture::new().with_max_commands(100);
+        let cwd = PathBuf::from("/home/user");
+
+        capture.start_command("make".to_string(), cwd.clone());
+        capture.finalize_command(2);
+        assert_eq!(capture.toggle_pin(0), Some(true));
+
+        for i in 0..1500 {
+            capture.start_command(format!("echo {}", i), cwd.clone());
+            capture.finalize_command(0);
+        }
+        capture.finalize_pending();
+
+        assert_eq!(capture.get_commands().len(), 100);
+        assert_eq!(capture.get_commands()[0].command, "make");
+        assert_eq!(capture.get_commands()[1].command, "echo 1401");
+        assert_eq!(capture.evicted(), 1401);
+        assert!(capture.get_commands()[0].status_label().starts_with("📌"));
+
+        // Pinned commands are always part of the context
+        let context = capture.recent_context(ContextStrategy::RecentN, &cwd, 2, 10_000);
+        assert_eq!(context.len(), 3);
+        assert!(context[0].starts_with("$ make"));
+
+        assert_eq!(capture.toggle_pin(0), Some(false));
+        assert_eq!(capture.toggle_pin(500), None);
+    }
+
+    #[test]
+    fn test_prompt_markers_delimit_output() {
+        let cwd = PathBuf::from("/home/user");
+        // Bare markers as other shell integrations write them: the command is the typed text
+        let stream = "\x1b]133;A\x07\x1b[32muser@host\x1b[0m % \x1b]133;B\x07gti\x08\x08it status\r\n\
+                      \x1b]133;C\x07On branch main\r\n\x1b]133;D;0\x07\
+                      \x1b]133;A\x07user@host % \x1b]133;B\x07sleep 9\r\n\x1b]133;C\x07zz\n\
+                      \x1b]133;A\x07user@host % "
+            .as_bytes();
+
+        for split in 0..=stream.len() {
+            let mut capture = CommandCapture::new();
+            capture.process_bytes(&stream[..split], &cwd);
+            capture.process_bytes(&stream[split..], &cwd);
+
+            let commands = capture.get_commands();
+            assert_eq!(commands[0].command, "git status", "split at {}", split);
+            assert_eq!(commands[0].output, "On branch main\r\n", "split at {}", split);
+            assert_eq!(commands[0].exit_code, Some(0));
+
+            // No 133;D: the prompt drawn after it still stays out of the output
+            let cmd = capture.current().unwrap();
+            assert_eq!(cmd.command, "sleep 9", "split at {}", split);
+            assert_eq!(cmd.output, "zz\n", "split at {}", split);
+            assert_eq!(cmd.exit_code, None);
+        }
+    }
+
+    #[test]
+    fn test_multibyte_command_split_at_every_byte() {
+        let cwd = PathBuf::from("/home/user");
+        let stream = "\x1b]133;C;echo café 🎉\x07café 🎉\n\x1b]133;D;0\x07".as_bytes();
+
+        for split in 0..=stream.len() {
+            let mut capture = CommandCapture::new();
+            capture.process_bytes(&stream[..split], &cwd);
+            capture.process_bytes(&stream[split..], &cwd);
+
+            let cmd = capture.current().unwrap();
+            assert_eq!(cmd.command, "echo café 🎉", "split at {}", split);
+            assert_eq!(cmd.output, "café 🎉\n", "split at {}", split);
+            assert_eq!(cmd.exit_code, Some(0));
+        }
+    }
+
+    #[test]
+    fn test_invalid_utf8_is_replaced_not_dropped() {
+        let mut capture = CommandCapture::new();
+        let cwd = PathBuf::from("/home/user");
+        capture.process_bytes(b"\x1b]133;C;cat blob\x07a\xffb\x1b]133;D;0\x07", &cwd);
+
+        let cmd = capture.current().unwrap();
+        assert_eq!(cmd.output, "a\u{FFFD}b");
+        assert_eq!(cmd.exit_code, Some(0));
+    }
+
+    #[test]
+    fn test_output_buffer_trim_respects_char_boundaries() {
+        let mut capture = CommandCapture::new();
+        let cwd = PathBuf::from("/home/user");
+        // The cut point lands in the middle of an "é"
+        capture.process_output(&format!("{}x", "é".repeat(5000)), &cwd);
+        assert!(capture.output_buffer.len() <= PROMPT_BUFFER_BYTES);
+        assert!(capture.output_buffer.ends_with("éx"));
+    }
+
+    #[test]
+    fn test_strip_osc_sequences() {
+        assert_eq!(strip_osc_sequences("plain output"), "plain output");
+        assert_eq!(
+            strip_osc_sequences("a\x1b]133;A\x07b\x1b]133;B\x07c\x1b]133;D;0\x07"),
+            "abc"
+        );
+        // Other OSC sequences are left alone, an unterminated 133 one too
+        assert_eq!(strip_osc_sequences("\x1b]0;title\x07x"), "\x1b]0;title\x07x");
+        assert_eq!(strip_osc_sequences("x\x1b]133;A\x07y\x1b]133;C;ls"), "xy\x1b]133;C;ls");
+    }
+
+    #[test]
+    fn test_find_marker_skips_other_osc_133() {
+        // Bare A and B are markers too, other OSC 133 sequences are skipped
+        assert_eq!(find_marker("ab\x1b]133;A\x07cd\x1b]133;D;0\x07"), Some(2));
+        assert_eq!(find_marker("\x1b]133;B\x07"), Some(0));
+        assert_eq!(find_marker("ab\x1b]133;P;k=i\x07cd\x1b]133;D;0\x07"), Some(16));
+        assert_eq!(find_marker("ab\x1b]133;P;k=i\x07cd"), None);
+        // Cut off at the end: the next chunk completes it
+        assert_eq!(find_marker("out\x1b]133;"), Some(3));
+        assert_eq!(find_marker("out\x1b]13"), Some(3));
+    }
+
+    #[test]
+    fn test_oversized_command_marker_is_bounded() {
+        let mut capture = CommandCapture::new().with_max_command_bytes(16);
+        let cwd = PathBuf::from("/home/user");
+        // Spread over reads like a real PTY, the payload is far larger than the cap
+        let payload = "é".repeat(20_000);
+        let stream = format!("\x1b]133;C;{}\x07out\n\x1b]133;D;0\x07", payload);
+        for chunk in stream.as_bytes().chunks(8192) {
+            capture.process_bytes(chunk, &cwd);
+        }
+
+        let cmd = capture.current().unwrap();
+        assert_eq!(cmd.command, format!("{}{}", "é".repeat(8), COMMAND_TRUNCATED_MARKER));
+        assert_eq!(cmd.exit_code, Some(0));
+        assert_eq!(cmd.output, "out\n");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(chrono::Duration::milliseconds(420)), "0.4s");
+        assert_eq!(format_duration(chrono::Duration::seconds(41)), "41s");
+        assert_eq!(format_duration(chrono::Duration::seconds(185)), "3m05");
+        assert_eq!(format_duration(chrono::Duration::minutes(62)), "1h02");
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut capture = CommandCapture::new();
+        let cwd = PathBuf::from("/home/user");
+        capture.process_output(
+            "\x1b]133;C;git status\x07clean\n\x1b]133;D;0\x07\
+             \x1b]133;C;make test\x07FAILED\n\x1b]133;D;2\x07\
+             \x1b]133;C;git push\x07\x1b]133;D;1\x07\
+             \x1b]133;C;git log\x07",
+            &cwd,
+        );
+
+        let stats = capture.stats();
+        assert_eq!(stats.total, 4);
+        assert_eq!((stats.succeeded, stats.failed, stats.unfinished), (1, 2, 1));
+        assert_eq!(stats.output_bytes, "clean\n".len() + "FAILED\n".len());
+        assert_eq!(stats.top_commands, vec![("git".to_string(), 3), ("make".to_string(), 1)]);
+        assert!(stats.session_duration >= chrono::Duration::zero());
+    }
+
+    #[test]
+    fn test_paused_capture_records_nothing() {
+        let mut capture = CommandCapture::new();
+        let cwd = PathBuf::from("/home/user");
+        capture.process_output("\x1b]133;C;ls\x07a\n\x1b]133;D;0\x07", &cwd);
+
+        // Paused in the middle of a marker: the tail must not complete it after resuming
+        capture.process_output("\x1b]133;C;export TOKEN=", &cwd);
+        capture.set_recording(false);
+        capture.process_output("secret\x07\x1b]133;C;cat .env\x07API_KEY=secret\n\x1b]133;D;0\x07", &cwd);
+        assert_eq!(capture.len(), 1);
+        assert!(capture.current().is_none());
+
+        capture.set_recording(true);
+        capture.process_output("\x1b]133;C;pwd\x07/home/user\n\x1b]133;D;0\x07", &cwd);
+        let commands: Vec<&str> = capture.get_commands().iter().map(|cmd| cmd.command.as_str()).collect();
+        assert_eq!(commands, vec!["ls"]);
+        assert_eq!(capture.current().unwrap().command, "pwd");
+    }
+
+    #[test]
+    fn test_output_capture_disabled() {
+        let mut capture = CommandCapture::new().with_output_capture(false);
+        let cwd = PathBuf::from("/home/user");
+        capture.process_output("\x1b]133;C;cat .env\x07API_KEY=secret\n\x1b]133;D;0\x07", &cwd);
+
+        let cmd = capture.current().unwrap();
+        assert_eq!(cmd.command, "cat .env");
+        assert_eq!(cmd.exit_code, Some(0));
+        assert_eq!(cmd.working_dir, cwd);
+        assert!(cmd.output.is_empty());
+
+        let context = capture.recent_context(ContextStrategy::RecentN, &cwd, 10, 10_000);
+        assert!(context[0].starts_with("$ cat .env\n# cwd: /home/user | exit: 0 |"));
+        assert!(!context[0].contains("secret"));
+        assert_eq!(context[0].lines().count(), 2);
+    }
+
+    #[test]
+    fn test_get_by_index() {
+        let mut capture = CommandCapture::new();
+        let cwd = PathBuf::from("/home/user");
+        assert!(capture.is_empty());
+
+        capture.start_command("first".to_string(), cwd.clone());
+        capture.finalize_command(0);
+        capture.start_command("running".to_string(), cwd);
+
+        assert_eq!(capture.len(), 2);
+        assert_eq!(capture.get(0).unwrap().command, "first");
+        assert_eq!(capture.get(1).unwrap().command, "running");
+        assert!(capture.get(2).is_none());
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(strip_ansi("\x1b[1;31merror\x1b[0m: nope"), "error: nope");
+        assert_eq!(strip_ansi("\x1b]0;title\x07text"), "text");
+        assert_eq!(strip_ansi("\x1b]8;;url\x1b\\link"), "link");
+        assert_eq!(strip_ansi("plain é"), "plain é");
+    }
+
+    #[test]
+    fn test_status_badge() {
+        let mut cmd = CapturedCommand::new("make".to_string(), PathBuf::from("/tmp"));
+        assert_eq!(cmd.status_badge(), ("–", Color::DarkGray));
+        assert_eq!(cmd.status_label(), "–");
+
+        cmd.set_exit_code(0);
+        assert_eq!(cmd.status_badge(), ("✓", Color::Green));
+        assert_eq!(cmd.status_label(), "✓");
+
+        cmd.set_exit_code(127);
+        assert_eq!(cmd.status_badge(), ("✗", Color::Red));
+        assert_eq!(cmd.status_label(), "✗ 127");
+    }
+
+    #[test]
+    fn test_fuzzy_search_ranking() {
+        let cwd = PathBuf::from("/tmp");
+        let mut capture = CommandCapture::new();
+        for command in ["git status", "grep -r pst src", "ls", "git stash"] {
+            capture.start_command(command.to_string(), cwd.clone());
+            capture.finalize_command(0);
+        }
+
+        let found: Vec<&str> = capture.fuzzy_search("gst").iter().map(|(_, cmd)| cmd.command.as_str()).collect();
+        // "git status" and "git stash" score the same, the more recent one comes first
+        assert_eq!(found, vec!["git stash", "git status", "grep -r pst src"]);
+
+        let all = capture.fuzzy_search("");
+        assert_eq!(all.len(), 4);
+        assert_eq!(capture.index_of(all[0].1), Some(3));
+    }
+
+    #[test]
+    fn test_notes_in_labels_and_context() {
+        let cwd = PathBuf::from("/tmp");
+        let mut capture = CommandCapture::new();
+        capture.start_command("make test".to_string(), cwd.clone());
+        capture.finalize_command(2);
+
+        assert!(capture.set_note(0, Some("étape de repro".to_string())));
+        assert!(!capture.set_note(5, Some("x".to_string())));
+        let cmd = capture.get(0).unwrap();
+        assert_eq!(cmd.status_label(), "🏷 ✗ 2");
+        assert!(cmd.to_context_entry(true).contains("# note: étape de repro"));
+        assert!(!cmd.to_context_entry(false).contains("note"));
+
+        assert!(capture.recent_context(ContextStrategy::RecentN, &cwd, 10, 10_000)[0].contains("# note:"));
+        let capture = capture.with_notes_in_context(false);
+        assert!(!capture.recent_context(ContextStrategy::RecentN, &cwd, 10, 10_000)[0].contains("# note:"));
+    }
+
+    #[test]
+    fn test_recent_context_budget() {
+        let mut capture = CommandCapture::new();
+        let cwd = PathBuf::from("/home/user");
+
+        for name in ["first", "second", "third"] {
+            capture.start_command(name.to_string(), cwd.clone());
+            capture.finalize_command(0);
+        }
+
+        let recent = ContextStrategy::RecentN;
+
+        // Everything fits, oldest first
+        let all = capture.recent_context(recent, &cwd, 100, 10_000);
+        assert_eq!(all.len(), 3);
+        assert!(all[0].starts_with("$ first"));
+        assert!(all[2].starts_with("$ third"));
+
+        // A tight budget keeps only the newest entries
+        let budget = all[2].len() + all[1].len();
+        let limited = capture.recent_context(recent, &cwd, 100, budget);
+        assert_eq!(limited.len(), 2);
+        assert!(limited[0].starts_with("$ second"));
+
+        // So does the command count limit
+        let last = capture.recent_context(recent, &cwd, 1, 10_000);
+        assert_eq!(last.len(), 1);
+        assert!(last[0].starts_with("$ third"));
+
+        // The newest entry is truncated rather than dropped
+        let tiny = capture.recent_context(recent, &cwd, 100, 10);
+        assert_eq!(tiny.len(), 1);
+        assert!(tiny[0].len() <= 10 + "[…tronqué]\n".len());
+    }
+
+    #[test]
+    fn test_context_strategy_failed_first() {
+        let mut capture = CommandCapture::new();
+        let cwd = PathBuf::from("/home/user");
+
+        for (name, code) in [("broken", 1), ("ok1", 0), ("ok2", 0)] {
+            capture.start_command(name.to_string(), cwd.clone());
+            capture.finalize_command(code);
+        }
+
+        // Only two slots: the failure wins over the older success, output stays chronological
+        let context = capture.recent_context(ContextStrategy::FailedFirst, &cwd, 2, 10_000);
+        assert_eq!(context.len(), 2);
+        assert!(context[0].starts_with("$ broken"));
+        assert!(context[1].starts_with("$ ok2"));
+    }
+
+    #[test]
+    fn test_context_strategy_current_dir_only() {
+        let mut capture = CommandCapture::new();
+        let project = PathBuf::from("/home/user/project");
+        let other = PathBuf::from("/tmp");
+
+        capture.start_command("make".to_string(), project.clone());
+        capture.finalize_command(0);
+        capture.start_command("ls".to_string(), other);
+        capture.finalize_command(0);
+
+        let context = capture.recent_context(ContextStrategy::CurrentDirOnly, &project, 100, 10_000);
+        assert_eq!(context.len(), 1);
+        assert!(context[0].starts_with("$ make"));
+    }
+
+    #[test]
+    fn test_progress_bar_keeps_final_state() {
+        let mut cmd = CapturedCommand::new("curl -O".to_string(), PathBuf::from("/tmp"));
+        cmd.append_output("Starting\r\n");
+        for percent in (0..=100).step_by(10) {
+            cmd.append_output(&format!("\rDownloading {}%", percent));
+        }
+        // A CRLF split across two chunks is still a newline
+        cmd.append_output("\r");
+        cmd.append_output("\nDone\r\n");
+        assert_eq!(cmd.output, "Starting\r\nDownloading 100%\r\nDone\r\n");
+
+        // Redraws only replace the last line, even once the output is long
+        cmd.append_output("line 1\nline 2\n50%\r");
+        cmd.append_output("100%");
+        assert!(cmd.output.ends_with("Done\r\nline 1\nline 2\n100%"));
+
+        // A carriage return the command ended on is kept
+        cmd.append_output("\r");
+        cmd.set_exit_code(0);
+        assert!(cmd.output.ends_with("\n100%\r"));
+    }
+
+    #[test]
+    fn test_current_dir_from_osc_7() {
+        let mut capture = CommandCapture::new();
+        let started_in = PathBuf::from("/home/user");
+
+        capture.process_output(
+            "\x1b]7;file://host/home/user/api\x07\x1b]133;C;cargo test\x07ok\n\x1b]133;D;0\x07\
+             \x1b]7;file://host/home/user/web%20app\x07\x1b]133;C;npm test\x07ok\n\x1b]133;D;0\x07\
+             \x1b]7;file://host/home/user/api\x07\x1b]133;C;cargo build\x07\x1b]133;D;0\x07\
+             \x1b]7;file://host/home/user/web%20app\x07",
+            &started_in,
+        );
+
+        let here = capture.current_dir().unwrap().to_path_buf();
+        assert_eq!(here, PathBuf::from("/home/user/web app"));
+        assert_eq!(capture.get_commands()[1].working_dir, here);
+
+        let context = capture.recent_context(ContextStrategy::CurrentDirOnly, &here, 100, 10_000);
+        assert_eq!(context.len(), 1);
+        assert!(context[0].starts_with("$ npm test"));
+    }
+
+    #[test]
+    fn test_consecutive_commands_in_one_chunk() {
+        let mut capture = CommandCapture::new();
+        let cwd = PathBuf::from("/home/user");
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        capture.add_sink(Box::new(RecordingSink(recorded.clone())));
+
+        capture.process_output(
+            "\x1b]133;C;a\x07out a\n\x1b]133;D;0\x07\
+             \x1b]133;C;b\x07out b\n\x1b]133;D;1\x07\
+             \x1b]133;C;c\x07out c\n\x1b]133;D;2\x07~ % ",
+            &cwd,
+        );
+
+        let commands = capture.get_commands();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].command, "a");
+        assert_eq!(commands[0].output, "out a\n");
+        assert_eq!(commands[0].exit_code, Some(0));
+        assert_eq!(commands[1].command, "b");
+        assert_eq!(commands[1].output, "out b\n");
+        assert_eq!(commands[1].exit_code, Some(1));
+
+        let current = capture.current().unwrap();
+        assert_eq!(current.command, "c");
+        assert_eq!(current.output, "out c\n");
+        assert_eq!(current.exit_code, Some(2));
+
+        assert_eq!(recorded.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_marker_split_across_chunks() {
+        let mut capture = CommandCapture::new();
+        let cwd = PathBuf::from("/home/user");
+
+        capture.process_output("\x1b]133;C;git st", &cwd);
+        assert!(capture.current().is_none());
+
+        capture.process_output("atus\x07clean\n\x1b]133;D;0\x07", &cwd);
+        let current = capture.current().unwrap();
+        assert_eq!(current.command, "git status");
+        assert_eq!(current.output, "clean\n");
+        assert_eq!(current.exit_code, Some(0));
+    }
+
+    #[test]
+    fn test_sink_notified_on_osc_finish() {
+        let mut capture = CommandCapture::new();
+        let cwd = PathBuf::from("/home/user");
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        capture.add_sink(Box::new(RecordingSink(recorded.clone())));
+
+        capture.process_output("\x1b]133;C;make\x07", &cwd);
+        assert!(recorded.lock().unwrap().is_empty());
+
+        capture.process_output("error\n\x1b]133;D;2\x07", &cwd);
+        assert_eq!(*recorded.lock().unwrap(), vec![("make".to_string(), Some(2))]);
+
+        // An empty Enter: another D for the same command, ignored
+        capture.process_output("\x1b]133;D;0\x07", &cwd);
+        assert_eq!(*recorded.lock().unwrap(), vec![("make".to_string(), Some(2))]);
+        assert_eq!(capture.current().unwrap().exit_code, Some(2));
+    }
+
+    #[test]
+    fn test_sink_notified_on_finalize() {
+        let mut capture = CommandCapture::new();
+        let cwd = PathBuf::from("/home/user");
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        capture.add_sink(Box::new(RecordingSink(recorded.clone())));
+
+        capture.start_command("ls".to_string(), cwd);
+        capture.finalize_command(0);
+
+        assert_eq!(*recorded.lock().unwrap(), vec![("ls".to_string(), Some(0))]);
+    }
 }
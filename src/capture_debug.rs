@@ -0,0 +1,147 @@
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use crossterm::event::{self, Event, KeyCode};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::Alignment,
+    style::{Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph},
+    Terminal,
+};
+use std::io::Stdout;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::capture::{self, CaptureEvent, CommandCapture};
+use crate::keys;
+use crate::theme::Theme;
+
+/// How often the panel picks up new markers
+const REFRESH: Duration = Duration::from_millis(200);
+
+/// Live view of the shell integration markers the capture parses, to check the hooks fire
+/// Refreshed while open: markers of a running command or a background job show up as they arrive
+pub fn run_capture_debug(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    capture: &Arc<Mutex<CommandCapture>>,
+    theme: &Theme,
+) -> Result<()> {
+    loop {
+        // Formatted under the lock, drawn after it's released
+        let (lines, recording, current_dir) = match capture.lock() {
+            Ok(capture) => (
+                event_lines(capture.recent_events(), theme),
+                capture.is_recording(),
+                capture.current_dir().map(|dir| dir.display().to_string()),
+            ),
+            Err(_) => return Ok(()),
+        };
+
+        terminal.draw(|frame| {
+            let area = frame.area();
+            let mut text = vec![
+                Line::from(vec![
+                    Span::raw("Capture: "),
+                    Span::raw(capture::recording_indicator(recording)),
+                    Span::raw("   Répertoire (OSC 7): "),
+                    Span::styled(
+                        current_dir.unwrap_or_else(|| "jamais signalé".to_string()),
+                        Style::default().fg(theme.chat_border),
+                    ),
+                ]),
+                Line::from(""),
+            ];
+            if lines.is_empty() {
+                text.push(Line::from(Span::styled(
+                    "Aucun marqueur reçu: les hooks du shell ne semblent pas actifs (petoncle doctor)",
+                    Style::default().fg(theme.highlight),
+                )));
+            }
+            // The newest markers stay in view
+            let visible = (area.height as usize).saturating_sub(text.len() + 2);
+            text.extend(lines[lines.len().saturating_sub(visible)..].iter().cloned());
+
+            let panel = Paragraph::new(text).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(theme.logs_border))
+                    .title("🔬 Marqueurs du shell (OSC 133 / OSC 7) — q quitter")
+                    .title_alignment(Alignment::Center),
+            );
+            frame.render_widget(panel, area);
+        })?;
+
+        if !event::poll(REFRESH)? {
+            continue;
+        }
+        match event::read()? {
+            Event::Key(key_event) if keys::is_key_input(&key_event) => {
+                if matches!(key_event.code, KeyCode::Char('q') | KeyCode::Esc) {
+                    return Ok(());
+                }
+            }
+            Event::Resize(_, _) => terminal.autoresize()?,
+            _ => {}
+        }
+    }
+}
+
+/// One colored line per marker, oldest first
+fn event_lines<'a>(
+    events: impl Iterator<Item = &'a (DateTime<Local>, CaptureEvent)>,
+    theme: &Theme,
+) -> Vec<Line<'static>> {
+    events
+        .map(|(at, event)| {
+            let (marker, color, detail) = match event {
+                CaptureEvent::PromptStart => ("133;A", theme.code, "invite".to_string()),
+                CaptureEvent::PromptEnd => ("133;B", theme.code, "saisie de la commande".to_string()),
+                CaptureEvent::CommandStart(command) => ("133;C", theme.user, format!("$ {}", command)),
+                CaptureEvent::CommandEnd(0) => ("133;D", theme.assistant, "exit 0".to_string()),
+                CaptureEvent::CommandEnd(code) => ("133;D", theme.badges.error, format!("exit {}", code)),
+                CaptureEvent::MalformedEnd(code) => {
+                    ("133;D", theme.highlight, format!("code illisible {:?}, ignoré", code))
+                }
+                CaptureEvent::WorkingDir(dir) => ("7", theme.chat_border, format!("cd {}", dir.display())),
+            };
+            Line::from(vec![
+                Span::styled(at.format("%H:%M:%S%.3f ").to_string(), Style::default().fg(theme.badges.system)),
+                Span::styled(format!("{:<6}", marker), Style::default().fg(color).add_modifier(Modifier::BOLD)),
+                Span::raw(detail),
+            ])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_event_lines() {
+        let mut capture = CommandCapture::new();
+        capture.process_output(
+            "\x1b]7;file://host/srv\x07\x1b]133;C;make\x07oops\n\x1b]133;D;2\x07\x1b]133;D;abc\x07\
+             \x1b]133;A\x07% \x1b]133;B\x07",
+            &PathBuf::from("/tmp"),
+        );
+
+        let text: Vec<String> = event_lines(capture.recent_events(), &Theme::default())
+            .iter()
+            .map(|line| line.spans[1..].iter().map(|span| span.content.as_ref()).collect())
+            .collect();
+        assert_eq!(
+            text,
+            [
+                "7     cd /srv",
+                "133;C $ make",
+                "133;D exit 2",
+                "133;D code illisible \"abc\", ignoré",
+                "133;A invite",
+                "133;B saisie de la commande",
+            ]
+        );
+    }
+}
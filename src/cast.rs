@@ -0,0 +1,172 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::capture;
+
+/// Common start of the markers written by Petoncle's hooks (133;A, 133;B, 133;C;<command> and
+/// 133;D;<code>)
+const OSC_133_PREFIX: &str = "\x1b]133;";
+
+/// Longest marker held back waiting for its terminator, past it the bytes are recorded as they are
+const MAX_HELD_MARKER: usize = 4096;
+
+/// Shell output recorded as an asciicast v2 file, replayable with `asciinema play`
+pub struct CastRecorder {
+    out: BufWriter<File>,
+    started: Instant,
+    strip_markers: bool,
+    pending: Vec<u8>, // Cut off at the end of the last chunk: a UTF-8 character or a hook marker
+}
+
+impl CastRecorder {
+    /// Start a recording of a `cols` x `rows` terminal
+    /// `strip_markers` leaves the OSC 133 markers of Petoncle's hooks out of the file
+    pub fn create(path: &Path, cols: u16, rows: u16, strip_markers: bool) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("Impossible de créer {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        let header = serde_json::json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": Local::now().timestamp(),
+            "env": { "TERM": "xterm-256color" },
+        });
+        writeln!(out, "{}", header)?;
+        out.flush()?;
+        Ok(Self {
+            out,
+            started: Instant::now(),
+            strip_markers,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Record shell output, as read from the PTY
+    pub fn write_output(&mut self, data: &[u8]) -> Result<()> {
+        let mut bytes = std::mem::take(&mut self.pending);
+        bytes.extend_from_slice(data);
+        let split = capture::incomplete_utf8_start(&bytes);
+        self.pending = bytes.split_off(split);
+
+        let text = String::from_utf8_lossy(&bytes);
+        let text = if self.strip_markers {
+            let (kept, held) = strip_hook_markers(&text);
+            self.pending.splice(0..0, held.bytes());
+            kept
+        } else {
+            text.into_owned()
+        };
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let event = serde_json::json!([self.started.elapsed().as_secs_f64(), "o", text]);
+        writeln!(self.out, "{}", event)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Remove the OSC 133 A/B/C/D markers, keeping any other sequence (colors, titles...)
+/// Returns the text to record and the end of it that may be the start of a marker, held for the
+/// next chunk
+fn strip_hook_markers(text: &str) -> (String, &str) {
+    let mut kept = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(OSC_133_PREFIX) {
+        let kind = &rest[start + OSC_133_PREFIX.len()..];
+        if !matches!(kind.as_bytes(), [b'A'..=b'D', b';' | b'\x07', ..]) {
+            if matches!(kind.as_bytes(), [] | [b'A'..=b'D']) {
+                kept.push_str(&rest[..start]);
+                return (kept, &rest[start..]);
+            }
+            kept.push_str(&rest[..start + OSC_133_PREFIX.len()]);
+            rest = kind;
+            continue;
+        }
+
+        // Terminated by BEL (what the hooks write) or ST
+        let bel = kind.find('\x07').map(|end| end + 1);
+        let st = kind.find("\x1b\\").map(|end| end + 2);
+        match bel.into_iter().chain(st).min() {
+            Some(end) => {
+                kept.push_str(&rest[..start]);
+                rest = &kind[end..];
+            }
+            None if rest.len() - start <= MAX_HELD_MARKER => {
+                kept.push_str(&rest[..start]);
+                return (kept, &rest[start..]);
+            }
+            None => {
+                kept.push_str(rest);
+                return (kept, "");
+            }
+        }
+    }
+
+    // "\x1b]13" at the very end may be the start of a marker
+    match rest.rfind('\x1b').filter(|&start| OSC_133_PREFIX.starts_with(&rest[start..])) {
+        Some(start) => {
+            kept.push_str(&rest[..start]);
+            (kept, &rest[start..])
+        }
+        None => {
+            kept.push_str(rest);
+            (kept, "")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output text of the events of a cast file
+    fn recorded_output(path: &Path) -> String {
+        let content = std::fs::read_to_string(path).unwrap();
+        let mut lines = content.lines();
+        let header: serde_json::Value = serde_json::from_str(lines.next().unwrap()).unwrap();
+        assert_eq!(header["version"], 2);
+        lines
+            .map(|line| {
+                let event: serde_json::Value = serde_json::from_str(line).unwrap();
+                assert_eq!(event[1], "o");
+                event[2].as_str().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_hook_markers_left_out_of_the_cast() {
+        let chunks: [&[u8]; 5] = [
+            b"$ \x1b]133;C;ls\x07file\r\n\x1b]1",
+            b"33;D;0\x07\x1b[32m$\x1b[0m \x1b]133;A\x07caf\xc3",
+            b"\xa9 \x1b]133;C;m",
+            b"ake\x1b\\",
+            b"ok\r\n",
+        ];
+
+        let path = std::env::temp_dir().join(format!("petoncle-cast-{}.cast", std::process::id()));
+        let mut recorder = CastRecorder::create(&path, 80, 24, true).unwrap();
+        for chunk in chunks {
+            recorder.write_output(chunk).unwrap();
+        }
+        assert_eq!(
+            recorded_output(&path),
+            "$ file\r\n\x1b[32m$\x1b[0m café ok\r\n"
+        );
+
+        // Kept when asked to
+        let mut recorder = CastRecorder::create(&path, 80, 24, false).unwrap();
+        for chunk in chunks {
+            recorder.write_output(chunk).unwrap();
+        }
+        let output = recorded_output(&path);
+        std::fs::remove_file(&path).ok();
+        assert!(output.starts_with("$ \x1b]133;C;ls\x07file\r\n\x1b]133;D;0\x07"));
+    }
+}
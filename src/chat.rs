@@ -1,6 +1,6 @@
 use anyhow::Result;
 use chrono::{DateTime, Local};
-use crossterm::event::{self, Event, KeyCode};
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
 use ratatui::{
     backend::CrosstermBackend,
     layout::{Alignment, Constraint, Direction, Layout, Rect},
@@ -9,13 +9,34 @@ use ratatui::{
     widgets::{Block, Borders, Paragraph, Wrap},
     Frame, Terminal,
 };
-use std::io::Stdout;
-use std::sync::mpsc::{self, Receiver, Sender};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{Read, Seek, SeekFrom, Stdout};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
-use tokio::runtime::Runtime;
+use tracing::{debug, field, info, info_span, warn};
+use unicode_segmentation::UnicodeSegmentation;
 
-use crate::grpc_client::AgentClient;
+use crate::actions::{AgentAction, PtyWriter};
+use crate::attach;
+use crate::capture::{self, CaptureStats, CapturedCommand, CommandCapture, ContextStrategy};
+use crate::clipboard;
+use crate::commands::ChatCommand;
+use crate::config::{BackendConfig, Config, ExportConfig};
+use crate::diff;
+use crate::export;
+use crate::fuzzy;
+use crate::grpc_client::MessageTooLarge;
+use crate::keys;
+use crate::markdown::{self, CodeBlock, LineKind};
+use crate::pending::{self, OutputGate};
+use crate::theme::{BadgeColors, Theme};
+use crate::transport::{self, ChatTransport, Progress};
 
 #[derive(Debug, Clone)]
 pub enum MessageRole {
@@ -27,8 +48,17 @@ pub enum MessageRole {
 pub enum MessageState {
     Loading,
     Ready,
+
+    /// Question from the agent, waiting for the user's answer
+    Question,
+
+    /// The agent answered with nothing: a placeholder, styled like an error
+    Empty,
 }
 
+/// Content of a reply the agent left empty
+const EMPTY_REPLY_PLACEHOLDER: &str = "(aucune réponse)";
+
 #[derive(Debug, Clone)]
 pub struct ChatMessage {
     pub role: MessageRole,
@@ -36,29 +66,258 @@ pub struct ChatMessage {
     pub timestamp: DateTime<Local>,
     pub state: MessageState,
     pub agent: Option<String>, // Which agent handled this message (toolsmith, researcher, scribe, general)
+    pub prompt: Option<SentPrompt>, // What was sent to get this reply, for regeneration (assistant replies only)
+}
+
+impl ChatMessage {
+    /// Fenced code blocks of the message, numbered from 1 in the UI
+    pub fn code_blocks(&self) -> Vec<CodeBlock> {
+        markdown::code_blocks(&self.content)
+    }
+}
+
+/// Prompt and context sent to the agent for one reply
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentPrompt {
+    pub text: String,
+    pub context: Vec<String>,
+}
+
+/// How message timestamps are displayed
+/// Configured as a strftime string (e.g. "%d/%m %H:%M") or "relative" ("2m", "1h")
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "String")]
+pub enum TimestampFormat {
+    Strftime(String),
+    Relative,
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        TimestampFormat::Strftime("%H:%M:%S".to_string())
+    }
+}
+
+impl From<String> for TimestampFormat {
+    fn from(value: String) -> Self {
+        if value == "relative" {
+            TimestampFormat::Relative
+        } else {
+            TimestampFormat::Strftime(value)
+        }
+    }
+}
+
+impl TimestampFormat {
+    /// Format a timestamp, relative to `now` in relative mode
+    /// Relative labels are padded to a fixed width so headers don't jitter as they age
+    pub fn format(&self, timestamp: DateTime<Local>, now: DateTime<Local>) -> String {
+        match self {
+            TimestampFormat::Strftime(format) => {
+                // Invalid user formats fail at display time, fall back rather than panic
+                let mut formatted = String::new();
+                if write!(formatted, "{}", timestamp.format(format)).is_err() {
+                    formatted = timestamp.format("%H:%M:%S").to_string();
+                }
+                formatted
+            }
+            TimestampFormat::Relative => {
+                let seconds = (now - timestamp).num_seconds().max(0);
+                let label = match seconds {
+                    0..=9 => "maintenant".to_string(),
+                    10..=59 => format!("{}s", seconds),
+                    60..=3599 => format!("{}m", seconds / 60),
+                    3600..=86_399 => format!("{}h", seconds / 3600),
+                    _ => format!("{}j", seconds / 86_400),
+                };
+                format!("{:>10}", label)
+            }
+        }
+    }
+}
+
+/// Reply from the agent worker thread
+#[derive(Debug, Clone)]
+pub struct AgentReply {
+    pub message: String,
+    pub agent: String,
+    pub action: Option<AgentAction>,
+    pub awaiting_clarification: bool,
+}
+
+/// Open clarification: the agent asked `question` about `original` and waits for the answer
+///
+/// State machine on `ChatState::clarification`:
+/// - None: the next message starts a new topic
+/// - a reply flagged `awaiting_clarification` opens one (keeping the first `original` if one was open)
+/// - the next message is sent as the answer, with the original request and the question as context,
+///   which closes it until the agent asks again
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clarification {
+    pub original: String,
+    pub question: String,
+}
+
+impl Clarification {
+    /// Context entry reminding the agent what the answer refers to
+    pub fn to_context_entry(&self) -> String {
+        format!(
+            "# Clarification\nDemande initiale: {}\nQuestion de l'agent: {}",
+            self.original, self.question
+        )
+    }
+}
+
+/// Number of log lines shown by `/logs`
+const LOG_TAIL_LINES: usize = 200;
+
+/// What the messages area currently shows
+#[derive(Debug, Clone)]
+pub enum ChatMode {
+    /// The conversation
+    Chat,
+    /// Read-only tail of the session log
+    Logs { lines: Vec<String>, scroll: u16 },
+    /// Read-only timeline of the captured commands, built when opened and in focus mode (`f`)
+    Timeline { lines: Vec<Line<'static>>, commands: usize, scroll: u16, focus: Option<TimelineFocus> },
+    /// Waiting for the user to accept or refuse an action proposed by the agent
+    ConfirmAction(AgentAction),
+    /// Fuzzy search over the captured commands, Enter shows the selected one's output
+    /// The matches are scored again only when the query changes, not on every frame
+    Palette { query: String, selected: usize, entries: Vec<PaletteEntry> },
+    /// Waiting for the number of the code block to copy from the last reply
+    CopyBlock,
+    /// Context assembled for a message, held until the user agrees to send it (`confirm_context`)
+    ConfirmContext { text: String, context: Vec<String>, expanded: bool, scroll: u16 },
+}
+
+/// Focus mode of the timeline: successful commands shrink to one line, failed ones show their output
+/// Each command can be unfolded or folded again, the selected one with Enter
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineFocus {
+    expanded: Vec<bool>,
+    selected: usize,
+}
+
+impl TimelineFocus {
+    /// Failures unfolded, the first of them selected (the last command when none failed)
+    fn new<'a>(commands: impl IntoIterator<Item = &'a CapturedCommand>) -> Self {
+        let expanded: Vec<bool> = commands
+            .into_iter()
+            .map(|cmd| cmd.exit_code.is_some_and(|code| code != 0))
+            .collect();
+        let selected = expanded
+            .iter()
+            .position(|&failed| failed)
+            .unwrap_or(expanded.len().saturating_sub(1));
+        Self { expanded, selected }
+    }
+
+    fn is_expanded(&self, index: usize) -> bool {
+        self.expanded.get(index).copied().unwrap_or(false)
+    }
+}
+
+/// A captured command listed in the palette
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteEntry {
+    pub index: usize, // 1-based, as in /output
+    pub label: String,
+    pub command: String,
+    pub positions: Vec<usize>, // Chars of the command matched by the query, highlighted
 }
 
 // Spinner frames for loading animation
 const SPINNER_FRAMES: &[&str] = &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
 
+/// Most output lines of an unfolded command in the timeline's focus mode, from the end
+const TIMELINE_OUTPUT_LINES: usize = 12;
+
+// Duration of one spinner frame
+const SPINNER_FRAME_MS: u128 = 80;
+
+/// Keys of the conversation view, in the messages pane title when it's wide enough
+const CHAT_KEY_HINTS: &str = "↑↓ scroller | Home/End haut/bas | Ctrl+B backend | Ctrl+R retour ligne | \
+                              Ctrl+G régénérer | Ctrl+Y copier un bloc | Ctrl+N sans contexte | \
+                              ESC quitter";
+
+/// Window over which `max_requests_per_minute` counts requests
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Seconds between health checks while waiting for the agent service, the chat gives up after the
+/// last one (about two minutes in all)
+const SERVICE_WAIT_SCHEDULE: &[u64] = &[1, 2, 3, 5, 8, 13, 20, 30, 30];
+
+/// First opening of the chat with the agent service not up yet: health checks in the background
+/// until it answers, a message typed meanwhile is sent as soon as it does
+struct ServiceWait {
+    attempt: usize, // Checks that failed so far
+    next_check: Instant,
+    check: Option<Receiver<Result<()>>>, // Check in flight
+    held: Option<String>, // Message waiting for the service
+}
+
 pub struct ChatState {
     pub messages: Vec<ChatMessage>,
     pub input: String,
+    pub input_cursor: usize, // Cursor position in the input (byte index, on a grapheme cluster boundary)
     pub scroll_offset: u16, // Scroll position (line-based)
     pub auto_scroll: bool, // Auto-scroll to bottom on new message
+    pub auto_scroll_threshold: Option<u16>, // Lines from the bottom still counted as "at the bottom" (None: one screen)
+    send_on_idle: Option<Duration>, // Input left unchanged this long is sent without Enter
+    last_input_change: Option<Instant>, // Last edit of a message not sent yet
+    idle_close: Option<Duration>, // The chat closes by itself after this long without input
+    max_requests_per_minute: Option<u32>, // Requests beyond this within `RATE_WINDOW` are refused
+    recent_requests: VecDeque<Instant>, // When the requests of the last `RATE_WINDOW` were sent
+    last_interaction: Instant, // Last key or paste, or the last reply received
     pub last_visible_height: u16, // Last known visible height of messages area
-    pub spinner_frame: usize, // Current spinner frame index
-    pub last_spinner_update: Instant, // Last time spinner was updated
-    pub response_receiver: Option<Receiver<Result<(String, String)>>>, // Channel to receive async responses (message, agent)
-    grpc_client: AgentClient,
-    runtime: Runtime,
+    pub last_visible_width: u16, // Last known inner width of messages area (for wrapped line counts)
+    pub wrap_enabled: bool, // Wrap long message lines, or scroll them horizontally
+    pub h_scroll: u16, // Horizontal scroll position when wrapping is off (columns)
+    pub spinner_start: Instant, // When the spinner started, frames derive from elapsed time
+    pub response_receiver: Option<Receiver<Result<AgentReply>>>, // Channel to receive async responses
+    progress_receiver: Option<Receiver<Progress>>, // Pieces of the answer and retries, before the full response
+    pub status: Option<String>, // Short notice shown under the input box
+    pub mode: ChatMode, // Conversation or an auxiliary read-only view
+    queued_action: Option<AgentAction>, // Arrived while a view was open, confirmed once it closes
+    pub clarification: Option<Clarification>, // Question the next message answers
+    turn_topic: Option<String>, // Original request of the turn in flight
+    log_file: Option<PathBuf>, // Session log, shown by /logs
+    session_id: Option<String>, // ID of the Petoncle session, written in exports
+    pty_writer: Option<PtyWriter>, // Shell input, used for confirmed agent actions
+    command_capture: Option<Arc<Mutex<CommandCapture>>>, // Captured shell commands
+    evicted_seen: usize, // Evictions the user was already told about
+    output_gate: Option<Arc<OutputGate>>, // Shell output held while the chat is open
+    pending_attachments: Vec<String>, // Files attached to the next message (sent as context)
+    context_budget: usize, // Maximum bytes of command output sent to the agent
+    context_commands: usize, // Maximum captured commands sent with a message under /here
+    here_only: bool, // Send the commands of the shell's current directory with each message
+    no_context_next: bool, // The next message goes out without any context (Ctrl+N, /nocontext)
+    confirm_context: bool, // Show the context of each message and wait for consent before sending
+    export: ExportConfig, // Defaults of /export-script
+    timestamp_format: TimestampFormat, // How message headers show time
+    user_name: String, // Display name of the user in message headers
+    assistant_name: String, // Display name of the assistant in message headers
+    theme: Theme, // Colors of the chat UI
+    backends: Vec<BackendConfig>, // Agent services available to the chat
+    active_backend: usize, // Index of the backend used for new messages
+    shared_backend: Arc<Mutex<BackendConfig>>, // Copy of the active backend for requests made outside the chat
+    mock: bool, // Canned responses instead of the agent service (PETONCLE_MOCK=1)
+    transport: Arc<dyn ChatTransport>, // Where messages go, shared with the worker threads
+    service_checked: bool, // The service was checked when the chat first opened
+    service_wait: Option<ServiceWait>, // Waiting for the service to come up
 }
 
 impl ChatState {
-    pub fn new() -> Self {
-        // Initialize gRPC client and tokio runtime
-        let grpc_client = AgentClient::new("127.0.0.1:50051");
-        let runtime = Runtime::new().expect("Failed to create tokio runtime");
+    pub fn new(config: &Config) -> Self {
+        let backends = config.backends();
+        let transport = transport::for_backend(&backends[0], config.mock);
+        let shared_backend = Arc::new(Mutex::new(backends[0].clone()));
+
+        let (theme, theme_errors) = Theme::from_config(&config.theme);
+        for error in theme_errors {
+            warn!("Ignoring theme override: {}", error);
+        }
 
         Self {
             messages: vec![ChatMessage {
@@ -67,33 +326,140 @@ impl ChatState {
                 timestamp: Local::now(),
                 state: MessageState::Ready,
                 agent: None,
+                prompt: None,
             }],
             input: String::new(),
+            input_cursor: 0,
             scroll_offset: 0,
             auto_scroll: true,
+            auto_scroll_threshold: config.auto_scroll_threshold_lines,
+            send_on_idle: config.send_on_idle_ms.map(Duration::from_millis),
+            last_input_change: None,
+            idle_close: config.chat_idle_close_secs.map(Duration::from_secs),
+            max_requests_per_minute: config.max_requests_per_minute,
+            recent_requests: VecDeque::new(),
+            last_interaction: Instant::now(),
             last_visible_height: 20, // Default fallback
-            spinner_frame: 0,
-            last_spinner_update: Instant::now(),
+            last_visible_width: 80,
+            wrap_enabled: true,
+            h_scroll: 0,
+            spinner_start: Instant::now(),
             response_receiver: None,
-            grpc_client,
-            runtime,
+            progress_receiver: None,
+            status: None,
+            mode: ChatMode::Chat,
+            queued_action: None,
+            clarification: None,
+            turn_topic: None,
+            log_file: None,
+            session_id: None,
+            pty_writer: None,
+            command_capture: None,
+            evicted_seen: 0,
+            output_gate: None,
+            pending_attachments: Vec::new(),
+            context_budget: config.context_budget,
+            context_commands: config.context_commands,
+            here_only: false,
+            no_context_next: false,
+            confirm_context: config.confirm_context,
+            export: config.export.clone(),
+            timestamp_format: config.timestamp_format.clone(),
+            user_name: config.user_name.clone(),
+            assistant_name: config.assistant_name.clone(),
+            theme,
+            backends,
+            active_backend: 0,
+            shared_backend,
+            mock: config.mock,
+            transport,
+            service_checked: false,
+            service_wait: None,
         }
     }
 
     /// Calculate total number of lines in all messages
     fn count_total_lines(&self) -> usize {
+        self.messages.iter().map(|msg| self.message_rows(msg)).sum()
+    }
+
+    /// Screen rows one message takes, as `message_lines` lays it out
+    fn message_rows(&self, msg: &ChatMessage) -> usize {
         let mut count = 0;
-        for msg in &self.messages {
-            count += 1; // Header line
-            count += 1; // Empty line after header
-            count += msg.content.lines().count();
-            count += 1; // Empty line
-            count += 1; // Separator
-            count += 1; // Empty line after separator
-        }
+        count += 1; // Header line
+        count += 1; // Empty line after header
+        count += msg.content.lines().map(|line| self.display_rows(line)).sum::<usize>();
+        count += 1; // Empty line
+        count += 1; // Separator
+        count += 1; // Empty line after separator
         count
     }
 
+    /// Messages with at least one row in `[top, top + height)`, and the row the first of them starts at
+    /// Only these are turned into lines on each frame, long conversations don't cost more to draw
+    fn visible_messages(&self, top: usize, height: usize) -> (Range<usize>, usize) {
+        let mut row = 0;
+        let mut first = None;
+        for (i, msg) in self.messages.iter().enumerate() {
+            let rows = self.message_rows(msg);
+            if first.is_none() && row + rows > top {
+                first = Some((i, row));
+            }
+            if row + rows >= top + height
+                && let Some((start, start_row)) = first
+            {
+                return (start..i + 1, start_row);
+            }
+            row += rows;
+        }
+        match first {
+            Some((start, start_row)) => (start..self.messages.len(), start_row),
+            None => (self.messages.len()..self.messages.len(), row),
+        }
+    }
+
+    /// Screen rows a content line takes: several when wrapped, always one otherwise
+    fn display_rows(&self, line: &str) -> usize {
+        let width = self.last_visible_width as usize;
+        if !self.wrap_enabled || width == 0 {
+            return 1;
+        }
+        Line::raw(line).width().div_ceil(width).max(1)
+    }
+
+    /// Width of the widest message line, the limit for horizontal scrolling
+    fn max_line_width(&self) -> usize {
+        self.messages
+            .iter()
+            .flat_map(|msg| msg.content.lines())
+            .map(|line| Line::raw(line).width())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Switch between wrapped lines and horizontal scrolling
+    pub fn toggle_wrap(&mut self) {
+        self.wrap_enabled = !self.wrap_enabled;
+        self.h_scroll = 0;
+        self.clamp_scroll(self.last_visible_height);
+        self.status = Some(if self.wrap_enabled {
+            "Retour à la ligne activé".to_string()
+        } else {
+            "Retour à la ligne désactivé (←→ pour défiler)".to_string()
+        });
+    }
+
+    /// Scroll left (no-wrap mode)
+    pub fn scroll_left(&mut self, columns: u16) {
+        self.h_scroll = self.h_scroll.saturating_sub(columns);
+    }
+
+    /// Scroll right (no-wrap mode), stopping when the widest line is fully visible
+    pub fn scroll_right(&mut self, columns: u16) {
+        let max = self.max_line_width().saturating_sub(self.last_visible_width as usize) as u16;
+        self.h_scroll = (self.h_scroll + columns).min(max);
+    }
+
     /// Scroll to the latest message (bottom of chat)
     pub fn scroll_to_bottom(&mut self, visible_height: u16) {
         let total_lines = self.count_total_lines();
@@ -119,6 +485,28 @@ impl ChatState {
         }
     }
 
+    /// Re-clamp the scroll offset after the content shrank (messages removed, collapsed...)
+    pub fn clamp_scroll(&mut self, visible_height: u16) {
+        self.scroll_offset = self.scroll_offset.min(self.max_scroll_offset(visible_height));
+    }
+
+    /// Terminal resized while the chat is open
+    /// The next render recomputes the visible area and re-clamps the offset; a view showing
+    /// the last message keeps showing it instead of clipping it or leaving blank space
+    pub fn handle_resize(&mut self) {
+        if self.scroll_offset >= self.max_scroll_offset(self.last_visible_height) {
+            self.auto_scroll = true;
+        }
+    }
+
+    /// Whether the view is close enough to the bottom to follow incoming messages
+    /// Scrolled further up, the user is reading history and shouldn't be yanked down
+    pub fn is_near_bottom(&self, visible_height: u16) -> bool {
+        let threshold = self.auto_scroll_threshold.unwrap_or(visible_height);
+        let distance = self.max_scroll_offset(visible_height).saturating_sub(self.scroll_offset);
+        distance <= threshold
+    }
+
     /// Scroll down by n lines, respecting bounds
     pub fn scroll_down(&mut self, n: u16, visible_height: u16) {
         let max_offset = self.max_scroll_offset(visible_height);
@@ -139,19 +527,22 @@ impl ChatState {
             timestamp: Local::now(),
             state: MessageState::Ready,
             agent: None, // User messages don't have an agent
+            prompt: None,
         });
         self.auto_scroll = true; // Request auto-scroll on next render
     }
 
     pub fn add_assistant_message(&mut self, content: String, agent: Option<String>) {
+        let follow = self.is_near_bottom(self.last_visible_height);
         self.messages.push(ChatMessage {
             role: MessageRole::Assistant,
             content,
             timestamp: Local::now(),
             state: MessageState::Ready,
             agent,
+            prompt: None,
         });
-        self.auto_scroll = true; // Request auto-scroll on next render
+        self.auto_scroll |= follow; // Request auto-scroll on next render unless reading history
     }
 
     pub fn add_loading_message(&mut self) {
@@ -161,286 +552,1774 @@ impl ChatState {
             timestamp: Local::now(),
             state: MessageState::Loading,
             agent: None, // Will be set when response is received
+            prompt: None,
         });
+        self.spinner_start = Instant::now();
         self.auto_scroll = true;
     }
 
     pub fn update_last_message(&mut self, content: String, agent: Option<String>) {
+        let follow = self.is_near_bottom(self.last_visible_height);
         if let Some(last) = self.messages.last_mut() {
             last.content = content;
             last.state = MessageState::Ready;
             last.agent = agent;
-            self.auto_scroll = true;
+            self.auto_scroll |= follow;
         }
     }
 
     pub fn clear_input(&mut self) {
         self.input.clear();
+        self.input_cursor = 0;
+        self.last_input_change = None;
     }
 
-    /// Start generating AI response asynchronously (non-blocking)
-    pub fn start_generate_response(&mut self, user_input: String) {
-        // Create channel for async communication
-        let (tx, rx): (Sender<Result<(String, String)>>, Receiver<Result<(String, String)>>) = mpsc::channel();
+    /// Insert a character at the cursor
+    pub fn insert_char(&mut self, c: char) {
+        self.input.insert(self.input_cursor, c);
+        self.input_cursor += c.len_utf8();
+        self.last_input_change = Some(Instant::now());
+    }
 
-        // Take ownership of grpc_client temporarily
-        let mut client = AgentClient::new("127.0.0.1:50051");
-        std::mem::swap(&mut client, &mut self.grpc_client);
+    /// Insert text (e.g. a paste) at the cursor
+    pub fn insert_str(&mut self, text: &str) {
+        self.input.insert_str(self.input_cursor, text);
+        self.input_cursor += text.len();
+        self.last_input_change = Some(Instant::now());
+    }
 
-        // Spawn thread to handle gRPC call
-        thread::spawn(move || {
-            // Create runtime for this thread
-            let runtime = Runtime::new().unwrap();
+    /// Delete the character before the cursor, as seen on screen: a whole grapheme cluster (flag,
+    /// emoji with a skin tone, ZWJ sequence)
+    pub fn backspace(&mut self) {
+        if let Some(cluster) = self.input[..self.input_cursor].graphemes(true).next_back() {
+            let start = self.input_cursor - cluster.len();
+            self.input.replace_range(start..self.input_cursor, "");
+            self.input_cursor = start;
+            self.last_input_change = Some(Instant::now());
+        }
+    }
 
-            let result = runtime.block_on(async {
-                client.send_message(user_input, vec![]).await
-            });
+    /// Delete the character under the cursor
+    pub fn delete_forward(&mut self) {
+        if let Some(cluster) = self.input[self.input_cursor..].graphemes(true).next() {
+            let end = self.input_cursor + cluster.len();
+            self.input.replace_range(self.input_cursor..end, "");
+            self.last_input_change = Some(Instant::now());
+        }
+    }
 
-            let response = match result {
-                Ok(resp) => Ok((resp.message, resp.agent)),
-                Err(e) => Ok((format!(
-                    "⚠️ Service IA non disponible\n\n\
-                     Erreur: {}\n\n\
-                     💡 Assurez-vous que le service Python est démarré:\n\
-                     cd python && python agent_service.py",
-                    e
-                ), "error".to_string())),
+    /// Send the typed message, or run it when it's a slash command (Enter)
+    pub fn submit_input(&mut self) {
+        // Slash commands are handled locally, never sent to the agent
+        if let Some(parsed) = ChatCommand::parse(&self.input) {
+            self.clear_input();
+            match parsed {
+                Ok(command) => self.execute_command(command),
+                Err(e) => self.add_system_message(format!("❌ {}", e)),
+            }
+            return;
+        }
+
+        if self.service_wait.as_ref().is_some_and(|wait| wait.held.is_some()) {
+            self.status = Some("Un message attend déjà le service IA".to_string());
+            return;
+        }
+
+        // Send message, a repeated Enter while a request is out is ignored
+        if !self.input.is_empty() && !self.is_sending() {
+            // Refused before anything changes: the text stays in the input for later
+            if self.rate_limited(Instant::now()) {
+                return;
+            }
+
+            // {{3}} / {{last}}: the command and its output go to the agent, a folded
+            // reference stays in the conversation
+            if self.input.contains("{{") {
+                self.warn_renumbered();
+            }
+            let expanded = match self.command_capture.as_ref().map(|c| c.lock()) {
+                Some(Ok(capture)) => expand_command_refs(&self.input, &capture, self.context_budget),
+                _ => ExpandedInput::unchanged(&self.input),
             };
+            self.add_user_message(expanded.shown);
+            self.clear_input();
+            if !expanded.unknown.is_empty() {
+                warn!("Unknown command references left as is: {:?}", expanded.unknown);
+                self.status = Some(format!(
+                    "⚠️ Référence(s) inconnue(s) laissée(s) telle(s) quelle(s): {}",
+                    expanded.unknown.join(", ")
+                ));
+            }
 
-            // Send result back
-            tx.send(response).ok();
-        });
+            // The service isn't up yet: the message goes out as soon as it is
+            if let Some(wait) = self.service_wait.as_mut() {
+                wait.held = Some(expanded.sent);
+                self.status = Some("Message envoyé dès que le service IA répond".to_string());
+                return;
+            }
 
-        // Store receiver
-        self.response_receiver = Some(rx);
+            // Start generating AI response asynchronously (non-blocking)
+            self.start_generate_response(expanded.sent);
+        }
+    }
 
-        // Add loading message
-        self.add_loading_message();
+    /// First opening: wait for the agent service in the background instead of failing the
+    /// first message (the mock is always ready)
+    pub fn start_service_wait(&mut self, now: Instant) {
+        if self.mock || std::mem::replace(&mut self.service_checked, true) {
+            return;
+        }
+        self.service_wait = Some(ServiceWait {
+            attempt: 0,
+            next_check: now,
+            check: None,
+            held: None,
+        });
     }
 
-    /// Check if response is ready and update message
-    pub fn check_response(&mut self) -> bool {
-        if let Some(ref receiver) = self.response_receiver {
-            if let Ok(result) = receiver.try_recv() {
-                // Response received!
-                match result {
-                    Ok((content, agent)) => {
-                        self.update_last_message(content, Some(agent));
-                    }
-                    Err(e) => {
-                        self.update_last_message(format!("❌ Error: {}", e), Some("error".to_string()));
-                    }
+    /// Run the next health check when due and pick up its result, true when the chat changed
+    pub fn poll_service_wait(&mut self, now: Instant) -> bool {
+        let Some(wait) = self.service_wait.as_mut() else {
+            return false;
+        };
+        let Some(ref check) = wait.check else {
+            if now >= wait.next_check {
+                let (tx, rx) = mpsc::channel();
+                let transport = Arc::clone(&self.transport);
+                thread::spawn(move || {
+                    tx.send(transport.check_ready()).ok();
+                });
+                wait.check = Some(rx);
+            }
+            return false;
+        };
+
+        let error = match check.try_recv() {
+            Ok(Ok(())) => {
+                info!("Agent service up after {} failed check(s)", wait.attempt);
+                let held = wait.held.take();
+                self.service_wait = None;
+                self.add_system_message("✅ Service IA prêt".to_string());
+                if let Some(message) = held {
+                    self.status = None;
+                    self.start_generate_response(message);
                 }
-                self.response_receiver = None;
                 return true;
             }
+            Ok(Err(e)) => e,
+            Err(TryRecvError::Empty) => return false,
+            Err(TryRecvError::Disconnected) => anyhow::anyhow!("la vérification s'est arrêtée"),
+        };
+
+        wait.check = None;
+        if let Some(&delay) = SERVICE_WAIT_SCHEDULE.get(wait.attempt) {
+            debug!("Agent service not up yet ({:#}), next check in {}s", error, delay);
+            wait.attempt += 1;
+            wait.next_check = now + Duration::from_secs(delay);
+            return false;
         }
-        false
-    }
 
-    /// Update spinner animation
-    pub fn update_spinner(&mut self) {
-        if self.last_spinner_update.elapsed() > Duration::from_millis(80) {
-            self.spinner_frame = (self.spinner_frame + 1) % SPINNER_FRAMES.len();
-            self.last_spinner_update = Instant::now();
+        // Out of patience: the usual error, a held message goes out to report it
+        warn!("Agent service still down after {} checks: {:#}", wait.attempt, error);
+        let held = wait.held.take();
+        self.service_wait = None;
+        self.add_system_message(format!(
+            "⚠️ Service IA toujours injoignable ({:#})\n\n\
+             💡 Assurez-vous que le service Python est démarré:\n\
+             cd python && python agent_service.py",
+            error
+        ));
+        if let Some(message) = held {
+            self.status = None;
+            self.start_generate_response(message);
         }
+        true
     }
-}
 
-/// Render the chat overlay UI
-pub fn render_chat_ui(
-    frame: &mut Frame,
-    state: &mut ChatState,
-    area: Rect,
-) {
-    // Create a centered popup area (80% width, 70% height)
-    let popup_area = centered_rect(80, 70, area);
+    /// Countdown to the next health check, under the input box while waiting for the service
+    fn service_wait_indicator(&self, now: Instant) -> Option<String> {
+        let wait = self.service_wait.as_ref()?;
+        Some(if wait.check.is_some() {
+            "⏳ En attente du service IA… vérification".to_string()
+        } else {
+            format!(
+                "⏳ En attente du service IA… nouvel essai dans {}s ({}/{})",
+                wait.next_check.saturating_duration_since(now).as_secs_f32().ceil(),
+                wait.attempt + 1,
+                SERVICE_WAIT_SCHEDULE.len() + 1
+            )
+        })
+    }
 
-    // Split into messages and input sections
-    let chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints(vec![
-            Constraint::Min(0),      // Messages
-            Constraint::Length(3),   // Input box
-        ])
-        .split(popup_area);
+    /// Whether the message should be sent on its own: send-on-idle is on, the input stopped
+    /// changing long enough ago, and nothing is pending
+    /// Slash commands still need Enter, a pause while typing one shouldn't run it half-written
+    pub fn idle_submit_due(&self, now: Instant) -> bool {
+        let (Some(idle), Some(changed)) = (self.send_on_idle, self.last_input_change) else {
+            return false;
+        };
+        matches!(self.mode, ChatMode::Chat)
+            && !self.is_sending()
+            && !self.input.trim().is_empty()
+            && !self.input.starts_with('/')
+            && now.duration_since(changed) >= idle
+    }
 
-    // Store the actual visible height of the messages area
-    let visible_height = chunks[0].height.saturating_sub(2); // Subtract borders
-    state.last_visible_height = visible_height;
+    /// Whether the chat has been left alone long enough to close by itself
+    /// Never while a reply is on its way: it would be lost from sight
+    pub fn idle_close_due(&self, now: Instant) -> bool {
+        self.idle_close.is_some_and(|idle| {
+            !self.is_sending() && now.duration_since(self.last_interaction) >= idle
+        })
+    }
 
-    // Apply auto-scroll if requested (before building lines)
-    if state.auto_scroll {
-        state.scroll_to_bottom(visible_height);
-        state.auto_scroll = false;
+    /// Move the cursor one character left
+    pub fn move_cursor_left(&mut self) {
+        if let Some(cluster) = self.input[..self.input_cursor].graphemes(true).next_back() {
+            self.input_cursor -= cluster.len();
+        }
     }
 
-    // Get current spinner frame
-    let current_spinner_frame = state.spinner_frame;
+    /// Move the cursor one character right
+    pub fn move_cursor_right(&mut self) {
+        if let Some(cluster) = self.input[self.input_cursor..].graphemes(true).next() {
+            self.input_cursor += cluster.len();
+        }
+    }
 
-    // Build a single text with all messages (line by line)
-    let mut lines: Vec<Line> = Vec::new();
+    /// Tab completes the name of a slash command when it's unambiguous
+    pub fn complete_command(&mut self) {
+        if self.input.contains(char::is_whitespace) {
+            return;
+        }
+        if let Some(prefix) = self.input.strip_prefix('/')
+            && let Some(name) = ChatCommand::complete(prefix)
+        {
+            self.input = format!("/{} ", name);
+            self.input_cursor = self.input.len();
+            self.last_input_change = Some(Instant::now());
+        }
+    }
 
-    for msg in &state.messages {
-        let time = msg.timestamp.format("%H:%M:%S");
-        let (prefix, style) = match msg.role {
-            MessageRole::User => (
-                "🧑 You",
-                Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
-            ),
-            MessageRole::Assistant => (
-                "🤖 Petoncle",
-                Style::default().fg(Color::Green).add_modifier(Modifier::BOLD),
-            ),
-        };
+    /// Capture status for the chat title, empty without a capture
+    fn capture_indicator(&self) -> &'static str {
+        match self.command_capture.as_ref().map(|c| c.lock()) {
+            Some(Ok(capture)) => capture::recording_indicator(capture.is_recording()),
+            _ => "",
+        }
+    }
 
-        // Build agent badge if available
-        let agent_badge = if let Some(ref agent) = msg.agent {
-            let (emoji, _) = match agent.as_str() {
-                "toolsmith" => ("🛠️", Color::Yellow),
-                "researcher" => ("🔍", Color::Blue),
-                "scribe" => ("📝", Color::Magenta),
-                "general" => ("🧠", Color::Cyan),
-                "error" => ("⚠️", Color::Red),
-                _ => ("❓", Color::White),
-            };
-            format!(" {} {}", emoji, agent)
+    /// Context marker for the chat title: none for the next message, or `/here`
+    fn context_indicator(&self) -> &'static str {
+        if self.no_context_next {
+            " 🚫 sans contexte"
+        } else if self.here_only {
+            " 📁 ici"
         } else {
-            String::new()
-        };
-
-        // Add header with agent badge
-        let mut header_spans = vec![
-            Span::styled(prefix, style),
-            Span::raw(format!(" • {}", time)),
-        ];
-        if !agent_badge.is_empty() {
-            if let Some(ref agent) = msg.agent {
-                let (_, color) = match agent.as_str() {
-                    "toolsmith" => ("🛠️", Color::Yellow),
-                    "researcher" => ("🔍", Color::Blue),
-                    "scribe" => ("📝", Color::Magenta),
-                    "general" => ("🧠", Color::Cyan),
-                    "error" => ("⚠️", Color::Red),
-                    _ => ("❓", Color::White),
-                };
-                header_spans.push(Span::styled(
-                    agent_badge,
-                    Style::default().fg(color).add_modifier(Modifier::BOLD),
-                ));
-            }
+            ""
+        }
+    }
+
+    /// Title of the messages pane fitting `width` columns (borders included): the key hints when
+    /// there's room, only the status without them, or just the name on a narrow terminal
+    fn messages_title(&self, width: u16) -> String {
+        let status = format!(
+            "💬 Petoncle Chat [{}] {}{}{}",
+            self.active_backend_name(),
+            self.capture_indicator(),
+            self.context_indicator(),
+            self.pending_output_indicator()
+        );
+        let available = usize::from(width.saturating_sub(2));
+        [format!("{} ({})", status, CHAT_KEY_HINTS), format!("{} (ESC quitter)", status)]
+            .into_iter()
+            .find(|title| Line::raw(title.as_str()).width() <= available)
+            .unwrap_or_else(|| "💬 Chat".to_string())
+    }
+
+    /// Badge telling the shell printed something since the chat opened
+    fn pending_output_indicator(&self) -> String {
+        match self.output_gate.as_ref().map(|gate| gate.pending_bytes()) {
+            Some(bytes) if bytes > 0 => format!(" {}", pending::pending_badge(bytes)),
+            _ => String::new(),
         }
-        lines.push(Line::from(header_spans));
-        lines.push(Line::from(""));
+    }
 
-        // Add content with spinner animation if loading
-        match msg.state {
-            MessageState::Loading => {
-                let spinner = SPINNER_FRAMES[current_spinner_frame];
-                lines.push(Line::from(vec![
-                    Span::styled(
-                        spinner,
-                        Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
-                    ),
-                    Span::raw(" "),
-                    Span::styled(
-                        &msg.content,
-                        Style::default().fg(Color::Yellow),
-                    ),
-                    Span::raw("..."),
-                ]));
+    /// Name of the backend new messages are sent to
+    pub fn active_backend_name(&self) -> &str {
+        if self.mock {
+            return "mock";
+        }
+        &self.active_backend().name
+    }
+
+    /// Backend active in the chat, readable while the chat holds its state (control socket)
+    pub fn shared_backend(&self) -> Arc<Mutex<BackendConfig>> {
+        Arc::clone(&self.shared_backend)
+    }
+
+    fn activate_backend(&mut self, index: usize) {
+        self.active_backend = index;
+        self.transport = transport::for_backend(self.active_backend(), self.mock);
+        if let Ok(mut shared) = self.shared_backend.lock() {
+            *shared = self.backends[index].clone();
+        }
+    }
+
+    /// Switch to the next configured backend
+    pub fn cycle_backend(&mut self) {
+        self.activate_backend((self.active_backend + 1) % self.backends.len());
+        self.add_system_message(format!("🔀 Backend actif: {}", self.active_backend_name()));
+    }
+
+    /// Switch to the backend with the given name
+    pub fn select_backend(&mut self, name: &str) {
+        match self.backends.iter().position(|b| b.name == name) {
+            Some(index) => {
+                self.activate_backend(index);
+                self.add_system_message(format!("🔀 Backend actif: {}", name));
             }
-            MessageState::Ready => {
-                // Add content (no truncation, full message)
-                for line in msg.content.lines() {
-                    lines.push(Line::from(line.to_string()));
-                }
+            None => {
+                let list = self.backend_list();
+                self.add_system_message(format!("❌ Backend inconnu: {}\n\n{}", name, list));
             }
         }
-
-        lines.push(Line::from(""));
-        lines.push(Line::from("───────────────────────────────────"));
-        lines.push(Line::from(""));
     }
 
-    // Create Paragraph with scroll
-    let messages_paragraph = Paragraph::new(lines)
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .border_style(Style::default().fg(Color::Cyan))
-                .title("💬 Petoncle Chat (↑↓ scroller | Home/End haut/bas | ESC quitter)")
-                .title_alignment(Alignment::Center),
-        )
-        .style(Style::default().bg(Color::Black))
-        .wrap(Wrap { trim: false })
-        .scroll((state.scroll_offset, 0));
+    /// Human-readable list of backends, the active one marked
+    fn backend_list(&self) -> String {
+        let mut list = String::from("Backends disponibles:");
+        for (i, backend) in self.backends.iter().enumerate() {
+            let marker = if i == self.active_backend { "➤" } else { " " };
+            list.push_str(&format!("\n{} {} ({})", marker, backend.name, backend.address));
+        }
+        list
+    }
 
-    frame.render_widget(messages_paragraph, chunks[0]);
+    /// Add a local notice (command feedback), never sent to the agent
+    pub fn add_system_message(&mut self, content: String) {
+        self.add_assistant_message(content, Some("system".to_string()));
+    }
 
-    // Render input box
-    let input_text = format!("➤ {}", state.input);
-    let input = Paragraph::new(input_text)
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .border_style(Style::default().fg(Color::Magenta))
-                .title("Votre message (Enter pour envoyer)"),
-        )
-        .style(Style::default().bg(Color::Black).fg(Color::White))
-        .wrap(Wrap { trim: false });
+    /// Remember the session log file for /logs
+    pub fn set_log_file(&mut self, path: PathBuf) {
+        self.log_file = Some(path);
+    }
 
-    frame.render_widget(input, chunks[1]);
-}
+    /// Remember the session ID, to tie exports to the session log
+    pub fn set_session_id(&mut self, session_id: String) {
+        self.session_id = Some(session_id);
+    }
 
-/// Result of the chat loop
-pub enum ChatLoopResult {
-    Closed,
-}
+    /// Follow the shell output held while the chat is open, for the pending output badge
+    pub fn set_output_gate(&mut self, gate: Arc<OutputGate>) {
+        self.output_gate = Some(gate);
+    }
 
-/// Run the chat overlay loop
-pub fn run_chat_loop(
-    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
-    state: &mut ChatState,
-) -> Result<ChatLoopResult> {
-    loop {
-        // Check if response is ready
-        state.check_response();
+    /// Give the chat access to the shell input, needed to perform agent actions
+    pub fn set_pty_writer(&mut self, writer: PtyWriter) {
+        self.pty_writer = Some(writer);
+    }
 
-        // Update spinner animation if waiting for response
-        if state.response_receiver.is_some() {
-            state.update_spinner();
-        }
+    /// Give the chat access to the captured shell commands
+    pub fn set_command_capture(&mut self, capture: Arc<Mutex<CommandCapture>>) {
+        self.command_capture = Some(capture);
+    }
 
-        // Render the UI
-        terminal.draw(|frame| {
-            let area = frame.area();
+    /// Show the captured output of a command (1-based index, default: last)
+    pub fn show_output(&mut self, index: Option<usize>) {
+        let message = self
+            .with_captured(index, |index, cmd| {
+                let output = cmd.clean_output();
+                let output = output.trim_end();
+                let note = cmd.note.as_ref().map(|note| format!("\n🏷 {}", note)).unwrap_or_default();
+                let written = attach::redirect_target(&cmd.command)
+                    .map(|file| {
+                        format!("\n\n💡 Sortie écrite dans {}: /attach-tail {} pour en joindre la fin", file, index)
+                    })
+                    .unwrap_or_default();
+                format!(
+                    "📤 Commande #{} {}\n$ {}{}\n\n{}{}",
+                    index,
+                    cmd.status_label(),
+                    cmd.command,
+                    note,
+                    if output.is_empty() { "(aucune sortie capturée)" } else { output },
+                    written
+                )
+            })
+            .unwrap_or_else(|e| e);
+        self.add_system_message(message);
+    }
 
-            // Fill background (simulate the terminal still being visible)
-            let bg = Block::default().style(Style::default().bg(Color::Black));
-            frame.render_widget(bg, area);
+    /// Line diff between the outputs of two captured commands (1-based indices)
+    pub fn show_diff(&mut self, first: usize, second: usize) {
+        let output = |_, cmd: &CapturedCommand| (cmd.command.clone(), cmd.clean_output());
+        let message = self
+            .with_captured(Some(first), output)
+            .and_then(|old| Ok((old, self.with_captured(Some(second), output)?)))
+            .map(|(old, new)| format_output_diff((first, &old.0, &old.1), (second, &new.0, &new.1)))
+            .unwrap_or_else(|e| e);
+        self.add_system_message(message);
+    }
 
-            render_chat_ui(frame, state, area);
-        })?;
+    /// `/here`: send the commands captured in the shell's current directory (OSC 7) with each
+    /// message, so unrelated projects of the session stay out of the context
+    pub fn toggle_here(&mut self) {
+        self.here_only = !self.here_only;
+        let dir = match self.command_capture.as_ref().map(|c| c.lock()) {
+            Some(Ok(capture)) => capture.current_dir().map(Path::to_path_buf),
+            _ => None,
+        };
+        self.status = Some(match (self.here_only, dir) {
+            (false, _) => "Contexte: les commandes ne sont plus jointes aux messages".to_string(),
+            (true, Some(dir)) => format!("📁 Contexte: commandes de {} (/here pour arrêter)", dir.display()),
+            (true, None) => "📁 Contexte: répertoire du shell encore inconnu, aucune commande jointe".to_string(),
+        });
+    }
 
-        // Use shorter poll timeout when waiting for response (for smoother animation)
-        let poll_timeout = if state.response_receiver.is_some() {
-            Duration::from_millis(50)
+    /// Ctrl+N / `/nocontext`: send the next message without any context, or attach it again
+    pub fn toggle_no_context(&mut self) {
+        self.no_context_next = !self.no_context_next;
+        self.status = Some(if self.no_context_next {
+            "🚫 Prochain message envoyé sans contexte (Ctrl+N pour annuler)".to_string()
         } else {
-            Duration::from_millis(100)
-        };
+            "Contexte de nouveau joint au prochain message".to_string()
+        });
+    }
 
-        // Handle input events
+    /// Commands captured in the shell's current directory, as agent context
+    fn here_context(&self) -> Vec<String> {
+        let Some(Ok(capture)) = self.command_capture.as_ref().map(|c| c.lock()) else {
+            return Vec::new();
+        };
+        match capture.current_dir() {
+            Some(dir) => capture.recent_context(
+                ContextStrategy::CurrentDirOnly,
+                dir,
+                self.context_commands,
+                self.context_budget,
+            ),
+            None => Vec::new(),
+        }
+    }
+
+    /// Open the timeline of the captured commands, scrolled to the latest ones
+    pub fn show_timeline(&mut self) {
+        let lines = match self.command_capture.as_ref().map(|c| c.lock()) {
+            Some(Ok(capture)) if capture.is_empty() => Err("❌ Aucune commande capturée pour l'instant"),
+            Some(Ok(capture)) => Ok((
+                timeline_lines((0..capture.len()).filter_map(|i| capture.get(i)), Local::now(), &self.theme, None).0,
+                capture.len(),
+            )),
+            _ => Err("❌ Capture des commandes indisponible"),
+        };
+        match lines {
+            Ok((lines, commands)) => {
+                let scroll = lines.len().saturating_sub(self.last_visible_height as usize) as u16;
+                self.mode = ChatMode::Timeline { lines, commands, scroll, focus: None };
+            }
+            Err(e) => self.status = Some(e.to_string()),
+        }
+    }
+
+    /// Timeline keys besides scrolling: `f` toggles focus mode, where Tab/Shift+Tab select a
+    /// command and Enter folds or unfolds it; false for any other key
+    /// The timeline is rebuilt and scrolled to keep the selected command in view, as heights change
+    pub fn handle_timeline_key(&mut self, key: KeyCode) -> bool {
+        if !matches!(key, KeyCode::Char('f') | KeyCode::Tab | KeyCode::BackTab | KeyCode::Enter) {
+            return false;
+        }
+        let Some(capture) = self.command_capture.clone() else {
+            return false;
+        };
+        let Ok(capture) = capture.lock() else {
+            return false;
+        };
+        let visible = self.last_visible_height as usize;
+        let ChatMode::Timeline { ref mut lines, ref mut commands, ref mut scroll, ref mut focus } = self.mode else {
+            return false;
+        };
+
+        // The last command stays current until the next one starts, it's listed too
+        let captured = || (0..capture.len()).filter_map(|i| capture.get(i));
+        if key == KeyCode::Char('f') {
+            *focus = if focus.is_some() { None } else { Some(TimelineFocus::new(captured())) };
+        } else {
+            let Some(focus) = focus.as_mut() else {
+                return false;
+            };
+            let last = capture.len().saturating_sub(1);
+            match key {
+                KeyCode::Tab => focus.selected = (focus.selected + 1).min(last),
+                KeyCode::BackTab => focus.selected = focus.selected.saturating_sub(1),
+                _ => {
+                    // Commands captured since focus mode started are folded
+                    focus.expanded.resize(capture.len(), false);
+                    if let Some(expanded) = focus.expanded.get_mut(focus.selected) {
+                        *expanded = !*expanded;
+                    }
+                }
+            }
+        }
+
+        let (new_lines, starts) = timeline_lines(captured(), Local::now(), &self.theme, focus.as_ref());
+        *lines = new_lines;
+        *commands = capture.len();
+        let mut top = *scroll as usize;
+        if let Some(start) = focus.as_ref().and_then(|focus| starts.get(focus.selected)) {
+            let end = starts
+                .iter()
+                .find(|&&next| next > *start)
+                .copied()
+                .unwrap_or(lines.len());
+            if *start < top {
+                top = *start;
+            } else if end > top + visible {
+                top = (end - visible).min(*start);
+            }
+        }
+        *scroll = top.min(lines.len().saturating_sub(visible)) as u16;
+        true
+    }
+
+    /// Session overview from the captured commands
+    pub fn show_stats(&mut self) {
+        let message = match self.command_capture.as_ref().map(|c| c.lock()) {
+            Some(Ok(capture)) => format_stats(&capture.stats()),
+            _ => "❌ Capture des commandes indisponible".to_string(),
+        };
+        self.add_system_message(message);
+    }
+
+    /// Ask the agent to summarize one command's output (1-based index, default: last)
+    /// Output past the context budget is cut from the start, and the prompt says so
+    pub fn summarize_command(&mut self, index: Option<usize>) {
+        if self.is_sending() {
+            self.status = Some("Une réponse est déjà en attente".to_string());
+            return;
+        }
+        if self.rate_limited(Instant::now()) {
+            return;
+        }
+
+        let budget = self.context_budget;
+        let prompt = self.with_captured(index, |index, cmd| {
+            let output = cmd.clean_output();
+            let output = output.trim_end();
+            if output.is_empty() {
+                return Err(format!("❌ La commande #{} n'a produit aucune sortie", index));
+            }
+
+            let mut notes = String::new();
+            if !cmd.is_complete() {
+                notes.push_str("(Commande toujours en cours, sortie partielle)\n");
+            }
+            if output.len() > budget {
+                let _ = writeln!(
+                    notes,
+                    "(Sortie tronquée: seuls les {} derniers octets sur {} sont inclus)",
+                    budget,
+                    output.len()
+                );
+            }
+            Ok((
+                index,
+                format!(
+                    "Résume la sortie de cette commande: ce qu'elle a fait, les erreurs éventuelles \
+                     et ce qu'il faudrait faire ensuite.\n\n$ {} ({})\n{}\n{}",
+                    cmd.command,
+                    cmd.status_label(),
+                    notes,
+                    capture::truncate_start(output, budget)
+                ),
+            ))
+        });
+
+        match prompt.and_then(|result| result) {
+            Ok((index, prompt)) => {
+                self.add_user_message(format!("📋 Résumé de la commande #{}", index));
+                self.start_generate_response(prompt);
+            }
+            Err(e) => self.add_system_message(e),
+        }
+    }
+
+    /// Run `f` on the captured command at a 1-based index (default: the last one)
+    /// Errors are messages ready to show in the chat
+    fn with_captured<T>(
+        &self,
+        index: Option<usize>,
+        f: impl FnOnce(usize, &CapturedCommand) -> T,
+    ) -> Result<T, String> {
+        let capture = self
+            .command_capture
+            .as_ref()
+            .and_then(|c| c.lock().ok())
+            .ok_or_else(|| "❌ Capture des commandes indisponible".to_string())?;
+        if capture.is_empty() {
+            return Err("❌ Aucune commande capturée pour l'instant".to_string());
+        }
+
+        let index = index.unwrap_or(capture.len());
+        let cmd = index
+            .checked_sub(1)
+            .and_then(|i| capture.get(i))
+            .ok_or_else(|| format!("❌ Commande #{} introuvable (1 à {})", index, capture.len()))?;
+        Ok(f(index, cmd))
+    }
+
+    /// Evictions shift the numbers of the commands left: say so before a number is used again
+    fn warn_renumbered(&mut self) {
+        let evicted = match self.command_capture.as_ref().map(|c| c.lock()) {
+            Some(Ok(capture)) => capture.evicted(),
+            _ => return,
+        };
+        if evicted > self.evicted_seen {
+            self.add_system_message(format!(
+                "⚠️ {} ancienne(s) commande(s) oubliée(s) (max_commands): les numéros ont été décalés d'autant",
+                evicted - self.evicted_seen
+            ));
+            self.evicted_seen = evicted;
+        }
+    }
+
+    /// Pin or unpin a captured command (1-based index) so it stays in memory and in the context
+    pub fn toggle_pin(&mut self, index: usize) {
+        let message = match self.command_capture.as_ref().map(|c| c.lock()) {
+            Some(Ok(mut capture)) => match capture.toggle_pin(index - 1) {
+                Some(true) => format!("📌 Commande #{} épinglée", index),
+                Some(false) => format!("Commande #{} désépinglée", index),
+                None => format!("❌ Commande #{} introuvable (1 à {})", index, capture.len()),
+            },
+            _ => "❌ Capture des commandes indisponible".to_string(),
+        };
+        self.add_system_message(message);
+    }
+
+    /// Set or remove (None) the note of a captured command (1-based index)
+    pub fn set_note(&mut self, index: usize, note: Option<String>) {
+        let message = match self.command_capture.as_ref().map(|c| c.lock()) {
+            Some(Ok(mut capture)) => {
+                let removed = note.is_none();
+                match capture.set_note(index - 1, note) {
+                    true if removed => format!("Note de la commande #{} supprimée", index),
+                    true => format!("🏷 Note ajoutée à la commande #{}", index),
+                    false => format!("❌ Commande #{} introuvable (1 à {})", index, capture.len()),
+                }
+            }
+            _ => "❌ Capture des commandes indisponible".to_string(),
+        };
+        self.add_system_message(message);
+    }
+
+    /// Perform a confirmed agent action in the shell
+    pub fn run_action(&mut self, action: &AgentAction) -> Result<()> {
+        let writer = self
+            .pty_writer
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Shell non disponible"))?;
+        action.execute(writer)
+    }
+
+    /// Write the captured commands to an executable script, `all` keeps the failed ones
+    pub fn export_script(&mut self, path: Option<String>, all: bool) {
+        let path = attach::expand_home(path.as_deref().unwrap_or(&self.export.script_path));
+        let skip_failed = self.export.skip_failed && !all;
+
+        let script = match self.command_capture.as_ref().map(|c| c.lock()) {
+            Some(Ok(capture)) if capture.is_empty() => Err("❌ Aucune commande capturée pour l'instant".to_string()),
+            Some(Ok(capture)) => Ok(export::session_script(
+                (0..capture.len()).filter_map(|i| capture.get(i)),
+                skip_failed,
+                self.session_id.as_deref(),
+                Local::now(),
+            )),
+            _ => Err("❌ Capture des commandes indisponible".to_string()),
+        };
+
+        let message = script.and_then(|(script, count)| {
+            export::write_script(&path, &script)
+                .map(|()| format!("📜 Script exporté: {} ({} commande(s))", path.display(), count))
+                .map_err(|e| format!("❌ Export impossible: {:#}", e))
+        });
+        self.add_system_message(message.unwrap_or_else(|e| e));
+    }
+
+    /// Copy a reproducible snippet of a captured command to the clipboard, or write it to `path`
+    pub fn export_snippet(&mut self, index: Option<usize>, path: Option<String>) {
+        let snippet = self.with_captured(index, |index, cmd| {
+            (index, export::command_snippet(cmd, cmd.working_dir.is_dir()), cmd.is_complete())
+        });
+        let message = snippet.and_then(|(index, snippet, complete)| {
+            let partial = if complete { "" } else { ", commande en cours: sortie partielle" };
+            match path {
+                Some(path) => {
+                    let path = attach::expand_home(&path);
+                    std::fs::write(&path, &snippet)
+                        .map(|()| format!("🧩 Extrait de la commande #{} écrit dans {}{}", index, path.display(), partial))
+                        .map_err(|e| format!("❌ Écriture de {} impossible: {}", path.display(), e))
+                }
+                None => clipboard::copy(&snippet)
+                    .map(|()| format!("🧩 Extrait de la commande #{} copié{}", index, partial))
+                    .map_err(|e| format!("❌ Copie impossible: {}", e)),
+            }
+        });
+        self.add_system_message(message.unwrap_or_else(|e| e));
+    }
+
+    /// Open the command palette
+    pub fn open_palette(&mut self, query: Option<String>) {
+        let query = query.unwrap_or_default();
+        self.mode = ChatMode::Palette {
+            entries: self.palette_entries(&query),
+            query,
+            selected: 0,
+        };
+    }
+
+    /// Captured commands matching the palette query, best first
+    pub fn palette_entries(&self, query: &str) -> Vec<PaletteEntry> {
+        let Some(Ok(capture)) = self.command_capture.as_ref().map(|c| c.lock()) else {
+            return Vec::new();
+        };
+        capture
+            .fuzzy_search(query)
+            .into_iter()
+            .filter_map(|(_, cmd)| {
+                let index = capture.index_of(cmd)? + 1;
+                let positions = fuzzy::fuzzy_match(query, &cmd.command)
+                    .map(|(_, positions)| positions)
+                    .unwrap_or_default();
+                Some(PaletteEntry {
+                    index,
+                    label: cmd.status_label(),
+                    command: cmd.command.clone(),
+                    positions,
+                })
+            })
+            .collect()
+    }
+
+    /// Open the read-only view of the session log
+    pub fn show_logs(&mut self) {
+        let Some(ref path) = self.log_file else {
+            self.status = Some("❌ Aucun fichier de log pour cette session".to_string());
+            return;
+        };
+
+        match tail_lines(path, LOG_TAIL_LINES) {
+            Ok(lines) => {
+                // Start at the bottom, where the latest lines are
+                let scroll = lines.len().saturating_sub(self.last_visible_height as usize) as u16;
+                self.mode = ChatMode::Logs { lines, scroll };
+            }
+            Err(e) => {
+                self.status = Some(format!("❌ Impossible de lire {}: {}", path.display(), e));
+            }
+        }
+    }
+
+    /// Attach a file to the next message
+    pub fn attach_file(&mut self, path: &str) {
+        match attach::read_attachment(path) {
+            Ok(content) => {
+                self.pending_attachments.push(content);
+                self.status = Some(format!(
+                    "📎 {} joint au prochain message ({} fichier(s))",
+                    path,
+                    self.pending_attachments.len()
+                ));
+            }
+            Err(e) => {
+                self.status = Some(format!("❌ {}", e));
+            }
+        }
+    }
+
+    /// Attach the end of the file a captured command wrote to (1-based index)
+    pub fn attach_command_file(&mut self, index: usize, lines: Option<usize>) {
+        let path = self.with_captured(Some(index), |index, cmd| {
+            attach::redirect_target(&cmd.command)
+                .map(|file| cmd.working_dir.join(attach::expand_home(&file)))
+                .ok_or_else(|| format!("❌ La commande #{} n'écrit dans aucun fichier", index))
+        });
+        let path = match path.and_then(|path| path) {
+            Ok(path) => path,
+            Err(e) => {
+                self.status = Some(e);
+                return;
+            }
+        };
+
+        match attach::read_tail(&path, lines.unwrap_or(attach::DEFAULT_TAIL_LINES)) {
+            Ok(content) => {
+                self.pending_attachments.push(content);
+                self.status = Some(format!(
+                    "📎 Fin de {} jointe au prochain message ({} fichier(s))",
+                    path.display(),
+                    self.pending_attachments.len()
+                ));
+            }
+            Err(e) => {
+                self.status = Some(format!("❌ {}", e));
+            }
+        }
+    }
+
+    /// Run a slash command typed in the input box
+    pub fn execute_command(&mut self, command: ChatCommand) {
+        if command.uses_numbers() {
+            self.warn_renumbered();
+        }
+        match command {
+            ChatCommand::Attach(path) => self.attach_file(&path),
+            ChatCommand::Logs => self.show_logs(),
+            ChatCommand::Stats => self.show_stats(),
+            ChatCommand::Timeline => self.show_timeline(),
+            ChatCommand::Here => self.toggle_here(),
+            ChatCommand::NoContext(None) => self.toggle_no_context(),
+            ChatCommand::NoContext(Some(text)) => {
+                self.no_context_next = true;
+                self.input = text;
+                self.input_cursor = self.input.len();
+                self.submit_input();
+            }
+            ChatCommand::Diff(first, second) => self.show_diff(first, second),
+            ChatCommand::AttachTail(index, lines) => self.attach_command_file(index, lines),
+            ChatCommand::Commands(query) => self.open_palette(query),
+            ChatCommand::ExportScript { path, all } => self.export_script(path, all),
+            ChatCommand::Snippet { index, path } => self.export_snippet(index, path),
+            ChatCommand::Output(index) => self.show_output(index),
+            ChatCommand::Pin(index) => self.toggle_pin(index),
+            ChatCommand::Note(index, note) => self.set_note(index, note),
+            ChatCommand::Summarize(index) => self.summarize_command(index),
+            ChatCommand::Backend(Some(name)) => self.select_backend(&name),
+            ChatCommand::Backend(None) => {
+                let list = self.backend_list();
+                self.add_system_message(list);
+            }
+        }
+    }
+
+    /// Backend new messages are sent to
+    pub fn active_backend(&self) -> &BackendConfig {
+        &self.backends[self.active_backend]
+    }
+
+    /// Transport of the active backend, for requests made outside the chat
+    pub fn transport(&self) -> Arc<dyn ChatTransport> {
+        Arc::clone(&self.transport)
+    }
+
+    /// Start generating AI response asynchronously (non-blocking)
+    pub fn start_generate_response(&mut self, user_input: String) {
+        // Sent bare: attachments wait for the next message, nothing else is added
+        let bare = std::mem::take(&mut self.no_context_next);
+        let mut context = Vec::new();
+        if !bare {
+            // Attachments are consumed by this message
+            context = std::mem::take(&mut self.pending_attachments);
+            if self.here_only {
+                context.splice(0..0, self.here_context());
+            }
+        }
+        self.status = None;
+
+        // Answering a question: remind the agent of the exchange instead of starting a new topic
+        match self.clarification.take() {
+            Some(clarification) => {
+                if !bare {
+                    context.insert(0, clarification.to_context_entry());
+                }
+                self.turn_topic = Some(clarification.original);
+                for msg in &mut self.messages {
+                    if matches!(msg.state, MessageState::Question) {
+                        msg.state = MessageState::Ready;
+                    }
+                }
+            }
+            None => self.turn_topic = Some(user_input.clone()),
+        }
+
+        // Nothing leaves the machine before the user has seen it
+        if self.confirm_context && !context.is_empty() {
+            self.mode = ChatMode::ConfirmContext {
+                text: user_input,
+                context,
+                expanded: false,
+                scroll: 0,
+            };
+            return;
+        }
+        self.send_request(user_input, context);
+    }
+
+    /// Ask for confirmation of an agent action, once the view in front of the chat is closed
+    /// Never replaces an open view: the user would lose it without having seen the action
+    fn offer_action(&mut self, action: AgentAction) {
+        if matches!(self.mode, ChatMode::Chat) {
+            self.mode = ChatMode::ConfirmAction(action);
+        } else {
+            self.queued_action = Some(action);
+            self.status = Some("Action de l'agent en attente de confirmation (ESC pour y revenir)".to_string());
+        }
+    }
+
+    /// Back to the conversation from a view, or to the action that arrived meanwhile
+    pub fn close_view(&mut self) {
+        self.mode = match self.queued_action.take() {
+            Some(action) => ChatMode::ConfirmAction(action),
+            None => ChatMode::Chat,
+        };
+    }
+
+    /// Answer the context confirmation: send with or without it, cancel, or browse the preview
+    pub fn handle_context_confirmation(&mut self, key: KeyCode, visible_height: u16) {
+        match key {
+            KeyCode::Char('o') | KeyCode::Char('y') | KeyCode::Enter => self.send_confirmed(true),
+            KeyCode::Char('s') => self.send_confirmed(false),
+            KeyCode::Char('n') | KeyCode::Esc => {
+                self.mode = ChatMode::Chat;
+                self.status = Some("Envoi annulé, rien n'a été envoyé".to_string());
+            }
+            _ => {
+                let ChatMode::ConfirmContext { ref context, ref mut expanded, ref mut scroll, .. } = self.mode
+                else {
+                    return;
+                };
+                match key {
+                    KeyCode::Tab => {
+                        *expanded = !*expanded;
+                        *scroll = 0;
+                    }
+                    KeyCode::Up => *scroll = scroll.saturating_sub(1),
+                    KeyCode::Down => {
+                        let total = context_preview_lines(context, *expanded, &self.theme).len();
+                        let max_scroll = total.saturating_sub(visible_height as usize) as u16;
+                        *scroll = (*scroll + 1).min(max_scroll);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Send the message held for confirmation, with its context or without any
+    pub fn send_confirmed(&mut self, with_context: bool) {
+        let mode = std::mem::replace(&mut self.mode, ChatMode::Chat);
+        if let ChatMode::ConfirmContext { text, context, .. } = mode {
+            let context = if with_context { context } else { Vec::new() };
+            self.send_request(text, context);
+        }
+    }
+
+    /// Ask for another answer to the last reply, which is replaced by the new one
+    pub fn regenerate_last(&mut self) {
+        if self.is_sending() {
+            self.status = Some("Une réponse est déjà en attente".to_string());
+            return;
+        }
+        let prompt = match self.messages.last() {
+            Some(msg) if matches!(msg.role, MessageRole::Assistant) => msg.prompt.clone(),
+            _ => None,
+        };
+        let Some(prompt) = prompt else {
+            self.status = Some("Rien à régénérer: la dernière réponse ne vient pas de l'agent".to_string());
+            return;
+        };
+        if self.rate_limited(Instant::now()) {
+            return;
+        }
+
+        // The replaced reply may have been a question, its topic is still the current one
+        if let Some(clarification) = self.clarification.take() {
+            self.turn_topic = Some(clarification.original);
+        }
+        self.messages.pop();
+        self.status = None;
+        self.send_request(prompt.text, prompt.context);
+    }
+
+    /// Send a prompt on a worker thread, the reply arrives through `check_response`
+    fn send_request(&mut self, user_input: String, context: Vec<String>) {
+        self.recent_requests.push_back(Instant::now());
+
+        // Create channel for async communication
+        let (tx, rx): (Sender<Result<AgentReply>>, Receiver<Result<AgentReply>>) = mpsc::channel();
+        let (progress_tx, progress_rx) = mpsc::channel::<Progress>();
+
+        // The request keeps the backend active at send time, even if the user switches meanwhile
+        let transport = Arc::clone(&self.transport);
+
+        let sent = SentPrompt {
+            text: user_input.clone(),
+            context: context.clone(),
+        };
+
+        // One span per turn, its fields end up in the log file for latency and routing analysis
+        let span = info_span!(
+            "chat_turn",
+            backend = %self.active_backend_name(),
+            prompt_len = user_input.len(),
+            context_items = context.len(),
+            context_bytes = context.iter().map(String::len).sum::<usize>(),
+            agent = field::Empty,
+            latency_ms = field::Empty,
+            outcome = field::Empty,
+        );
+
+        // Spawn thread to handle gRPC call
+        thread::spawn(move || {
+            let _entered = span.enter();
+            let started = Instant::now();
+            let result = transport.send_reporting(user_input, context, &mut |progress| {
+                progress_tx.send(progress).ok();
+            });
+            span.record("latency_ms", started.elapsed().as_millis() as u64);
+
+            let succeeded = result.is_ok();
+            let response = match result {
+                Ok(resp) => Ok(AgentReply {
+                    action: resp.action.as_ref().and_then(AgentAction::from_proto),
+                    message: resp.message,
+                    agent: resp.agent,
+                    awaiting_clarification: resp.awaiting_clarification,
+                }),
+                Err(e) if e.downcast_ref::<MessageTooLarge>().is_some() => Ok(AgentReply {
+                    message: format!(
+                        "⚠️ {}\n\n\
+                         💡 Réduisez le contexte envoyé: moins de fichiers joints, \
+                         ou un `context_budget` plus petit dans la configuration",
+                        e
+                    ),
+                    agent: "error".to_string(),
+                    action: None,
+                    awaiting_clarification: false,
+                }),
+                Err(e) => Ok(AgentReply {
+                    message: format!(
+                        "⚠️ Service IA non disponible\n\n\
+                         Erreur: {}\n\n\
+                         💡 Assurez-vous que le service Python est démarré:\n\
+                         cd python && python agent_service.py",
+                        e
+                    ),
+                    agent: "error".to_string(),
+                    action: None,
+                    awaiting_clarification: false,
+                }),
+            };
+
+            if let Ok(ref reply) = response {
+                span.record("agent", reply.agent.as_str());
+            }
+
+            // Send result back, the chat may have given up on it meanwhile
+            let delivered = tx.send(response).is_ok();
+            let outcome = match (delivered, succeeded) {
+                (false, _) => "cancel",
+                (true, true) => "success",
+                (true, false) => "error",
+            };
+            span.record("outcome", outcome);
+            info!("Chat turn finished");
+        });
+
+        // Store receiver: from here on a repeated Enter is ignored until the reply is in
+        self.response_receiver = Some(rx);
+        self.progress_receiver = Some(progress_rx);
+
+        // Add loading message, it keeps the prompt once the reply replaces it
+        self.add_loading_message();
+        if let Some(last) = self.messages.last_mut() {
+            last.prompt = Some(sent);
+        }
+    }
+
+    /// Check if response is ready and update message
+    pub fn check_response(&mut self) -> bool {
+        if let Some(ref receiver) = self.response_receiver {
+            let mut retrying = None;
+            if let Some(ref progress) = self.progress_receiver {
+                for Progress::Retrying { attempt, attempts, .. } in progress.try_iter() {
+                    retrying = Some((attempt, attempts));
+                }
+            }
+
+            let result = match receiver.try_recv() {
+                Ok(result) => Some(result),
+                Err(TryRecvError::Empty) => None,
+                // The worker went away without answering (panic): don't leave the spinner running
+                Err(TryRecvError::Disconnected) => Some(Err(anyhow::anyhow!("le traitement de la réponse s'est arrêté"))),
+            };
+
+            if let Some((attempt, attempts)) = retrying {
+                self.show_retry(attempt, attempts);
+            }
+            if let Some(result) = result {
+                // Response received!
+                match result {
+                    // Nothing to show: an explicit placeholder rather than a blank bubble
+                    Ok(reply) if reply.message.trim().is_empty() && reply.action.is_none() => {
+                        warn!("Empty response from agent {}", reply.agent);
+                        self.update_last_message(EMPTY_REPLY_PLACEHOLDER.to_string(), Some(reply.agent));
+                        if let Some(last) = self.messages.last_mut() {
+                            last.state = MessageState::Empty;
+                        }
+                        self.status = Some("Réponse vide — Ctrl+G pour réessayer".to_string());
+                    }
+                    Ok(reply) => {
+                        if reply.awaiting_clarification {
+                            self.clarification = Some(Clarification {
+                                original: self.turn_topic.take().unwrap_or_default(),
+                                question: reply.message.clone(),
+                            });
+                        }
+                        self.update_last_message(reply.message, Some(reply.agent));
+                        if self.clarification.is_some()
+                            && let Some(last) = self.messages.last_mut()
+                        {
+                            last.state = MessageState::Question;
+                        }
+                        if let Some(action) = reply.action {
+                            self.offer_action(action);
+                        }
+                    }
+                    Err(e) => {
+                        self.update_last_message(format!("❌ Error: {}", e), Some("error".to_string()));
+                    }
+                }
+                self.response_receiver = None;
+                self.progress_receiver = None;
+                return true;
+            }
+            return retrying.is_some();
+        }
+        false
+    }
+
+    /// How long until another request may go out, None when it can right away
+    /// Sliding window: each request counts for `RATE_WINDOW` after it was sent
+    fn throttle_delay(&mut self, now: Instant) -> Option<Duration> {
+        let limit = self.max_requests_per_minute.filter(|&limit| limit > 0)? as usize;
+        while self
+            .recent_requests
+            .front()
+            .is_some_and(|&sent| now.duration_since(sent) >= RATE_WINDOW)
+        {
+            self.recent_requests.pop_front();
+        }
+        if self.recent_requests.len() < limit {
+            return None;
+        }
+        let oldest = self.recent_requests[self.recent_requests.len() - limit];
+        Some(RATE_WINDOW - now.duration_since(oldest))
+    }
+
+    /// Refuse a request over the limit, saying when the next one can go; true when refused
+    fn rate_limited(&mut self, now: Instant) -> bool {
+        let Some(delay) = self.throttle_delay(now) else {
+            return false;
+        };
+        warn!("Request refused: {} sent in the last minute", self.recent_requests.len());
+        self.status = Some(format!(
+            "⏳ Trop de requêtes en une minute, réessayez dans {}s",
+            delay.as_secs_f32().ceil()
+        ));
+        true
+    }
+
+    /// Index of the last reply from the agent, whose code blocks get numbered badges
+    fn last_reply_index(&self) -> Option<usize> {
+        self.messages
+            .iter()
+            .rposition(|msg| matches!(msg.role, MessageRole::Assistant) && msg.agent.as_deref() != Some("system"))
+    }
+
+    /// Nth code block (1-based) of the last reply
+    fn reply_code_block(&self, n: usize) -> Option<CodeBlock> {
+        let reply = &self.messages[self.last_reply_index()?];
+        n.checked_sub(1).and_then(|i| reply.code_blocks().into_iter().nth(i))
+    }
+
+    /// Ctrl+Y: the next digit picks the code block to copy
+    pub fn start_copy_block(&mut self) {
+        let count = self
+            .last_reply_index()
+            .map(|i| self.messages[i].code_blocks().len())
+            .unwrap_or(0);
+        match count {
+            0 => self.status = Some("Aucun bloc de code dans la dernière réponse".to_string()),
+            1 => self.copy_code_block(1),
+            _ => {
+                self.mode = ChatMode::CopyBlock;
+                self.status = Some(format!("Copier le bloc n°: 1-{} (ESC annuler)", count.min(9)));
+            }
+        }
+    }
+
+    /// Copy the nth code block of the last reply to the clipboard
+    pub fn copy_code_block(&mut self, n: usize) {
+        self.status = Some(match self.reply_code_block(n) {
+            Some(block) => match clipboard::copy(&block.code) {
+                Ok(()) => format!("Bloc {} copié ({} ligne(s))", markdown::block_badge(n), block.code.lines().count()),
+                Err(e) => format!("❌ Copie impossible: {}", e),
+            },
+            None => format!("Pas de bloc n°{} dans la dernière réponse", n),
+        });
+    }
+
+    /// Whether a request is out: its receiver is set before the worker can answer and dropped
+    /// once the reply is in
+    fn is_sending(&self) -> bool {
+        self.response_receiver.is_some()
+    }
+
+    /// The service is being reached again: say so next to the spinner instead of waiting silently
+    fn show_retry(&mut self, attempt: u32, attempts: u32) {
+        if let Some(last) = self.messages.last_mut()
+            && matches!(last.state, MessageState::Loading)
+        {
+            last.content = format!("Service injoignable, reconnexion (tentative {}/{})", attempt, attempts);
+        }
+    }
+
+    /// Current spinner frame, computed from wall-clock time so the animation
+    /// stays smooth however often the loop happens to render
+    pub fn spinner_frame(&self) -> usize {
+        (self.spinner_start.elapsed().as_millis() / SPINNER_FRAME_MS) as usize % SPINNER_FRAMES.len()
+    }
+}
+
+/// Render the chat overlay UI
+pub fn render_chat_ui(
+    frame: &mut Frame,
+    state: &mut ChatState,
+    area: Rect,
+) {
+    // Create a centered popup area (80% width, 70% height)
+    let popup_area = centered_rect(80, 70, area);
+
+    // Split into messages and input sections
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![
+            Constraint::Min(0),      // Messages
+            Constraint::Length(3),   // Input box
+        ])
+        .split(popup_area);
+
+    // Store the actual visible height of the messages area
+    let visible_height = chunks[0].height.saturating_sub(2); // Subtract borders
+    state.last_visible_height = visible_height;
+    state.last_visible_width = chunks[0].width.saturating_sub(2);
+
+    // Content may have shrunk since the last frame
+    state.clamp_scroll(visible_height);
+
+    // Apply auto-scroll if requested (before building lines)
+    if state.auto_scroll {
+        state.scroll_to_bottom(visible_height);
+        state.auto_scroll = false;
+    }
+
+    // Get current spinner frame
+    let current_spinner_frame = state.spinner_frame();
+
+    // Only the messages on screen become lines, the paragraph scrolls within them
+    let (visible, first_row) = state.visible_messages(state.scroll_offset as usize, visible_height as usize);
+    let row_in_first = (state.scroll_offset as usize).saturating_sub(first_row) as u16;
+
+    // Relative timestamps are recomputed on every frame
+    let now = Local::now();
+
+    let view: &ChatState = state;
+    let numbered = view.last_reply_index();
+    let lines: Vec<Line> = view.messages[visible.clone()]
+        .iter()
+        .zip(visible)
+        .flat_map(|(msg, i)| message_lines(msg, view, now, current_spinner_frame, numbered == Some(i)))
+        .collect();
+
+    // Create Paragraph with scroll
+    let mut messages_paragraph = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(state.theme.chat_border))
+                .title(state.messages_title(chunks[0].width))
+                .title_alignment(Alignment::Center),
+        )
+        .style(Style::default().bg(state.theme.background).fg(state.theme.text));
+    messages_paragraph = if state.wrap_enabled {
+        messages_paragraph.wrap(Wrap { trim: false }).scroll((row_in_first, 0))
+    } else {
+        messages_paragraph.scroll((row_in_first, state.h_scroll))
+    };
+
+    match state.mode {
+        ChatMode::Chat | ChatMode::ConfirmAction(_) | ChatMode::CopyBlock => {
+            frame.render_widget(messages_paragraph, chunks[0])
+        }
+        ChatMode::Palette { selected, ref entries, .. } => {
+            // Keep the selection on screen
+            let first = selected.saturating_sub((visible_height as usize).saturating_sub(1));
+            let lines: Vec<Line> = entries
+                .iter()
+                .enumerate()
+                .skip(first)
+                .map(|(i, entry)| palette_line(entry, i == selected, &state.theme))
+                .collect();
+            let palette = Paragraph::new(lines)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(state.theme.chat_border))
+                        .title(format!(
+                            "🔎 Commandes ({} résultat(s) | ↑↓ choisir | Enter afficher la sortie | ESC retour)",
+                            entries.len()
+                        ))
+                        .title_alignment(Alignment::Center),
+                )
+                .style(Style::default().bg(state.theme.background).fg(state.theme.text));
+            frame.render_widget(palette, chunks[0]);
+        }
+        ChatMode::Timeline { ref lines, commands, scroll, ref focus } => {
+            let keys = if focus.is_some() {
+                "Tab/Shift+Tab choisir | Entrée déplier/replier | f tout afficher"
+            } else {
+                "f focus sur les échecs"
+            };
+            let timeline = Paragraph::new(lines.clone())
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(state.theme.chat_border))
+                        .title(format!(
+                            "🕒 Chronologie ({} commande(s) | ↑↓ scroller | {} | ESC retour)",
+                            commands, keys
+                        ))
+                        .title_alignment(Alignment::Center),
+                )
+                .style(Style::default().bg(state.theme.background).fg(state.theme.text))
+                .scroll((scroll, 0));
+            frame.render_widget(timeline, chunks[0]);
+        }
+        ChatMode::ConfirmContext { ref context, expanded, scroll, .. } => {
+            let bytes: usize = context.iter().map(String::len).sum();
+            let preview = Paragraph::new(context_preview_lines(context, expanded, &state.theme))
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(state.theme.confirm_border))
+                        .title(format!(
+                            "🔒 Contexte à envoyer ({} élément(s), {} octets | Tab déplier/replier | \
+                             ↑↓ scroller)",
+                            context.len(),
+                            bytes
+                        ))
+                        .title_alignment(Alignment::Center),
+                )
+                .style(Style::default().bg(state.theme.background).fg(state.theme.text))
+                .scroll((scroll, 0));
+            frame.render_widget(preview, chunks[0]);
+        }
+        ChatMode::Logs { ref lines, scroll } => {
+            let log_lines: Vec<Line> = lines.iter().map(|line| Line::from(line.as_str())).collect();
+            let logs_paragraph = Paragraph::new(log_lines)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(state.theme.logs_border))
+                        .title(format!("📜 Logs ({} dernières lignes | ↑↓ scroller | ESC retour)", lines.len()))
+                        .title_alignment(Alignment::Center),
+                )
+                .style(Style::default().bg(state.theme.background).fg(state.theme.logs_text))
+                .scroll((scroll, 0));
+            frame.render_widget(logs_paragraph, chunks[0]);
+        }
+    }
+
+    // A pending agent action replaces the input box until it's accepted or refused
+    if let ChatMode::ConfirmAction(ref action) = state.mode {
+        let title = if action.runs() {
+            "L'agent propose une action — [o] exécuter | [n] ignorer"
+        } else {
+            "L'agent propose une commande — [o] la taper dans l'invite (Entrée pour la lancer) | [n] ignorer"
+        };
+        let confirm = Paragraph::new(format!("⚡ {}", action.describe()))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(state.theme.confirm_border))
+                    .title(title),
+            )
+            .style(Style::default().bg(state.theme.background).fg(state.theme.highlight).add_modifier(Modifier::BOLD))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(confirm, chunks[1]);
+        return;
+    }
+
+    // Consent for the context replaces the input box too
+    if let ChatMode::ConfirmContext { ref text, .. } = state.mode {
+        let confirm = Paragraph::new(format!("✉️ {}", text))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(state.theme.confirm_border))
+                    .title("Envoyer ce contexte ? — [o] envoyer | [s] sans contexte | [n] annuler"),
+            )
+            .style(Style::default().bg(state.theme.background).fg(state.theme.highlight))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(confirm, chunks[1]);
+        return;
+    }
+
+    // The palette query replaces the message input
+    if let ChatMode::Palette { ref query, .. } = state.mode {
+        let search = Paragraph::new(format!("🔎 {}", query))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(state.theme.input_border))
+                    .title("Rechercher une commande (ex: gst pour git status)"),
+            )
+            .style(Style::default().bg(state.theme.background).fg(state.theme.text));
+        frame.render_widget(search, chunks[1]);
+        let cursor_col = (query.chars().count() as u16 + 3).min(chunks[1].width.saturating_sub(3));
+        frame.set_cursor_position((chunks[1].x + 1 + cursor_col, chunks[1].y + 1));
+        return;
+    }
+
+    // Render input box
+    let prompt = "➤ ";
+    let input_text = format!("{}{}", prompt, state.input);
+    let input = Paragraph::new(input_text)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(state.theme.input_border))
+                .title(format!(
+                    "{} (Enter pour envoyer{})",
+                    if state.clarification.is_some() {
+                        "Votre réponse à la question de l'agent"
+                    } else {
+                        "Votre message"
+                    },
+                    match state.send_on_idle {
+                        Some(idle) => format!(", envoi auto après {:.1}s sans frappe", idle.as_secs_f32()),
+                        None => String::new(),
+                    }
+                ))
+                .title_bottom(
+                    state
+                        .status
+                        .clone()
+                        .or_else(|| state.service_wait_indicator(Instant::now()))
+                        .unwrap_or_default(),
+                ),
+        )
+        .style(Style::default().bg(state.theme.background).fg(state.theme.text))
+        .wrap(Wrap { trim: false });
+
+    frame.render_widget(input, chunks[1]);
+
+    // Place the terminal cursor at the input cursor (clamped to the box)
+    let cursor_col = (prompt.chars().count() + Line::raw(&state.input[..state.input_cursor]).width()) as u16;
+    let max_col = chunks[1].width.saturating_sub(3);
+    frame.set_cursor_position((chunks[1].x + 1 + cursor_col.min(max_col), chunks[1].y + 1));
+}
+
+/// Palette row: number, status and command, with the matched characters highlighted
+fn palette_line(entry: &PaletteEntry, selected: bool, theme: &Theme) -> Line<'static> {
+    let mut spans = vec![Span::raw(format!("#{:<4} {} ", entry.index, entry.label))];
+    let highlight = Style::default().fg(theme.highlight).add_modifier(Modifier::BOLD);
+    spans.extend(entry.command.chars().enumerate().map(|(i, c)| {
+        if entry.positions.contains(&i) {
+            Span::styled(c.to_string(), highlight)
+        } else {
+            Span::raw(c.to_string())
+        }
+    }));
+
+    let line = Line::from(spans);
+    if selected {
+        line.style(Style::default().add_modifier(Modifier::REVERSED))
+    } else {
+        line
+    }
+}
+
+/// Lines of one message: header, content, separator
+/// With `numbered`, opening code fences get the badge used to copy the block
+fn message_lines<'a>(
+    msg: &'a ChatMessage,
+    state: &ChatState,
+    now: DateTime<Local>,
+    spinner_frame: usize,
+    numbered: bool,
+) -> Vec<Line<'a>> {
+    let mut lines = Vec::new();
+    let time = state.timestamp_format.format(msg.timestamp, now);
+    let (prefix, color) = role_style(&msg.role, &state.user_name, &state.assistant_name, &state.theme);
+
+    // Add header with agent badge if available
+    let mut header_spans = vec![
+        Span::styled(prefix, Style::default().fg(color).add_modifier(Modifier::BOLD)),
+        Span::raw(format!(" • {}", time)),
+    ];
+    if let Some(ref agent) = msg.agent {
+        let (emoji, color) = agent_style(agent, &state.theme.badges);
+        header_spans.push(Span::styled(
+            format!(" {} {}", emoji, agent),
+            Style::default().fg(color).add_modifier(Modifier::BOLD),
+        ));
+    }
+    lines.push(Line::from(header_spans));
+    lines.push(Line::from(""));
+
+    // Add content with spinner animation if loading
+    match msg.state {
+        MessageState::Loading => {
+            let spinner = SPINNER_FRAMES[spinner_frame];
+            lines.push(Line::from(vec![
+                Span::styled(
+                    spinner,
+                    Style::default().fg(state.theme.highlight).add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(" "),
+                Span::styled(
+                    &msg.content,
+                    Style::default().fg(state.theme.highlight),
+                ),
+                Span::raw("..."),
+            ]));
+        }
+        MessageState::Ready => {
+            // Add content (no truncation, full message)
+            lines.extend(content_lines(msg, &state.theme, numbered));
+        }
+        MessageState::Empty => {
+            lines.push(Line::from(Span::styled(
+                &msg.content,
+                Style::default().fg(state.theme.badges.error).add_modifier(Modifier::ITALIC),
+            )));
+        }
+        MessageState::Question => {
+            // Stands out until answered: the next message is the answer
+            let style = Style::default().fg(state.theme.highlight);
+            for (i, line) in msg.content.lines().enumerate() {
+                let marker = if i == 0 { "❔ " } else { "   " };
+                lines.push(Line::from(Span::styled(format!("{}{}", marker, line), style)));
+            }
+        }
+    }
+
+    lines.push(Line::from(""));
+    lines.push(Line::from("───────────────────────────────────"));
+    lines.push(Line::from(""));
+
+    lines
+}
+
+/// Header label and color for a message role, using the configured display names
+fn role_style(role: &MessageRole, user_name: &str, assistant_name: &str, theme: &Theme) -> (String, Color) {
+    match role {
+        MessageRole::User => (format!("🧑 {}", user_name), theme.user),
+        MessageRole::Assistant => (format!("🤖 {}", assistant_name), theme.assistant),
+    }
+}
+
+/// Badge emoji and color for the agent that handled a message
+fn agent_style(agent: &str, colors: &BadgeColors) -> (&'static str, Color) {
+    match agent {
+        "toolsmith" => ("🛠️", colors.toolsmith),
+        "researcher" => ("🔍", colors.researcher),
+        "scribe" => ("📝", colors.scribe),
+        "general" => ("🧠", colors.general),
+        "error" => ("⚠️", colors.error),
+        "system" => ("⚙️", colors.system),
+        _ => ("❓", colors.unknown),
+    }
+}
+
+/// Result of the chat loop
+pub enum ChatLoopResult {
+    Closed,
+}
+
+/// Run the chat overlay loop
+pub fn run_chat_loop(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    state: &mut ChatState,
+) -> Result<ChatLoopResult> {
+    state.last_interaction = Instant::now();
+    state.start_service_wait(Instant::now());
+    loop {
+        // Check if response is ready, a new reply gives the reader the whole idle delay again
+        if state.check_response() {
+            state.last_interaction = Instant::now();
+        }
+
+        // Service coming up (or given up on) while the chat waits for it
+        if state.poll_service_wait(Instant::now()) {
+            state.last_interaction = Instant::now();
+        }
+
+        // Unattended (kiosk, shared screen): back to the shell
+        if state.idle_close_due(Instant::now()) {
+            info!("Chat closed after {:?} without input", state.idle_close.unwrap_or_default());
+            return Ok(ChatLoopResult::Closed);
+        }
+
+        // Send-on-idle: dictated or pasted text goes out once the input settles
+        if state.idle_submit_due(Instant::now()) {
+            state.submit_input();
+        }
+
+        // Render the UI
+        terminal.draw(|frame| {
+            let area = frame.area();
+
+            // Fill background (simulate the terminal still being visible)
+            let bg = Block::default().style(Style::default().bg(state.theme.background));
+            frame.render_widget(bg, area);
+
+            render_chat_ui(frame, state, area);
+        })?;
+
+        // Use shorter poll timeout when waiting for response (for smoother animation)
+        let poll_timeout = if state.is_sending() {
+            Duration::from_millis(50)
+        } else {
+            Duration::from_millis(100)
+        };
+
+        // Handle input events
         if event::poll(poll_timeout)? {
-            match event::read()? {
+            let input = event::read()?;
+            if !matches!(input, Event::Resize(..)) {
+                state.last_interaction = Instant::now();
+            }
+            match input {
                 Event::Paste(text) => {
                     // Handle pasted text
-                    state.input.push_str(&text);
+                    state.insert_str(&text);
+                }
+                Event::Resize(_, _) => {
+                    // Redrawn at the top of the loop with the new size
+                    terminal.autoresize()?;
+                    state.handle_resize();
                 }
-                Event::Key(key_event) => {
+                Event::Key(key_event) if keys::is_key_input(&key_event) => {
                     // Use the last known visible height from render
                     let visible_height = state.last_visible_height;
 
+                    if matches!(state.mode, ChatMode::Timeline { .. }) && state.handle_timeline_key(key_event.code) {
+                        continue;
+                    }
+
+                    // The log and timeline views are read-only: only scrolling and leaving them
+                    let read_only = match state.mode {
+                        ChatMode::Logs { ref lines, ref mut scroll } => Some((lines.len(), scroll)),
+                        ChatMode::Timeline { ref lines, ref mut scroll, .. } => Some((lines.len(), scroll)),
+                        _ => None,
+                    };
+                    if let Some((line_count, scroll)) = read_only {
+                        let max_scroll = line_count.saturating_sub(visible_height as usize) as u16;
+                        let mut close = false;
+                        match key_event.code {
+                            KeyCode::Esc => close = true,
+                            KeyCode::Up => *scroll = scroll.saturating_sub(1),
+                            KeyCode::Down => *scroll = (*scroll + 1).min(max_scroll),
+                            KeyCode::PageUp => *scroll = scroll.saturating_sub(10),
+                            KeyCode::PageDown => *scroll = (*scroll + 10).min(max_scroll),
+                            _ => {}
+                        }
+                        if close {
+                            state.close_view();
+                        }
+                        continue;
+                    }
+
+                    // The palette takes every key until it's closed
+                    if let ChatMode::Palette { ref mut query, ref mut selected, .. } = state.mode {
+                        let mut chosen = None;
+                        let mut edited = false;
+                        match key_event.code {
+                            KeyCode::Esc => chosen = Some(None),
+                            KeyCode::Up => *selected = selected.saturating_sub(1),
+                            KeyCode::Down => *selected += 1,
+                            KeyCode::Backspace => {
+                                edited = query.pop().is_some();
+                                *selected = 0;
+                            }
+                            KeyCode::Char(c) if !key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                                query.push(c);
+                                *selected = 0;
+                                edited = true;
+                            }
+                            KeyCode::Enter => chosen = Some(Some(*selected)),
+                            _ => {}
+                        }
+
+                        let matches = edited.then(|| query.clone()).map(|query| state.palette_entries(&query));
+                        let mut shown = None;
+                        if let ChatMode::Palette { ref mut selected, ref mut entries, .. } = state.mode {
+                            if let Some(matches) = matches {
+                                *entries = matches;
+                            }
+                            *selected = (*selected).min(entries.len().saturating_sub(1));
+                            shown = chosen.flatten().and_then(|i| entries.get(i)).map(|entry| entry.index);
+                        }
+                        if chosen.is_some() {
+                            state.close_view();
+                            if let Some(index) = shown {
+                                state.show_output(Some(index));
+                            }
+                        }
+                        continue;
+                    }
+
+                    // Ctrl+Y waits for the number of the block to copy
+                    if matches!(state.mode, ChatMode::CopyBlock) {
+                        state.close_view();
+                        match key_event.code {
+                            KeyCode::Char(c @ '1'..='9') => state.copy_code_block(c as usize - '0' as usize),
+                            _ => state.status = None,
+                        }
+                        continue;
+                    }
+
+                    // The context is sent only on an explicit answer
+                    if matches!(state.mode, ChatMode::ConfirmContext { .. }) {
+                        state.handle_context_confirmation(key_event.code, visible_height);
+                        continue;
+                    }
+
+                    // Agent actions need an explicit answer before anything reaches the shell
+                    if let ChatMode::ConfirmAction(ref action) = state.mode {
+                        let action = action.clone();
+                        match key_event.code {
+                            KeyCode::Char('o') | KeyCode::Char('y') => {
+                                state.mode = ChatMode::Chat;
+                                match state.run_action(&action) {
+                                    // Back to the shell so the user sees the result, or reviews
+                                    // the inserted command
+                                    Ok(()) => return Ok(ChatLoopResult::Closed),
+                                    Err(e) => state.status = Some(format!("❌ Action échouée: {}", e)),
+                                }
+                            }
+                            KeyCode::Char('n') | KeyCode::Esc => {
+                                state.mode = ChatMode::Chat;
+                                state.status = Some("Action ignorée".to_string());
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
+
                     match key_event.code {
                         KeyCode::Esc => {
                             // Exit chat mode
@@ -471,32 +2350,340 @@ pub fn run_chat_loop(
                             // Jump to bottom
                             state.auto_scroll = true; // Trigger auto-scroll on next render
                         }
+                        KeyCode::Char('b') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            // Cycle through configured backends
+                            state.cycle_backend();
+                        }
+                        KeyCode::Char('r') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            // Wide output (tables) reads better without wrapping
+                            state.toggle_wrap();
+                        }
+                        KeyCode::Char('g') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            // Another answer to the same prompt
+                            state.regenerate_last();
+                        }
+                        KeyCode::Char('y') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            // Copy a code block of the last reply
+                            state.start_copy_block();
+                        }
+                        KeyCode::Char('n') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            // A general question, without the session muddying the answer
+                            state.toggle_no_context();
+                        }
                         KeyCode::Enter => {
-                            // Send message
-                            if !state.input.is_empty() && state.response_receiver.is_none() {
-                                let user_message = state.input.clone();
-                                state.add_user_message(user_message.clone());
-                                state.clear_input();
-
-                                // Start generating AI response asynchronously (non-blocking)
-                                state.start_generate_response(user_message);
-                            }
+                            state.submit_input();
                         }
-                        KeyCode::Char(c) => {
+                        KeyCode::Char(c)
+                            if !key_event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+                        {
                             // Add character to input
-                            state.input.push(c);
+                            state.insert_char(c);
                         }
                         KeyCode::Backspace => {
-                            // Remove character
-                            state.input.pop();
+                            // Remove character before the cursor
+                            state.backspace();
+                        }
+                        KeyCode::Delete => {
+                            // Remove character under the cursor
+                            state.delete_forward();
+                        }
+                        // Without wrapping, arrows scroll the messages while the input is empty
+                        KeyCode::Left if !state.wrap_enabled && state.input.is_empty() => {
+                            state.scroll_left(4);
+                        }
+                        KeyCode::Right if !state.wrap_enabled && state.input.is_empty() => {
+                            state.scroll_right(4);
+                        }
+                        KeyCode::Left => {
+                            state.move_cursor_left();
+                        }
+                        KeyCode::Right => {
+                            state.move_cursor_right();
+                        }
+                        KeyCode::Tab => {
+                            // Complete slash command names
+                            state.complete_command();
                         }
+                        // Insert, function keys and unhandled Ctrl/Alt combos must not
+                        // leak literal characters into the message
                         _ => {}
                     }
                 }
-                _ => {} // Ignore other events (Mouse, Resize, etc.)
+                _ => {} // Ignore other events (Mouse, focus, etc.)
+            }
+        }
+    }
+}
+
+/// Chat input with its `{{N}}` / `{{last}}` command references expanded
+#[derive(Debug, PartialEq)]
+struct ExpandedInput {
+    /// Sent to the agent: each reference replaced by the command and its cleaned output
+    sent: String,
+
+    /// Shown in the conversation: each reference folded to `⟪#N $ command⟫`
+    shown: String,
+
+    /// References to commands that don't exist, left literal
+    unknown: Vec<String>,
+}
+
+impl ExpandedInput {
+    fn unchanged(input: &str) -> Self {
+        Self {
+            sent: input.to_string(),
+            shown: input.to_string(),
+            unknown: Vec::new(),
+        }
+    }
+}
+
+/// Expand `{{N}}` (1-based) and `{{last}}` to captured commands, each output cut to `budget`
+/// Anything else between braces isn't a reference and is left alone
+fn expand_command_refs(input: &str, capture: &CommandCapture, budget: usize) -> ExpandedInput {
+    let mut expanded = ExpandedInput {
+        sent: String::with_capacity(input.len()),
+        shown: String::with_capacity(input.len()),
+        unknown: Vec::new(),
+    };
+    let mut rest = input;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        let token = &rest[start..start + len + 2];
+        let index = match token[2..len].trim() {
+            "last" => Some(capture.len()),
+            inner => inner.parse::<usize>().ok(),
+        };
+        expanded.sent.push_str(&rest[..start]);
+        expanded.shown.push_str(&rest[..start]);
+        match index.map(|index| (index, index.checked_sub(1).and_then(|i| capture.get(i)))) {
+            Some((index, Some(cmd))) => {
+                let output = capture::truncate_start(cmd.clean_output().trim_end(), budget);
+                let _ = write!(expanded.sent, "\n```\n$ {}\n{}\n```\n", cmd.command, output);
+                let _ = write!(expanded.shown, "⟪#{} $ {}⟫", index, cmd.command);
             }
+            Some((_, None)) => {
+                expanded.unknown.push(token.to_string());
+                expanded.sent.push_str(token);
+                expanded.shown.push_str(token);
+            }
+            None => {
+                expanded.sent.push_str(token);
+                expanded.shown.push_str(token);
+            }
+        }
+        rest = &rest[start + len + 2..];
+    }
+    expanded.sent.push_str(rest);
+    expanded.shown.push_str(rest);
+    expanded
+}
+
+/// Preview of the context about to be sent: the first line of each entry, or all of it when
+/// `expanded`
+fn context_preview_lines(context: &[String], expanded: bool, theme: &Theme) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    for (i, entry) in context.iter().enumerate() {
+        let mut entry_lines = entry.lines();
+        let first = entry_lines.next().unwrap_or_default();
+        let more = entry_lines.clone().count();
+        let marker = if expanded || more == 0 { "▾" } else { "▸" };
+        lines.push(Line::from(vec![
+            Span::styled(
+                format!("{} {}. ", marker, i + 1),
+                Style::default().fg(theme.highlight).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(first.to_string()),
+        ]));
+        if expanded {
+            let code = Style::default().fg(theme.code);
+            lines.extend(entry_lines.map(|line| Line::styled(format!("     {}", line), code)));
+        } else if more > 0 {
+            lines.push(Line::styled(
+                format!("     … {} ligne(s) de plus, {} octets", more, entry.len()),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+    }
+    lines
+}
+
+/// `/diff` message, the diff goes in a ```diff block for the +/- colors
+/// Each side is (index, command, cleaned output)
+fn format_output_diff(old: (usize, &str, &str), new: (usize, &str, &str)) -> String {
+    let (old_label, new_label) = (format!("#{}", old.0), format!("#{}", new.0));
+    match diff::unified_line_diff(old.2, new.2, &old_label, &new_label) {
+        None => format!("🟰 Aucune différence entre les sorties de {} et {}", old_label, new_label),
+        Some(diff) => format!(
+            "🔀 Sorties de {} et {}\n{}: $ {}\n{}: $ {}\n\n```diff\n{}```",
+            old_label, new_label, old_label, old.1, new_label, new.1, diff
+        ),
+    }
+}
+
+/// Message text with code blocks styled, and their copy badges when `numbered`
+fn content_lines<'a>(msg: &ChatMessage, theme: &Theme, numbered: bool) -> Vec<Line<'a>> {
+    let blocks = if numbered { msg.code_blocks() } else { Vec::new() };
+    let kinds = markdown::line_kinds(&msg.content);
+    let mut lines = Vec::new();
+    for (i, (line, kind)) in msg.content.lines().zip(kinds).enumerate() {
+        let style = match kind {
+            LineKind::Text => Style::default(),
+            LineKind::Fence => Style::default().fg(Color::DarkGray),
+            LineKind::Code(Some("diff")) => diff_line_style(line, theme),
+            LineKind::Code(_) => Style::default().fg(theme.code),
+        };
+        let mut spans = vec![Span::styled(line.to_string(), style)];
+        if let Some(n) = blocks.iter().position(|block| block.fence_line == i) {
+            spans.push(Span::styled(
+                format!(" {}", markdown::block_badge(n + 1)),
+                Style::default().fg(theme.highlight).add_modifier(Modifier::BOLD),
+            ));
+        }
+        lines.push(Line::from(spans));
+    }
+    lines
+}
+
+/// `/timeline` view: one dot per command with its start time, how long ago that was, its status
+/// and duration, and the idle gaps between commands
+/// Also returns the row each command starts at
+/// In focus mode, successful commands are one dim line and unfolded ones end with their output
+fn timeline_lines<'a>(
+    commands: impl IntoIterator<Item = &'a CapturedCommand>,
+    now: DateTime<Local>,
+    theme: &Theme,
+    focus: Option<&TimelineFocus>,
+) -> (Vec<Line<'static>>, Vec<usize>) {
+    let relative = TimestampFormat::Relative;
+    let dim = Style::default().fg(Color::DarkGray);
+    let mut lines = Vec::new();
+    let mut starts = Vec::new();
+    let mut previous_end: Option<DateTime<Local>> = None;
+
+    for (i, cmd) in commands.into_iter().enumerate() {
+        if let (Some(end), None) = (previous_end, focus) {
+            let gap = cmd.timestamp - end;
+            if gap >= chrono::Duration::minutes(1) {
+                lines.push(Line::from(Span::styled("  ┆", dim)));
+                lines.push(Line::from(Span::styled(
+                    format!("  ┆  {} sans commande", capture::format_duration(gap)),
+                    dim,
+                )));
+            }
+            lines.push(Line::from(Span::styled("  │", dim)));
+        }
+        previous_end = Some(cmd.finished_at.unwrap_or(cmd.timestamp));
+        starts.push(lines.len());
+
+        let (_, color) = cmd.status_badge();
+        let selected = focus.is_some_and(|focus| focus.selected == i);
+        let marker = if selected { "▶ " } else { "  " };
+        if focus.is_some_and(|focus| !focus.is_expanded(i)) {
+            lines.push(Line::from(vec![
+                Span::styled(marker, Style::default().fg(theme.highlight)),
+                Span::styled(
+                    format!(
+                        "· #{} {} {}  {}",
+                        i + 1,
+                        cmd.status_label(),
+                        cmd.timestamp.format("%H:%M:%S"),
+                        cmd.command.lines().next().unwrap_or_default()
+                    ),
+                    dim,
+                ),
+            ]));
+            continue;
+        }
+
+        let duration = match cmd.duration() {
+            Some(duration) => format!("⏱ {}", capture::format_duration(duration)),
+            None => "⏱ en cours".to_string(),
+        };
+        lines.push(Line::from(vec![
+            Span::styled(format!("{}● ", marker), Style::default().fg(color).add_modifier(Modifier::BOLD)),
+            Span::styled(
+                format!("{} ({})", cmd.timestamp.format("%H:%M:%S"), relative.format(cmd.timestamp, now).trim()),
+                Style::default().fg(theme.highlight),
+            ),
+            Span::raw(format!("  #{} ", i + 1)),
+            Span::styled(cmd.status_label(), Style::default().fg(color)),
+            Span::raw(format!("  {}", cmd.command.lines().next().unwrap_or_default())),
+        ]));
+        lines.push(Line::from(vec![Span::styled("  │   ", dim), Span::styled(duration, dim)]));
+        if focus.is_none() {
+            continue;
+        }
+
+        let output = cmd.clean_output();
+        let output: Vec<&str> = output.lines().collect();
+        let skipped = output.len().saturating_sub(TIMELINE_OUTPUT_LINES);
+        if output.is_empty() {
+            lines.push(Line::from(Span::styled("  │   (aucune sortie)", dim)));
+        } else if skipped > 0 {
+            lines.push(Line::from(Span::styled(format!("  │   … {} ligne(s) plus haut", skipped), dim)));
+        }
+        for line in &output[skipped..] {
+            lines.push(Line::from(vec![Span::styled("  │   ", dim), Span::raw(line.to_string())]));
+        }
+    }
+    (lines, starts)
+}
+
+/// Style of a line inside a ```diff block
+fn diff_line_style(line: &str, theme: &Theme) -> Style {
+    if line.starts_with("+++") || line.starts_with("---") {
+        Style::default().add_modifier(Modifier::BOLD)
+    } else if line.starts_with('+') {
+        Style::default().fg(Color::Green)
+    } else if line.starts_with('-') {
+        Style::default().fg(Color::Red)
+    } else if line.starts_with("@@") {
+        Style::default().fg(theme.highlight)
+    } else {
+        Style::default()
+    }
+}
+
+/// `/stats` message
+fn format_stats(stats: &CaptureStats) -> String {
+    let minutes = stats.session_duration.num_minutes();
+    let mut message = format!(
+        "📊 Session: {}h{:02} | {} commande(s)\n✓ {} réussie(s) | ✗ {} en échec | … {} sans code de sortie\n📦 {} octet(s) de sortie capturés",
+        minutes / 60,
+        minutes % 60,
+        stats.total,
+        stats.succeeded,
+        stats.failed,
+        stats.unfinished,
+        stats.output_bytes
+    );
+    if !stats.top_commands.is_empty() {
+        message.push_str("\n\n🔁 Les plus lancées:");
+        for (program, runs) in &stats.top_commands {
+            message.push_str(&format!("\n  {} ×{}", program, runs));
         }
     }
+    message
+}
+
+/// Read the last `count` lines of a file (only its tail is read, logs can be large)
+fn tail_lines(path: &Path, count: usize) -> std::io::Result<Vec<String>> {
+    const TAIL_BYTES: u64 = 64 * 1024;
+
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL_BYTES)))?;
+
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    let text = String::from_utf8_lossy(&bytes);
+
+    let lines: Vec<String> = text.lines().map(str::to_string).collect();
+    let skip = lines.len().saturating_sub(count);
+    Ok(lines.into_iter().skip(skip).collect())
 }
 
 /// Helper to create a centered rectangle
@@ -519,3 +2706,806 @@ fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
         ])
         .split(popup_layout[1])[1]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc_client::chat::ChatResponse;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_relative_timestamps() {
+        let now = Local::now();
+        let format = TimestampFormat::Relative;
+
+        assert_eq!(format.format(now, now).trim(), "maintenant");
+        assert_eq!(format.format(now - chrono::Duration::seconds(42), now).trim(), "42s");
+        assert_eq!(format.format(now - chrono::Duration::minutes(5), now).trim(), "5m");
+        assert_eq!(format.format(now - chrono::Duration::hours(3), now).trim(), "3h");
+
+        // Fixed width regardless of the label
+        assert_eq!(format.format(now, now).len(), format.format(now - chrono::Duration::hours(3), now).len());
+    }
+
+    #[test]
+    fn test_timestamp_format_from_config() {
+        assert_eq!(TimestampFormat::from("relative".to_string()), TimestampFormat::Relative);
+        assert_eq!(
+            TimestampFormat::from("%I:%M %p".to_string()),
+            TimestampFormat::Strftime("%I:%M %p".to_string())
+        );
+    }
+
+    #[test]
+    fn test_clamp_scroll_after_content_shrinks() {
+        let mut state = ChatState::new(&Config::default());
+        for i in 0..20 {
+            state.add_user_message(format!("message {}", i));
+        }
+
+        let visible_height = 10;
+        state.scroll_to_bottom(visible_height);
+        let old_offset = state.scroll_offset;
+        assert!(old_offset > 0);
+
+        // Drop most of the conversation
+        state.messages.truncate(2);
+        state.clamp_scroll(visible_height);
+
+        assert_eq!(state.scroll_offset, state.max_scroll_offset(visible_height));
+        assert!(state.scroll_offset < old_offset);
+    }
+
+    #[test]
+    fn test_auto_scroll_threshold_boundary() {
+        let config = Config {
+            auto_scroll_threshold_lines: Some(3),
+            ..Config::default()
+        };
+        let mut state = ChatState::new(&config);
+        for i in 0..20 {
+            state.add_user_message(format!("message {}", i));
+        }
+        state.last_visible_height = 10;
+        state.auto_scroll = false;
+        let max = state.max_scroll_offset(10);
+
+        state.scroll_offset = max - 3;
+        assert!(state.is_near_bottom(10));
+        state.add_assistant_message("réponse".to_string(), None);
+        assert!(state.auto_scroll);
+
+        state.auto_scroll = false;
+        state.scroll_offset = state.max_scroll_offset(10) - 4;
+        assert!(!state.is_near_bottom(10));
+        state.add_assistant_message("réponse".to_string(), None);
+        assert!(!state.auto_scroll);
+    }
+
+    #[test]
+    fn test_resize_keeps_bottom_and_reclamps() {
+        let mut state = ChatState::new(&Config::default());
+        for i in 0..20 {
+            state.add_user_message(format!("message {}", i));
+        }
+        state.last_visible_height = 10;
+        state.scroll_to_bottom(10);
+        state.auto_scroll = false;
+
+        // At the bottom: stays there after the resize
+        state.handle_resize();
+        assert!(state.auto_scroll);
+
+        // Scrolled up: keeps its position, clamped when the window grows
+        state.auto_scroll = false;
+        state.scroll_offset = state.max_scroll_offset(10) - 5;
+        state.handle_resize();
+        assert!(!state.auto_scroll);
+
+        let taller = state.max_scroll_offset(10) + 10;
+        state.last_visible_height = taller;
+        state.clamp_scroll(taller);
+        assert_eq!(state.scroll_offset, 0);
+    }
+
+    #[test]
+    fn test_empty_reply_gets_placeholder() {
+        let config = Config {
+            mock: true,
+            ..Config::default()
+        };
+        let mut state = ChatState::new(&config);
+        let (tx, rx) = mpsc::channel();
+        state.add_loading_message();
+        state.response_receiver = Some(rx);
+
+        tx.send(Ok(AgentReply {
+            message: " \n".to_string(),
+            agent: "researcher".to_string(),
+            action: None,
+            awaiting_clarification: true,
+        }))
+        .unwrap();
+        assert!(state.check_response());
+
+        let last = state.messages.last().unwrap();
+        assert!(matches!(last.state, MessageState::Empty));
+        assert_eq!(last.content, EMPTY_REPLY_PLACEHOLDER);
+        assert_eq!(last.agent.as_deref(), Some("researcher"));
+        assert_eq!(state.clarification, None);
+    }
+
+    /// Down for its first two health checks, then up
+    struct SlowStart {
+        checks: AtomicUsize,
+    }
+
+    impl ChatTransport for SlowStart {
+        fn send(&self, message: String, _context: Vec<String>) -> Result<ChatResponse> {
+            Ok(ChatResponse { message, ..Default::default() })
+        }
+
+        fn check_ready(&self) -> Result<()> {
+            match self.checks.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(anyhow::anyhow!("connection refused")),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    /// Counts the requests that reach the service
+    struct Counting {
+        sent: Arc<AtomicUsize>,
+    }
+
+    impl ChatTransport for Counting {
+        fn send(&self, message: String, _context: Vec<String>) -> Result<ChatResponse> {
+            self.sent.fetch_add(1, Ordering::SeqCst);
+            Ok(ChatResponse { message, ..Default::default() })
+        }
+    }
+
+    #[test]
+    fn test_repeated_enter_sends_once() {
+        let mut state = ChatState::new(&Config::default());
+        let sent = Arc::new(AtomicUsize::new(0));
+        state.transport = Arc::new(Counting { sent: Arc::clone(&sent) });
+
+        // Enter, then Enter again on the same text typed back before any reply
+        state.insert_str("bonjour");
+        state.submit_input();
+        state.insert_str("bonjour");
+        state.submit_input();
+        assert_eq!(state.input, "bonjour");
+        assert_eq!(state.recent_requests.len(), 1);
+
+        for _ in 0..200 {
+            if state.check_response() && !state.is_sending() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(!state.is_sending());
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+        let user_messages = state.messages.iter().filter(|msg| matches!(msg.role, MessageRole::User));
+        assert_eq!(user_messages.count(), 1);
+
+        // Once answered, the next Enter goes out
+        state.submit_input();
+        assert!(state.is_sending());
+    }
+
+    /// Poll until the health check in flight comes back
+    fn finish_check(state: &mut ChatState, now: Instant) -> bool {
+        for _ in 0..200 {
+            let changed = state.poll_service_wait(now);
+            if state.service_wait.as_ref().is_none_or(|wait| wait.check.is_none()) {
+                return changed;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("health check never finished");
+    }
+
+    #[test]
+    fn test_message_held_until_service_is_up() {
+        let mut state = ChatState::new(&Config::default());
+        state.transport = Arc::new(SlowStart { checks: AtomicUsize::new(0) });
+        let start = Instant::now();
+        state.start_service_wait(start);
+
+        // First check fails: countdown to the next one
+        state.poll_service_wait(start);
+        assert_eq!(state.service_wait_indicator(start).unwrap(), "⏳ En attente du service IA… vérification");
+        assert!(!finish_check(&mut state, start));
+        assert_eq!(
+            state.service_wait_indicator(start).unwrap(),
+            "⏳ En attente du service IA… nouvel essai dans 1s (2/10)"
+        );
+
+        // Typed meanwhile: held, not sent
+        state.input = "bonjour".to_string();
+        state.submit_input();
+        assert!(state.response_receiver.is_none());
+
+        // Not due yet, then a second failure
+        assert!(!state.poll_service_wait(start));
+        let later = start + Duration::from_secs(1);
+        state.poll_service_wait(later);
+        assert!(!finish_check(&mut state, later));
+
+        // Up: the held message goes out
+        let later = later + Duration::from_secs(2);
+        state.poll_service_wait(later);
+        assert!(finish_check(&mut state, later));
+        assert!(state.service_wait.is_none());
+        assert!(state.response_receiver.is_some());
+        assert_eq!(state.messages.last().unwrap().prompt.as_ref().unwrap().text, "bonjour");
+
+        // Only on first opening
+        state.start_service_wait(later);
+        assert!(state.service_wait.is_none());
+    }
+
+    #[test]
+    fn test_messages_title_fits_width() {
+        let config = Config {
+            mock: true,
+            ..Config::default()
+        };
+        let state = ChatState::new(&config);
+        let full = state.messages_title(300);
+        assert!(full.ends_with(&format!("({})", CHAT_KEY_HINTS)));
+
+        let short = state.messages_title(60);
+        assert_eq!(short, "💬 Petoncle Chat [mock]  (ESC quitter)");
+        assert!(Line::raw(short.as_str()).width() <= 58);
+
+        assert_eq!(state.messages_title(20), "💬 Chat");
+    }
+
+    #[test]
+    fn test_requests_throttled_past_the_limit() {
+        let config = Config {
+            mock: true,
+            max_requests_per_minute: Some(3),
+            ..Config::default()
+        };
+        let mut state = ChatState::new(&config);
+        let start = Instant::now();
+        state.recent_requests.extend([start, start, start + Duration::from_secs(30)]);
+
+        let almost = start + RATE_WINDOW - Duration::from_millis(500);
+        assert_eq!(state.throttle_delay(almost), Some(Duration::from_millis(500)));
+        assert!(state.rate_limited(almost));
+        assert_eq!(
+            state.status.as_deref(),
+            Some("⏳ Trop de requêtes en une minute, réessayez dans 1s")
+        );
+
+        // The first two leave the window together
+        assert_eq!(state.throttle_delay(start + RATE_WINDOW), None);
+        assert_eq!(state.recent_requests.len(), 1);
+
+        // Refused input isn't lost
+        state.recent_requests.extend([start + RATE_WINDOW; 2]);
+        state.input = "encore".to_string();
+        state.submit_input();
+        assert_eq!(state.input, "encore");
+        assert!(state.response_receiver.is_none());
+
+        state.max_requests_per_minute = None;
+        assert_eq!(state.throttle_delay(start), None);
+    }
+
+    #[test]
+    fn test_backend_switch_reaches_shared_copy() {
+        let mut config: Config = toml::from_str(
+            "[[backends]]\nname = \"local\"\naddress = \"127.0.0.1:50051\"\n\
+             [[backends]]\nname = \"gpu\"\naddress = \"10.0.0.2:50051\"\n",
+        )
+        .unwrap();
+        config.mock = true;
+        let mut state = ChatState::new(&config);
+        let shared = state.shared_backend();
+        assert_eq!(shared.lock().unwrap().name, "local");
+
+        state.select_backend("gpu");
+        assert_eq!(shared.lock().unwrap().name, "gpu");
+        state.cycle_backend();
+        assert_eq!(shared.lock().unwrap().name, "local");
+    }
+
+    #[test]
+    fn test_action_waits_for_the_open_view() {
+        let mut state = ChatState::new(&Config::default());
+        let (tx, rx) = mpsc::channel();
+        state.add_loading_message();
+        state.response_receiver = Some(rx);
+        state.mode = ChatMode::Logs { lines: vec!["INFO start".to_string()], scroll: 0 };
+
+        tx.send(Ok(AgentReply {
+            message: "Je lance les tests".to_string(),
+            agent: "general".to_string(),
+            action: Some(AgentAction::RunCommand("cargo test".to_string())),
+            awaiting_clarification: false,
+        }))
+        .unwrap();
+        assert!(state.check_response());
+        assert!(matches!(state.mode, ChatMode::Logs { .. }));
+
+        state.close_view();
+        assert!(matches!(state.mode, ChatMode::ConfirmAction(AgentAction::RunCommand(ref c)) if c == "cargo test"));
+        state.mode = ChatMode::Chat;
+        state.close_view();
+        assert!(matches!(state.mode, ChatMode::Chat));
+    }
+
+    #[test]
+    fn test_context_held_until_confirmed() {
+        let config = Config {
+            mock: true,
+            confirm_context: true,
+            ..Config::default()
+        };
+        let mut state = ChatState::new(&config);
+        state.pending_attachments.push("$ make\nerror: missing\nexit 2".to_string());
+        state.start_generate_response("pourquoi ?".to_string());
+        assert!(matches!(state.mode, ChatMode::ConfirmContext { ref context, .. } if context.len() == 1));
+        assert!(state.response_receiver.is_none());
+
+        let ChatMode::ConfirmContext { ref context, .. } = state.mode else { unreachable!() };
+        assert_eq!(context_preview_lines(context, false, &state.theme).len(), 2);
+        assert_eq!(context_preview_lines(context, true, &state.theme).len(), 3);
+
+        // Sent without its context
+        state.handle_context_confirmation(KeyCode::Char('s'), 20);
+        assert!(matches!(state.mode, ChatMode::Chat));
+        assert!(state.response_receiver.is_some());
+        let prompt = state.messages.last().unwrap().prompt.as_ref().unwrap();
+        assert!(prompt.context.is_empty());
+    }
+
+    #[test]
+    fn test_next_message_sent_without_context() {
+        let config = Config {
+            mock: true,
+            ..Config::default()
+        };
+        let mut state = ChatState::new(&config);
+        state.pending_attachments.push("$ make\nerror: missing\nexit 2".to_string());
+        state.toggle_no_context();
+        assert!(state.messages_title(300).contains("🚫 sans contexte"));
+
+        state.execute_command(ChatCommand::NoContext(Some("c'est quoi un inode ?".to_string())));
+        let prompt = state.messages.last().unwrap().prompt.as_ref().unwrap();
+        assert!(prompt.context.is_empty());
+        assert_eq!(state.pending_attachments.len(), 1);
+
+        // Only that message: the flag is consumed
+        assert!(!state.no_context_next);
+        assert!(!state.messages_title(300).contains("🚫"));
+    }
+
+    #[test]
+    fn test_clarification_round_trip() {
+        let config = Config {
+            mock: true,
+            ..Config::default()
+        };
+        let mut state = ChatState::new(&config);
+        let (tx, rx) = mpsc::channel();
+        state.turn_topic = Some("installe nmap".to_string());
+        state.add_loading_message();
+        state.response_receiver = Some(rx);
+
+        tx.send(Ok(AgentReply {
+            message: "Quelle distribution ?".to_string(),
+            agent: "general".to_string(),
+            action: None,
+            awaiting_clarification: true,
+        }))
+        .unwrap();
+        assert!(state.check_response());
+        assert_eq!(
+            state.clarification,
+            Some(Clarification {
+                original: "installe nmap".to_string(),
+                question: "Quelle distribution ?".to_string(),
+            })
+        );
+        assert!(matches!(state.messages.last().unwrap().state, MessageState::Question));
+
+        // The answer closes the clarification but keeps the original topic for the next reply
+        state.start_generate_response("Debian".to_string());
+        assert_eq!(state.clarification, None);
+        assert_eq!(state.turn_topic.as_deref(), Some("installe nmap"));
+        assert!(!state.messages.iter().any(|msg| matches!(msg.state, MessageState::Question)));
+    }
+
+    #[test]
+    fn test_regenerate_replaces_last_reply() {
+        let config = Config {
+            mock: true,
+            ..Config::default()
+        };
+        let mut state = ChatState::new(&config);
+
+        // The welcome message wasn't produced by a prompt
+        state.regenerate_last();
+        assert!(state.status.is_some());
+
+        state.add_user_message("bonjour".to_string());
+        state.start_generate_response("bonjour".to_string());
+        assert!(state.messages.last().unwrap().prompt.is_some());
+
+        // Refused while the first answer is still pending
+        let count = state.messages.len();
+        state.regenerate_last();
+        assert_eq!(state.messages.len(), count);
+        assert_eq!(state.status.as_deref(), Some("Une réponse est déjà en attente"));
+
+        state.response_receiver = None;
+        state.update_last_message("salut".to_string(), Some("general".to_string()));
+        state.regenerate_last();
+        assert_eq!(state.messages.len(), count);
+        let last = state.messages.last().unwrap();
+        assert!(matches!(last.state, MessageState::Loading));
+        assert_eq!(last.prompt.as_ref().unwrap().text, "bonjour");
+        assert!(state.response_receiver.is_some());
+    }
+
+    #[test]
+    fn test_retry_shown_while_reconnecting() {
+        let mut state = ChatState::new(&Config::default());
+        let (tx, rx) = mpsc::channel();
+        let (progress_tx, progress_rx) = mpsc::channel();
+        state.add_loading_message();
+        state.response_receiver = Some(rx);
+        state.progress_receiver = Some(progress_rx);
+
+        progress_tx
+            .send(Progress::Retrying { attempt: 2, attempts: 4, delay: Duration::from_secs(1) })
+            .unwrap();
+        assert!(state.check_response());
+        assert_eq!(
+            state.messages.last().unwrap().content,
+            "Service injoignable, reconnexion (tentative 2/4)"
+        );
+
+        // The worker gave up: the error replaces the spinner
+        tx.send(Err(anyhow::anyhow!("service unavailable"))).unwrap();
+        assert!(state.check_response());
+        let last = state.messages.last().unwrap();
+        assert!(matches!(last.state, MessageState::Ready));
+        assert!(last.content.starts_with("❌ Error: service unavailable"));
+        assert!(state.response_receiver.is_none());
+    }
+
+    #[test]
+    fn test_worker_gone_stops_spinner() {
+        let mut state = ChatState::new(&Config::default());
+        let (tx, rx) = mpsc::channel::<Result<AgentReply>>();
+        state.add_loading_message();
+        state.response_receiver = Some(rx);
+        drop(tx);
+
+        assert!(state.check_response());
+        assert!(matches!(state.messages.last().unwrap().state, MessageState::Ready));
+        assert!(state.response_receiver.is_none());
+    }
+
+    #[test]
+    fn test_palette_entries() {
+        let mut capture = CommandCapture::new();
+        for command in ["git status", "ls"] {
+            capture.start_command(command.to_string(), PathBuf::from("/tmp"));
+            capture.finalize_command(0);
+        }
+        let mut state = ChatState::new(&Config::default());
+        state.set_command_capture(Arc::new(Mutex::new(capture)));
+
+        assert_eq!(
+            state.palette_entries("gst"),
+            vec![PaletteEntry {
+                index: 1,
+                label: "✓".to_string(),
+                command: "git status".to_string(),
+                positions: vec![0, 4, 5],
+            }]
+        );
+        assert_eq!(state.palette_entries("").len(), 2);
+
+        // Scored once when opened, not again on every frame
+        state.open_palette(Some("ls".to_string()));
+        assert!(matches!(state.mode, ChatMode::Palette { ref entries, .. } if entries.len() == 1 && entries[0].index == 2));
+    }
+
+    #[test]
+    fn test_expand_command_refs() {
+        let mut capture = CommandCapture::new();
+        capture.process_output(
+            "\x1b]133;C;make\x07\x1b[31merror\x1b[0m: missing\n\x1b]133;D;2\x07\
+             \x1b]133;C;ls\x07a\n\x1b]133;D;0\x07",
+            &PathBuf::from("/tmp"),
+        );
+
+        let expanded = expand_command_refs("pourquoi {{1}} échoue ? {{9}} {{ name }}", &capture, 1000);
+        assert_eq!(expanded.shown, "pourquoi ⟪#1 $ make⟫ échoue ? {{9}} {{ name }}");
+        assert_eq!(
+            expanded.sent,
+            "pourquoi \n```\n$ make\nerror: missing\n```\n échoue ? {{9}} {{ name }}"
+        );
+        assert_eq!(expanded.unknown, ["{{9}}"]);
+
+        let expanded = expand_command_refs("{{last}}", &capture, 1000);
+        assert_eq!(expanded.shown, "⟪#2 $ ls⟫");
+        assert_eq!(expand_command_refs("{{last}}", &CommandCapture::new(), 1000).unknown, ["{{last}}"]);
+    }
+
+    #[test]
+    fn test_eviction_renumbering_is_announced() {
+        let mut capture = CommandCapture::new().with_max_commands(2);
+        for command in ["make", "ls", "pwd"] {
+            capture.start_command(command.to_string(), PathBuf::from("/tmp"));
+            capture.finalize_command(0);
+        }
+        capture.start_command("date".to_string(), PathBuf::from("/tmp"));
+        let mut state = ChatState::new(&Config::default());
+        state.set_command_capture(Arc::new(Mutex::new(capture)));
+
+        // Commands without numbers don't mention it
+        state.execute_command(ChatCommand::Stats);
+        assert!(!state.messages.iter().any(|m| m.content.contains("décalés")));
+
+        state.execute_command(ChatCommand::Pin(1));
+        let notices: Vec<_> = state.messages.iter().filter(|m| m.content.contains("décalés")).collect();
+        assert_eq!(notices.len(), 1);
+        assert!(notices[0].content.starts_with("⚠️ 1 ancienne(s) commande(s) oubliée(s)"));
+
+        // Told once per eviction
+        state.execute_command(ChatCommand::Pin(1));
+        assert_eq!(state.messages.iter().filter(|m| m.content.contains("décalés")).count(), 1);
+    }
+
+    #[test]
+    fn test_attach_command_file() {
+        let dir = std::env::temp_dir().join(format!("petoncle-tail-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("build.log"), "compiling\nerror: linker failed\n").unwrap();
+
+        let mut capture = CommandCapture::new();
+        for command in ["make > build.log 2>&1", "ls"] {
+            capture.start_command(command.to_string(), dir.clone());
+            capture.finalize_command(0);
+        }
+        let mut state = ChatState::new(&Config::default());
+        state.set_command_capture(Arc::new(Mutex::new(capture)));
+
+        state.attach_command_file(1, Some(1));
+        assert_eq!(state.pending_attachments.len(), 1);
+        assert!(state.pending_attachments[0].ends_with("(last 1 lines)\nerror: linker failed\n"));
+
+        state.attach_command_file(2, None);
+        assert_eq!(state.pending_attachments.len(), 1);
+        assert_eq!(state.status.as_deref(), Some("❌ La commande #2 n'écrit dans aucun fichier"));
+    }
+
+    #[test]
+    fn test_code_blocks_of_last_reply() {
+        let mut state = ChatState::new(&Config::default());
+        state.add_assistant_message(
+            "Deux options:\n```bash\nbrew install nmap\n```\n```bash\nsudo apt install nmap\n```".to_string(),
+            Some("toolsmith".to_string()),
+        );
+        // Notices from slash commands don't hide the reply
+        state.add_system_message("📌 Commande épinglée".to_string());
+
+        assert_eq!(state.reply_code_block(2).unwrap().code, "sudo apt install nmap\n");
+        assert_eq!(state.reply_code_block(3), None);
+        assert_eq!(state.reply_code_block(0), None);
+
+        state.start_copy_block();
+        assert!(matches!(state.mode, ChatMode::CopyBlock));
+        assert!(state.status.as_deref().unwrap().contains("1-2"));
+    }
+
+    #[test]
+    fn test_format_output_diff() {
+        let message = format_output_diff((1, "make", "ok\n"), (4, "make", "ok\n"));
+        assert_eq!(message, "🟰 Aucune différence entre les sorties de #1 et #4");
+
+        let message = format_output_diff((1, "make", "ok\n"), (4, "make -j8", "ok\nerror: race\n"));
+        let blocks = markdown::code_blocks(&message);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].lang.as_deref(), Some("diff"));
+        assert!(blocks[0].code.ends_with(" ok\n+error: race\n"));
+    }
+
+    #[test]
+    fn test_timeline_lines() {
+        let now = Local::now();
+        let mut build = CapturedCommand::new("make".to_string(), PathBuf::from("/srv"));
+        build.timestamp = now - chrono::Duration::minutes(10);
+        build.exit_code = Some(2);
+        build.finished_at = Some(build.timestamp + chrono::Duration::seconds(41));
+        let mut tests = CapturedCommand::new("make test".to_string(), PathBuf::from("/srv"));
+        tests.timestamp = now - chrono::Duration::minutes(2);
+
+        let text: Vec<String> = timeline_lines([&build, &tests], now, &Theme::default(), None)
+            .0
+            .iter()
+            .map(|line| line.spans.iter().map(|span| span.content.as_ref()).collect())
+            .collect();
+        assert!(text[0].ends_with("(10m)  #1 ✗ 2  make"));
+        assert_eq!(text[1], "  │   ⏱ 41s");
+        assert_eq!(text[3], "  ┆  7m19 sans commande");
+        assert!(text[5].ends_with("(2m)  #2 –  make test"));
+        assert_eq!(text[6], "  │   ⏱ en cours");
+    }
+
+    #[test]
+    fn test_timeline_focus_mode() {
+        let now = Local::now();
+        let mut capture = CommandCapture::new();
+        capture.process_output(
+            "\x1b]133;C;ls\x07a\r\n\x1b]133;D;0\x07\
+             \x1b]133;C;make\x07cc -c main.c\r\nerror: missing\r\n\x1b]133;D;2\x07\
+             \x1b]133;C;true\x07\x1b]133;D;0\x07",
+            &PathBuf::from("/srv"),
+        );
+        // `true` is still the current command, listed with the finished ones
+        let commands: Vec<&CapturedCommand> = (0..capture.len()).filter_map(|i| capture.get(i)).collect();
+        let mut focus = TimelineFocus::new(commands.iter().copied());
+        assert_eq!(focus, TimelineFocus { expanded: vec![false, true, false], selected: 1 });
+
+        let text = |focus: &TimelineFocus| -> (Vec<String>, Vec<usize>) {
+            let (lines, starts) = timeline_lines(commands.iter().copied(), now, &Theme::default(), Some(focus));
+            let text = lines
+                .iter()
+                .map(|line| line.spans.iter().map(|span| span.content.as_ref()).collect())
+                .collect();
+            (text, starts)
+        };
+        let (lines, starts) = text(&focus);
+        assert_eq!(starts, [0, 1, 5]);
+        assert!(lines[0].starts_with("  · #1 ✓ ") && lines[0].ends_with("  ls"));
+        assert!(lines[1].starts_with("▶ ● ") && lines[1].ends_with("#2 ✗ 2  make"));
+        assert_eq!(lines[3..5], ["  │   cc -c main.c", "  │   error: missing"]);
+        assert_eq!(lines.len(), 6);
+
+        // Unfolding a success shows it has no output
+        focus.selected = 2;
+        focus.expanded[2] = true;
+        let (lines, starts) = text(&focus);
+        assert_eq!(starts, [0, 1, 5]);
+        assert_eq!(lines.last().unwrap(), "  │   (aucune sortie)");
+    }
+
+    #[test]
+    fn test_format_stats() {
+        let stats = CaptureStats {
+            total: 12,
+            succeeded: 9,
+            failed: 2,
+            unfinished: 1,
+            output_bytes: 4096,
+            top_commands: vec![("git".to_string(), 5), ("make".to_string(), 3)],
+            session_duration: chrono::Duration::minutes(75),
+        };
+        let message = format_stats(&stats);
+        assert!(message.starts_with("📊 Session: 1h15 | 12 commande(s)\n✓ 9 réussie(s) | ✗ 2 en échec"));
+        assert!(message.ends_with("🔁 Les plus lancées:\n  git ×5\n  make ×3"));
+    }
+
+    #[test]
+    fn test_input_edits_whole_grapheme_clusters() {
+        let config = Config {
+            mock: true,
+            ..Config::default()
+        };
+        let mut state = ChatState::new(&config);
+        let family = "👨\u{200d}👩\u{200d}👧";
+        state.insert_str(&format!("a{}🇫🇷👍🏽", family));
+
+        state.backspace();
+        assert_eq!(state.input, format!("a{}🇫🇷", family));
+        state.move_cursor_left();
+        state.backspace();
+        assert_eq!(state.input, "a🇫🇷");
+        assert_eq!(state.input_cursor, 1);
+
+        state.delete_forward();
+        assert_eq!(state.input, "a");
+        state.move_cursor_left();
+        state.move_cursor_right();
+        assert_eq!(state.input_cursor, 1);
+    }
+
+    #[test]
+    fn test_idle_submit_due() {
+        let config = Config {
+            send_on_idle_ms: Some(2000),
+            ..Config::default()
+        };
+        let mut state = ChatState::new(&config);
+        let now = Instant::now();
+        assert!(!state.idle_submit_due(now + Duration::from_secs(5)));
+
+        state.insert_str("comment lister les ports ouverts");
+        assert!(!state.idle_submit_due(now + Duration::from_millis(500)));
+        assert!(state.idle_submit_due(now + Duration::from_secs(3)));
+
+        // Not while a reply is pending, nor for a half-typed slash command
+        let (_tx, rx) = mpsc::channel();
+        state.response_receiver = Some(rx);
+        assert!(!state.idle_submit_due(now + Duration::from_secs(3)));
+        state.response_receiver = None;
+        state.clear_input();
+        state.insert_str("/exp");
+        assert!(!state.idle_submit_due(now + Duration::from_secs(3)));
+
+        // Off by default
+        let mut state = ChatState::new(&Config::default());
+        state.insert_str("bonjour");
+        assert!(!state.idle_submit_due(now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_idle_close_due() {
+        let config = Config {
+            chat_idle_close_secs: Some(60),
+            ..Config::default()
+        };
+        let mut state = ChatState::new(&config);
+        let now = Instant::now();
+        state.last_interaction = now;
+        assert!(!state.idle_close_due(now + Duration::from_secs(59)));
+        assert!(state.idle_close_due(now + Duration::from_secs(60)));
+
+        // Kept open while a reply is on its way
+        let (_tx, rx) = mpsc::channel();
+        state.response_receiver = Some(rx);
+        assert!(!state.idle_close_due(now + Duration::from_secs(600)));
+
+        // Off by default
+        assert!(!ChatState::new(&Config::default()).idle_close_due(now + Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn test_visible_messages_only_covers_the_window() {
+        let mut state = ChatState::new(&Config::default());
+        state.messages.clear();
+        for i in 0..100 {
+            state.add_system_message(format!("message {}", i)); // 6 rows each
+        }
+
+        assert_eq!(state.visible_messages(0, 10), (0..2, 0));
+        // Row 301 is the second row of message 50, the window ends inside message 52
+        assert_eq!(state.visible_messages(301, 12), (50..53, 300));
+        assert_eq!(state.visible_messages(594, 6), (99..100, 594));
+        assert_eq!(state.visible_messages(600, 6), (100..100, 600));
+    }
+
+    #[test]
+    fn test_wrap_toggle_line_count_and_horizontal_scroll() {
+        let mut state = ChatState::new(&Config::default());
+        state.messages.clear();
+        state.last_visible_width = 10;
+        state.add_system_message("x".repeat(25));
+
+        // Header, blank, 3 wrapped rows, blank, separator, blank
+        assert_eq!(state.count_total_lines(), 8);
+
+        state.toggle_wrap();
+        assert_eq!(state.count_total_lines(), 6);
+
+        state.scroll_right(100);
+        assert_eq!(state.h_scroll, 15);
+        state.scroll_left(4);
+        assert_eq!(state.h_scroll, 11);
+
+        state.toggle_wrap();
+        assert_eq!(state.h_scroll, 0);
+    }
+}
@@ -0,0 +1,186 @@
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::hooks::Shell;
+
+/// Petoncle: your shell, with an AI chat one keystroke away ('!')
+#[derive(Debug, Parser)]
+#[command(name = "petoncle", version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    // Options of the interactive session, the default command
+    #[command(flatten)]
+    pub session: SessionArgs,
+
+    /// Shell to wrap
+    #[arg(long, global = true, default_value = "zsh")]
+    pub shell: Shell,
+
+    /// Address of the agent service (overrides the config file, its `backends` included, and
+    /// PETONCLE_AGENT_ADDR)
+    #[arg(long, global = true, value_name = "URL")]
+    pub agent_addr: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Check the environment (shell, config, agent service, terminal) and exit
+    Doctor,
+
+    /// Ask the agent a single question and print the answer
+    Ask {
+        /// The question, quoted or as several words
+        #[arg(required = true, trailing_var_arg = true)]
+        question: Vec<String>,
+    },
+}
+
+/// Command-line options of the interactive session
+#[derive(Debug, Args)]
+pub struct SessionArgs {
+    /// Skip the startup banner and go straight into the shell (also PETONCLE_QUIET=1)
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// Start the shell in this directory instead of the current one
+    #[arg(long, value_name = "DIR")]
+    pub cwd: Option<PathBuf>,
+
+    /// Run this command in the shell once it has started
+    #[arg(short = 'e', long = "exec", value_name = "COMMAND")]
+    pub exec: Option<String>,
+
+    /// Start the shell with only PATH and HOME from this environment (plus TERM, ZDOTDIR and
+    /// Petoncle's own variables), add more with --env
+    #[arg(long)]
+    pub clean_env: bool,
+
+    /// Set a variable in the shell's environment, can be repeated
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env_assignment)]
+    pub env: Vec<(String, String)>,
+
+    /// Record commands from the start, whatever `capture_enabled` says
+    #[arg(long, overrides_with = "no_record")]
+    pub record: bool,
+
+    /// Start with the command capture paused
+    #[arg(long, overrides_with = "record")]
+    pub no_record: bool,
+
+    /// Enable the shell marker debug panel, on `capture_debug_key` or F5 when it isn't set
+    #[arg(long)]
+    pub debug_capture: bool,
+
+    /// Mirror every keystroke to N local shells, only the first one is displayed
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(2..=8))]
+    pub split: Option<u8>,
+
+    /// Print the injected shell startup files and exit
+    #[arg(long)]
+    pub print_hooks: bool,
+
+    /// Measure the capture overhead on a scripted session and exit
+    #[arg(long, hide = true)]
+    pub bench: bool,
+
+    /// Program to wrap instead of the shell, after `--` (e.g. `petoncle -- ssh host`)
+    /// No shell hooks are injected: commands are captured only if it emits OSC 133 markers
+    #[arg(last = true, value_name = "COMMAND")]
+    pub wrap: Vec<String>,
+}
+
+impl Cli {
+    /// Parse the process arguments, PETONCLE_QUIET=1 also enables quiet mode
+    /// Exits with a usage message on invalid arguments (and for --help/--version)
+    pub fn parse_args() -> Self {
+        let mut cli = Self::parse();
+        if env_flag("PETONCLE_QUIET") {
+            cli.session.quiet = true;
+        }
+        cli
+    }
+}
+
+impl SessionArgs {
+    /// Program given after `--`, None to run the shell
+    pub fn wrapped(&self) -> Option<(&str, &[String])> {
+        let (program, args) = self.wrap.split_first()?;
+        Some((program.as_str(), args))
+    }
+
+    /// Capture state asked on the command line, None to follow the config
+    pub fn record(&self) -> Option<bool> {
+        match (self.record, self.no_record) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        }
+    }
+}
+
+/// `KEY=VALUE` from --env, the value may be empty or contain '='
+fn parse_env_assignment(assignment: &str) -> Result<(String, String), String> {
+    match assignment.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !key.contains(char::is_whitespace) => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected KEY=VALUE, got {:?}", assignment)),
+    }
+}
+
+/// Whether an environment variable is set to a truthy value
+pub fn env_flag(name: &str) -> bool {
+    matches!(
+        std::env::var(name).as_deref(),
+        Ok("1") | Ok("true") | Ok("yes")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_parse_session_and_subcommands() {
+        let cli = Cli::try_parse_from(["petoncle", "-q", "--cwd", "/tmp", "-e", "ls -la", "--no-record"]).unwrap();
+        assert!(cli.command.is_none());
+        assert!(cli.session.quiet);
+        assert_eq!(cli.session.cwd, Some(PathBuf::from("/tmp")));
+        assert_eq!(cli.session.exec.as_deref(), Some("ls -la"));
+        assert_eq!(cli.session.record(), Some(false));
+        assert!(!cli.session.debug_capture);
+        assert!(Cli::try_parse_from(["petoncle", "--debug-capture"]).unwrap().session.debug_capture);
+
+        let cli = Cli::try_parse_from(["petoncle", "ask", "comment", "lister", "les", "ports", "?"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Ask { ref question }) if question.join(" ") == "comment lister les ports ?"));
+
+        let cli = Cli::try_parse_from(["petoncle", "doctor", "--agent-addr", "http://[::1]:50052"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Doctor)));
+        assert_eq!(cli.agent_addr.as_deref(), Some("http://[::1]:50052"));
+
+        let cli = Cli::try_parse_from(["petoncle", "--clean-env", "--env", "LANG=C", "--env", "OPTS=a=b"]).unwrap();
+        assert!(cli.session.clean_env);
+        assert_eq!(
+            cli.session.env,
+            [("LANG".to_string(), "C".to_string()), ("OPTS".to_string(), "a=b".to_string())]
+        );
+        assert!(Cli::try_parse_from(["petoncle", "--env", "LANG"]).is_err());
+
+        let cli = Cli::try_parse_from(["petoncle", "-q", "--", "ssh", "-p", "2222", "host"]).unwrap();
+        assert!(cli.session.quiet);
+        assert_eq!(cli.session.wrapped(), Some(("ssh", &["-p", "2222", "host"].map(String::from)[..])));
+        assert_eq!(Cli::try_parse_from(["petoncle"]).unwrap().session.wrapped(), None);
+
+        assert!(Cli::try_parse_from(["petoncle", "--split", "1"]).is_err());
+        assert!(Cli::try_parse_from(["petoncle", "--shell", "fish"]).is_err());
+        assert!(Cli::try_parse_from(["petoncle", "doctor", "--quiet"]).is_err());
+    }
+}
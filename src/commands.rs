@@ -0,0 +1,327 @@
+/// Names of the available slash commands, used for Tab completion
+const COMMAND_NAMES: &[&str] = &[
+    "attach",
+    "attach-tail",
+    "backend",
+    "commands",
+    "diff",
+    "export-script",
+    "here",
+    "logs",
+    "nocontext",
+    "note",
+    "output",
+    "pin",
+    "snippet",
+    "stats",
+    "summarize",
+    "timeline",
+];
+
+/// Slash commands typed in the chat input (e.g. `/backend local`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatCommand {
+    /// Switch the active agent backend, or list them when no name is given
+    Backend(Option<String>),
+
+    /// Attach a file to the next message as context
+    Attach(String),
+
+    /// Attach the end of the file a captured command wrote to (`> file`, `tee file`),
+    /// optionally with a number of lines
+    AttachTail(usize, Option<usize>),
+
+    /// Show the last lines of the session log
+    Logs,
+
+    /// Open the palette searching the captured commands, optionally with a first query
+    Commands(Option<String>),
+
+    /// Show the captured output of a command (1 = first of the session, default: last)
+    Output(Option<usize>),
+
+    /// Pin or unpin a captured command (same numbering as /output)
+    Pin(usize),
+
+    /// Annotate a captured command, an empty note removes it
+    Note(usize, Option<String>),
+
+    /// Ask the agent to summarize a captured command's output (default: last)
+    Summarize(Option<usize>),
+
+    /// Compare the outputs of two captured commands (same numbering as /output)
+    Diff(usize, usize),
+
+    /// Show counts over the captured commands
+    Stats,
+
+    /// Show the captured commands on a timeline, with durations and exit statuses
+    Timeline,
+
+    /// Send the commands captured in the shell's current directory with each message, or stop
+    Here,
+
+    /// Send the next message (or the given one right away) without any context
+    NoContext(Option<String>),
+
+    /// Write the captured commands to a shell script (default path from the config)
+    /// `--all` keeps the failed commands
+    ExportScript { path: Option<String>, all: bool },
+
+    /// Turn one captured command (default: last) into a reproducible snippet, copied to the
+    /// clipboard or written to a file
+    Snippet { index: Option<usize>, path: Option<String> },
+}
+
+impl ChatCommand {
+    /// Parse a chat input line
+    /// Returns None when the input is a regular message, Some(Err) for an unknown or malformed command
+    pub fn parse(input: &str) -> Option<Result<Self, String>> {
+        let rest = input.trim().strip_prefix('/')?;
+        let (name, args) = match rest.split_once(char::is_whitespace) {
+            Some((name, args)) => (name, args.trim()),
+            None => (rest, ""),
+        };
+
+        Some(match name {
+            "backend" => Ok(ChatCommand::Backend(optional_arg(args))),
+            "logs" => Ok(ChatCommand::Logs),
+            "stats" => Ok(ChatCommand::Stats),
+            "timeline" => Ok(ChatCommand::Timeline),
+            "here" => Ok(ChatCommand::Here),
+            "nocontext" => Ok(ChatCommand::NoContext(optional_arg(args))),
+            "commands" => Ok(ChatCommand::Commands(optional_arg(args))),
+            "output" => optional_index(args).map(ChatCommand::Output),
+            "summarize" => optional_index(args).map(ChatCommand::Summarize),
+            "diff" => match args.split_whitespace().collect::<Vec<_>>()[..] {
+                [first, second] => match (optional_index(first), optional_index(second)) {
+                    (Ok(Some(first)), Ok(Some(second))) => Ok(ChatCommand::Diff(first, second)),
+                    (Err(e), _) | (_, Err(e)) => Err(e),
+                    _ => Err("Usage: /diff <numéro> <numéro>".to_string()),
+                },
+                _ => Err("Usage: /diff <numéro> <numéro>".to_string()),
+            },
+            "pin" => optional_index(args)
+                .and_then(|index| index.ok_or_else(|| "Usage: /pin <numéro>".to_string()))
+                .map(ChatCommand::Pin),
+            "note" => {
+                let (index, text) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                optional_index(index)
+                    .and_then(|index| index.ok_or_else(|| "Usage: /note <numéro> [texte]".to_string()))
+                    .map(|index| ChatCommand::Note(index, optional_arg(text.trim())))
+            }
+            "export-script" => {
+                let (all, path) = match args.strip_prefix("--all") {
+                    Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => (true, rest.trim()),
+                    _ => (false, args),
+                };
+                Ok(ChatCommand::ExportScript {
+                    path: optional_arg(path),
+                    all,
+                })
+            }
+            "snippet" => {
+                let (index, path) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                if index.chars().all(|c| c.is_ascii_digit()) {
+                    optional_index(index).map(|index| ChatCommand::Snippet {
+                        index,
+                        path: optional_arg(path.trim()),
+                    })
+                } else {
+                    Ok(ChatCommand::Snippet {
+                        index: None,
+                        path: optional_arg(args),
+                    })
+                }
+            }
+            "attach-tail" => {
+                let (index, lines) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                let lines = match lines.trim() {
+                    "" => Ok(None),
+                    lines => lines
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .map(Some)
+                        .ok_or_else(|| format!("Nombre de lignes invalide: {}", lines)),
+                };
+                optional_index(index)
+                    .and_then(|index| index.ok_or_else(|| "Usage: /attach-tail <numéro> [lignes]".to_string()))
+                    .and_then(|index| Ok(ChatCommand::AttachTail(index, lines?)))
+            }
+            "attach" => optional_arg(args)
+                .map(ChatCommand::Attach)
+                .ok_or_else(|| "Usage: /attach <chemin>".to_string()),
+            _ => Err(format!("Commande inconnue: /{}", name)),
+        })
+    }
+
+    /// Whether the command refers to captured commands by their 1-based number
+    pub fn uses_numbers(&self) -> bool {
+        matches!(
+            self,
+            ChatCommand::AttachTail(..)
+                | ChatCommand::Output(Some(_))
+                | ChatCommand::Pin(_)
+                | ChatCommand::Note(..)
+                | ChatCommand::Summarize(Some(_))
+                | ChatCommand::Diff(..)
+                | ChatCommand::Snippet { index: Some(_), .. }
+        )
+    }
+
+    /// Complete a command name prefix when exactly one command matches
+    pub fn complete(prefix: &str) -> Option<&'static str> {
+        let mut matches = COMMAND_NAMES.iter().filter(|name| name.starts_with(prefix));
+        match (matches.next(), matches.next()) {
+            (Some(name), None) => Some(*name),
+            _ => None,
+        }
+    }
+}
+
+/// Parse an optional 1-based command index
+fn optional_index(args: &str) -> Result<Option<usize>, String> {
+    if args.is_empty() {
+        return Ok(None);
+    }
+    match args.parse::<usize>() {
+        Ok(index) if index > 0 => Ok(Some(index)),
+        _ => Err(format!("Numéro de commande invalide: {}", args)),
+    }
+}
+
+/// Turn an empty argument string into None
+fn optional_arg(args: &str) -> Option<String> {
+    if args.is_empty() {
+        None
+    } else {
+        Some(args.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_regular_message() {
+        assert_eq!(ChatCommand::parse("how do I use nmap?"), None);
+    }
+
+    #[test]
+    fn test_parse_backend() {
+        assert_eq!(
+            ChatCommand::parse("/backend local"),
+            Some(Ok(ChatCommand::Backend(Some("local".to_string()))))
+        );
+        assert_eq!(ChatCommand::parse("/backend"), Some(Ok(ChatCommand::Backend(None))));
+    }
+
+    #[test]
+    fn test_parse_attach() {
+        assert_eq!(
+            ChatCommand::parse("/attach ~/.ssh/config"),
+            Some(Ok(ChatCommand::Attach("~/.ssh/config".to_string())))
+        );
+        assert!(matches!(ChatCommand::parse("/attach"), Some(Err(_))));
+
+        assert_eq!(ChatCommand::parse("/attach-tail 4"), Some(Ok(ChatCommand::AttachTail(4, None))));
+        assert_eq!(ChatCommand::parse("/attach-tail 4 200"), Some(Ok(ChatCommand::AttachTail(4, Some(200)))));
+        assert!(matches!(ChatCommand::parse("/attach-tail"), Some(Err(_))));
+        assert!(matches!(ChatCommand::parse("/attach-tail 4 beaucoup"), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_parse_output() {
+        assert_eq!(ChatCommand::parse("/output 3"), Some(Ok(ChatCommand::Output(Some(3)))));
+        assert_eq!(ChatCommand::parse("/output"), Some(Ok(ChatCommand::Output(None))));
+        assert!(matches!(ChatCommand::parse("/output 0"), Some(Err(_))));
+        assert!(matches!(ChatCommand::parse("/output abc"), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_parse_pin() {
+        assert_eq!(ChatCommand::parse("/pin 2"), Some(Ok(ChatCommand::Pin(2))));
+        assert!(matches!(ChatCommand::parse("/pin"), Some(Err(_))));
+        assert_eq!(ChatCommand::parse("/summarize 4"), Some(Ok(ChatCommand::Summarize(Some(4)))));
+        assert_eq!(ChatCommand::parse("/stats"), Some(Ok(ChatCommand::Stats)));
+        assert_eq!(ChatCommand::parse("/here"), Some(Ok(ChatCommand::Here)));
+        assert_eq!(ChatCommand::parse("/nocontext"), Some(Ok(ChatCommand::NoContext(None))));
+        assert_eq!(
+            ChatCommand::parse("/nocontext c'est quoi un inode ?"),
+            Some(Ok(ChatCommand::NoContext(Some("c'est quoi un inode ?".to_string()))))
+        );
+        assert_eq!(ChatCommand::parse("/diff 3 7"), Some(Ok(ChatCommand::Diff(3, 7))));
+        assert!(matches!(ChatCommand::parse("/diff 3"), Some(Err(_))));
+        assert!(matches!(ChatCommand::parse("/diff 0 2"), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_parse_note() {
+        assert_eq!(
+            ChatCommand::parse("/note 2 étape de repro"),
+            Some(Ok(ChatCommand::Note(2, Some("étape de repro".to_string()))))
+        );
+        assert_eq!(ChatCommand::parse("/note 2"), Some(Ok(ChatCommand::Note(2, None))));
+        assert!(matches!(ChatCommand::parse("/note"), Some(Err(_))));
+        assert!(matches!(ChatCommand::parse("/note repro"), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_parse_export_script() {
+        assert_eq!(
+            ChatCommand::parse("/export-script ~/repro.sh"),
+            Some(Ok(ChatCommand::ExportScript {
+                path: Some("~/repro.sh".to_string()),
+                all: false
+            }))
+        );
+        assert_eq!(
+            ChatCommand::parse("/export-script --all"),
+            Some(Ok(ChatCommand::ExportScript { path: None, all: true }))
+        );
+        assert_eq!(
+            ChatCommand::parse("/export-script --allowed.sh"),
+            Some(Ok(ChatCommand::ExportScript {
+                path: Some("--allowed.sh".to_string()),
+                all: false
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_snippet() {
+        assert_eq!(
+            ChatCommand::parse("/snippet"),
+            Some(Ok(ChatCommand::Snippet { index: None, path: None }))
+        );
+        assert_eq!(
+            ChatCommand::parse("/snippet 3 ~/bug.sh"),
+            Some(Ok(ChatCommand::Snippet {
+                index: Some(3),
+                path: Some("~/bug.sh".to_string())
+            }))
+        );
+        assert_eq!(
+            ChatCommand::parse("/snippet ~/bug.sh"),
+            Some(Ok(ChatCommand::Snippet {
+                index: None,
+                path: Some("~/bug.sh".to_string())
+            }))
+        );
+        assert!(matches!(ChatCommand::parse("/snippet 0"), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_complete() {
+        assert_eq!(ChatCommand::complete("back"), Some("backend"));
+        assert_eq!(ChatCommand::complete("com"), Some("commands"));
+        assert_eq!(ChatCommand::complete("zzz"), None);
+    }
+
+    #[test]
+    fn test_parse_unknown() {
+        assert!(matches!(ChatCommand::parse("/nope"), Some(Err(_))));
+    }
+}
@@ -0,0 +1,288 @@
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::Alignment,
+    style::{Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Wrap},
+    Terminal,
+};
+use std::path::Path;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::Duration;
+
+use crate::keys;
+use crate::markdown;
+use crate::theme::Theme;
+
+/// Recent commands sent along with a completion request
+pub const COMPLETION_CONTEXT_COMMANDS: usize = 5;
+
+/// The command line being typed at the prompt, rebuilt from the keystrokes sent to the shell
+/// Only plain typing is followed: anything the shell edits on its own (history, Tab completion,
+/// cursor motion) makes the line unknown until the next one starts
+#[derive(Debug, Default)]
+pub struct LineTracker {
+    line: String,
+    unknown: bool,
+}
+
+impl LineTracker {
+    /// Follow bytes written to the shell (one key, or text inserted by Petoncle)
+    pub fn feed(&mut self, bytes: &[u8]) {
+        match bytes {
+            // Enter runs the line, Ctrl+C drops it: a new one starts either way
+            b"\r" | b"\n" | [3] => self.reset(),
+            // Ctrl+U kills the whole line, what follows is known again
+            [21] => self.reset(),
+            [127] | [8] => {
+                self.line.pop();
+            }
+            // Ctrl+W: previous word
+            [23] => {
+                let kept = self.line.trim_end().rfind(' ').map_or(0, |i| i + 1);
+                self.line.truncate(kept);
+            }
+            _ => match std::str::from_utf8(bytes) {
+                Ok(text) if !text.chars().any(char::is_control) => self.line.push_str(text),
+                _ => self.unknown = true,
+            },
+        }
+    }
+
+    /// Follow a paste, a multi-line one runs commands and leaves the line unknown
+    pub fn paste(&mut self, text: &str) {
+        if text.contains(['\n', '\r']) || text.chars().any(char::is_control) {
+            self.unknown = true;
+        } else {
+            self.line.push_str(text);
+        }
+    }
+
+    /// Start a new, empty line
+    pub fn reset(&mut self) {
+        self.line.clear();
+        self.unknown = false;
+    }
+
+    /// The typed line, None when it can't be trusted
+    pub fn current(&self) -> Option<&str> {
+        if self.unknown { None } else { Some(&self.line) }
+    }
+}
+
+/// Question asking the agent to finish a command line
+pub fn completion_prompt(line: &str, cwd: &Path) -> String {
+    format!(
+        "Complète cette commande shell (zsh) en cours de saisie. Réponds uniquement par la \
+         commande complète, sur une seule ligne, sans explication.\n\
+         Répertoire courant: {}\n\
+         Commande: {}",
+        cwd.display(),
+        line
+    )
+}
+
+/// A completion proposed by the agent
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    /// The whole suggested command line
+    pub command: String,
+
+    /// What to type after the current line, None when the agent rewrote its beginning
+    pub suffix: Option<String>,
+}
+
+impl Completion {
+    /// Read the agent's reply: the first code block if any, else the first non-empty line
+    /// Control characters are refused, a reply must never run anything by itself
+    pub fn from_reply(line: &str, reply: &str) -> Option<Self> {
+        let blocks = markdown::code_blocks(reply);
+        let text = blocks.first().map_or(reply, |block| block.code.as_str());
+        let command = text.lines().map(str::trim).find(|l| !l.is_empty())?;
+        let command = command.trim_matches('`').trim();
+        let command = command.strip_prefix("$ ").unwrap_or(command).to_string();
+        if command.is_empty() || command.chars().any(char::is_control) {
+            return None;
+        }
+
+        let suffix = command.strip_prefix(line).map(str::to_string);
+        if suffix.as_deref() == Some("") {
+            return None;
+        }
+        Some(Self { command, suffix })
+    }
+
+    /// Bytes written to the shell: the rest of the line, or the whole line after Ctrl+U
+    /// No Enter: the user reviews the command and runs it
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self.suffix {
+            Some(ref suffix) => suffix.clone().into_bytes(),
+            None => format!("\x15{}", self.command).into_bytes(),
+        }
+    }
+}
+
+/// Where the completion request stands
+enum CompletionState {
+    Waiting,
+    Ready(Completion),
+    Failed(String),
+}
+
+/// Wait for the agent's completion and ask whether to insert it
+/// Returns the completion to insert, None when cancelled or when the agent had nothing useful
+pub fn confirm_completion(
+    terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>,
+    line: &str,
+    reply: Receiver<Result<String>>,
+    theme: &Theme,
+) -> Result<Option<Completion>> {
+    let mut state = CompletionState::Waiting;
+    loop {
+        if matches!(state, CompletionState::Waiting) {
+            state = match reply.try_recv() {
+                Ok(Ok(text)) => match Completion::from_reply(line, &text) {
+                    Some(completion) => CompletionState::Ready(completion),
+                    None => CompletionState::Failed("Aucune complétion proposée".to_string()),
+                },
+                Ok(Err(e)) => CompletionState::Failed(e.to_string()),
+                Err(TryRecvError::Empty) => CompletionState::Waiting,
+                Err(TryRecvError::Disconnected) => {
+                    CompletionState::Failed("Requête interrompue".to_string())
+                }
+            };
+        }
+
+        terminal.draw(|frame| {
+            let hint = Style::default().fg(theme.highlight);
+            let mut lines = vec![
+                Line::from(vec![
+                    Span::raw("Commande: "),
+                    Span::styled(line.to_string(), Style::default().fg(theme.logs_text)),
+                ]),
+                Line::from(""),
+            ];
+            match state {
+                CompletionState::Waiting => {
+                    lines.push(Line::from(Span::styled("⏳ Demande à l'agent…", hint)));
+                    lines.push(Line::from(""));
+                    lines.push(Line::from(Span::styled("[Esc] Annuler", hint)));
+                }
+                CompletionState::Ready(ref completion) => {
+                    let suggestion = match completion.suffix {
+                        Some(ref suffix) => vec![
+                            Span::raw(line.to_string()),
+                            Span::styled(
+                                suffix.clone(),
+                                Style::default().fg(theme.assistant).add_modifier(Modifier::BOLD),
+                            ),
+                        ],
+                        None => vec![Span::styled(
+                            completion.command.clone(),
+                            Style::default().fg(theme.assistant).add_modifier(Modifier::BOLD),
+                        )],
+                    };
+                    lines.push(Line::from(suggestion));
+                    if completion.suffix.is_none() {
+                        lines.push(Line::from(Span::styled(
+                            "(remplace la ligne tapée)",
+                            Style::default().fg(theme.badges.system),
+                        )));
+                    }
+                    lines.push(Line::from(""));
+                    lines.push(Line::from(Span::styled("[Entrée/o/y] Insérer   [n/Esc] Annuler", hint)));
+                }
+                CompletionState::Failed(ref error) => {
+                    let error_style = Style::default().fg(theme.badges.error);
+                    lines.push(Line::from(Span::styled(format!("❌ {}", error), error_style)));
+                    lines.push(Line::from(""));
+                    lines.push(Line::from(Span::styled("[Esc] Fermer", hint)));
+                }
+            }
+
+            let paragraph = Paragraph::new(lines)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(theme.chat_border))
+                        .title("✨ Compléter la commande")
+                        .title_alignment(Alignment::Center),
+                )
+                .wrap(Wrap { trim: false });
+            frame.render_widget(paragraph, frame.area());
+        })?;
+
+        if !event::poll(Duration::from_millis(100))? {
+            continue;
+        }
+        if let Event::Key(key_event) = event::read()? {
+            if !keys::is_key_input(&key_event) {
+                continue;
+            }
+            let accept = matches!(key_event.code, KeyCode::Enter | KeyCode::Char('o') | KeyCode::Char('y'));
+            match (key_event.code, &state) {
+                (_, CompletionState::Ready(completion)) if accept => return Ok(Some(completion.clone())),
+                (KeyCode::Char('n') | KeyCode::Esc, _) => return Ok(None),
+                (_, CompletionState::Failed(_)) => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(tracker: &mut LineTracker, text: &str) {
+        for c in text.chars() {
+            tracker.feed(c.to_string().as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_line_tracker() {
+        let mut tracker = LineTracker::default();
+        typed(&mut tracker, "git comit");
+        tracker.feed(&[127]);
+        tracker.feed(&[127]);
+        typed(&mut tracker, "mit -");
+        assert_eq!(tracker.current(), Some("git commit -"));
+
+        tracker.feed(&[23]);
+        assert_eq!(tracker.current(), Some("git commit "));
+
+        // History recall: the shell changed the line behind our back
+        tracker.feed(b"\x1b[A");
+        assert_eq!(tracker.current(), None);
+        tracker.feed(&[9]);
+        assert_eq!(tracker.current(), None);
+
+        tracker.feed(b"\r");
+        assert_eq!(tracker.current(), Some(""));
+        tracker.paste("docker ps");
+        assert_eq!(tracker.current(), Some("docker ps"));
+        tracker.paste("ls\nrm x");
+        assert_eq!(tracker.current(), None);
+        tracker.feed(&[21]);
+        assert_eq!(tracker.current(), Some(""));
+    }
+
+    #[test]
+    fn test_completion_from_reply() {
+        let completion = Completion::from_reply("tar -x", "```sh\ntar -xzf archive.tar.gz\n```").unwrap();
+        assert_eq!(completion.suffix.as_deref(), Some("zf archive.tar.gz"));
+        assert_eq!(completion.to_bytes(), b"zf archive.tar.gz");
+
+        // Rewritten beginning: the whole line is replaced
+        let completion = Completion::from_reply("git comit", "`git commit --amend`").unwrap();
+        assert_eq!(completion.suffix, None);
+        assert_eq!(completion.to_bytes(), b"\x15git commit --amend");
+
+        assert_eq!(Completion::from_reply("ls", "ls"), None);
+        assert_eq!(Completion::from_reply("ls", "\n\n"), None);
+        assert_eq!(Completion::from_reply("rm", "rm -rf /tmp/x\x1b[2J"), None);
+    }
+}
@@ -0,0 +1,549 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::capture::{ContextStrategy, DEFAULT_MAX_COMMANDS, DEFAULT_MAX_COMMAND_BYTES};
+use crate::chat::TimestampFormat;
+use crate::cli::env_flag;
+use crate::keys::{KeyBinding, KeyChord};
+use crate::theme::ThemeConfig;
+use crate::pending::PendingOutputMode;
+use crate::trigger::ChatTrigger;
+use std::path::PathBuf;
+use tracing::debug;
+
+/// Default address of the Python agent service
+pub const DEFAULT_AGENT_ADDR: &str = "127.0.0.1:50051";
+
+/// Default gRPC message size limit (tonic's own default is 4MB for responses)
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+
+/// A named agent service the chat can send messages to
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackendConfig {
+    /// Name used by `/backend <name>` and shown in the chat title
+    pub name: String,
+
+    /// Address of the gRPC service (host:port)
+    pub address: String,
+
+    /// Optional bearer token sent with every request
+    #[serde(default)]
+    pub token: Option<String>,
+
+    /// Largest gRPC message sent or received, in bytes
+    #[serde(default = "default_max_message_bytes")]
+    pub max_message_bytes: usize,
+
+    /// Deadlines, shared by every backend (copied from the `[timeouts]` section)
+    #[serde(skip)]
+    pub timeouts: TimeoutConfig,
+}
+
+fn default_max_message_bytes() -> usize {
+    DEFAULT_MAX_MESSAGE_BYTES
+}
+
+/// Deadlines for talking to an agent service, in seconds
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// Deadline for one chat request, the agent may need a while to answer
+    pub request_secs: u64,
+
+    /// Deadline for establishing the connection
+    pub connect_secs: u64,
+
+    /// Deadline the channel applies to every call, a backstop above `request_secs` by default
+    /// (the shorter of the two wins)
+    pub channel_secs: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            request_secs: 45,
+            connect_secs: 5,
+            channel_secs: 120,
+        }
+    }
+}
+
+impl TimeoutConfig {
+    /// Reject zero deadlines, which would make every request fail immediately
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("request_secs", self.request_secs),
+            ("connect_secs", self.connect_secs),
+            ("channel_secs", self.channel_secs),
+        ] {
+            if value == 0 {
+                bail!("timeouts.{} must be greater than 0", name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Deadline a chat request actually gets
+    pub fn effective_request_secs(&self) -> u64 {
+        self.request_secs.min(self.channel_secs)
+    }
+}
+
+/// Where the chat overlay is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatScreen {
+    /// Alternate screen unless the terminal is known not to support it
+    #[default]
+    Auto,
+
+    /// Alternate screen, the shell screen is restored untouched when the chat closes
+    Alternate,
+
+    /// Region drawn below the prompt, for terminals without a reliable alternate screen
+    Inline,
+}
+
+impl ChatScreen {
+    /// Settle `Auto` from $TERM: dumb terminals and the Linux console have no alternate screen
+    pub fn resolve(self, term: Option<&str>) -> Self {
+        match self {
+            ChatScreen::Auto => match term {
+                None | Some("") | Some("dumb") => ChatScreen::Inline,
+                Some(term) if term.starts_with("linux") => ChatScreen::Inline,
+                Some(_) => ChatScreen::Alternate,
+            },
+            other => other,
+        }
+    }
+}
+
+/// JSONL record of captured commands, kept across sessions
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Write captured commands to the history file (off by default, outputs may hold secrets)
+    pub enabled: bool,
+
+    /// History file (default: $XDG_DATA_HOME/petoncle/history.jsonl or ~/.local/share/...)
+    pub path: Option<PathBuf>,
+
+    /// Seconds between checkpoints of a command that is still running
+    pub checkpoint_secs: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            checkpoint_secs: 5,
+        }
+    }
+}
+
+impl HistoryConfig {
+    /// Configured path, or the default one in the data directory
+    pub fn resolved_path(&self) -> Option<PathBuf> {
+        if let Some(ref path) = self.path {
+            return Some(path.clone());
+        }
+        let base = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))?;
+        Some(base.join("petoncle").join("history.jsonl"))
+    }
+}
+
+/// Recording of the session's terminal output as an asciicast v2 file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CastConfig {
+    /// Cast file written during the session (`~/` is expanded), no recording when unset
+    pub path: Option<String>,
+
+    /// Leave the OSC 133 markers written by Petoncle's hooks out of the recording; markers of a
+    /// program wrapped with `--` are always kept, they aren't Petoncle's
+    pub strip_markers: bool,
+}
+
+impl Default for CastConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            strip_markers: true,
+        }
+    }
+}
+
+/// `/export-script`: captured commands written as a replayable shell script
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ExportConfig {
+    /// Script written when `/export-script` gets no path (`~/` is expanded, relative to the launch directory)
+    pub script_path: String,
+
+    /// Leave failed and unfinished commands out of the script (`/export-script --all` keeps them)
+    pub skip_failed: bool,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            script_path: "petoncle-session.sh".to_string(),
+            skip_failed: true,
+        }
+    }
+}
+
+/// Safety net for dangerous commands pasted into the shell
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PasteGuardConfig {
+    /// Ask for confirmation before forwarding a matching paste (off by default)
+    pub enabled: bool,
+
+    /// Substrings that make a paste dangerous
+    pub patterns: Vec<String>,
+}
+
+impl Default for PasteGuardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            patterns: [
+                "rm -rf /",
+                "rm -rf ~",
+                "mkfs",
+                "dd if=",
+                "> /dev/sd",
+                ":(){ :|:& };:",
+                "chmod -R 777 /",
+            ]
+            .iter()
+            .map(|p| p.to_string())
+            .collect(),
+        }
+    }
+}
+
+/// User configuration, loaded from `~/.config/petoncle/config.toml`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Address of the default agent service
+    pub agent_addr: String,
+
+    /// Largest gRPC message for the default agent service, in bytes
+    pub max_message_bytes: usize,
+
+    /// Deadlines for every agent backend
+    pub timeouts: TimeoutConfig,
+
+    /// Named agent backends (the default address is used when empty)
+    pub backends: Vec<BackendConfig>,
+
+    /// Confirmation before forwarding dangerous pastes
+    pub paste_guard: PasteGuardConfig,
+
+    /// Ask the agent for a summary of the session when the shell exits
+    pub summary_on_exit: bool,
+
+    /// Maximum bytes of captured commands sent as context
+    pub context_budget: usize,
+
+    /// Maximum number of captured commands sent as context
+    pub context_commands: usize,
+
+    /// Which captured commands are sent as context
+    pub context_strategy: ContextStrategy,
+
+    /// Send the notes attached to commands (/note) as context
+    pub context_notes: bool,
+
+    /// Finished commands kept in memory, the oldest unpinned ones are dropped first
+    pub max_commands: usize,
+
+    /// Record command output (false: only commands, exit codes and times reach the agent)
+    pub capture_output: bool,
+
+    /// Longest command line recorded, in bytes (longer ones are cut)
+    pub max_command_bytes: usize,
+
+    /// Record commands from the start of the session (toggled at runtime with `capture_key`)
+    pub capture_enabled: bool,
+
+    /// Hotkey pausing and resuming the command capture, e.g. for sensitive work
+    /// Only taken at the prompt: while a command runs it goes to that program
+    pub capture_key: KeyBinding,
+
+    /// Persistent command history
+    pub history: HistoryConfig,
+
+    /// Show the context (captured commands, output, attached files) before each message and send
+    /// it only once confirmed
+    pub confirm_context: bool,
+
+    /// Shell command run (`sh -c`) whenever a captured command exits non-zero, with the failure
+    /// as JSON on its stdin (command, exit_code, working_dir, output...)
+    pub on_failure: Option<String>,
+
+    /// Shell script export of the captured commands
+    pub export: ExportConfig,
+
+    /// asciicast recording of the session
+    pub cast: CastConfig,
+
+    /// Chat timestamp format: a strftime string or "relative"
+    pub timestamp_format: TimestampFormat,
+
+    /// Bytes of recent shell output kept in memory
+    pub scrollback_bytes: usize,
+
+    /// Hotkey opening the shell output viewer (e.g. "f2", "ctrl+o", "alt+s")
+    pub scrollback_key: KeyBinding,
+
+    /// Hotkey asking the agent to finish the command typed at the prompt
+    /// Only taken at the prompt: while a command runs it goes to that program
+    pub complete_key: KeyBinding,
+
+    /// Hotkey showing the shell integration markers as they are parsed (capture not working?)
+    /// Unbound by default so the key stays with full-screen programs, `--debug-capture` binds F5
+    pub capture_debug_key: Option<KeyBinding>,
+
+    /// How '!' opens the chat: "single", "double" (two quick presses) or "off"
+    pub chat_trigger: ChatTrigger,
+
+    /// Other keys opening the chat, alone ("f6") or as a two-key chord ("ctrl+a c") for a tmux
+    /// style prefix
+    pub chat_keys: Vec<KeyChord>,
+
+    /// Longest wait for the second key of a chord, in milliseconds, before both go to the shell
+    pub chord_timeout_ms: u64,
+
+    /// Shell output arriving while the chat is open: "replay" it on exit, only "notify" or "off"
+    pub pending_output: PendingOutputMode,
+
+    /// Send the chat message by itself once the input has been left unchanged this many
+    /// milliseconds (dictation, paste-then-wait), off when unset
+    pub send_on_idle_ms: Option<u64>,
+
+    /// Close the chat and go back to the shell after this many seconds without input (kiosk,
+    /// shared screens), never while a reply is awaited; off when unset
+    pub chat_idle_close_secs: Option<u64>,
+
+    /// Most requests sent to the agent in any minute (messages, regenerations, summaries), extra
+    /// ones are refused with a notice; no limit when unset or 0
+    pub max_requests_per_minute: Option<u32>,
+
+    /// How many lines above the bottom of the chat still follow new messages (default: one screen)
+    pub auto_scroll_threshold_lines: Option<u16>,
+
+    /// Display name of the user in the chat
+    pub user_name: String,
+
+    /// Display name of the assistant in the chat
+    pub assistant_name: String,
+
+    /// Chat colors: a preset and individual overrides
+    pub theme: ThemeConfig,
+
+    /// Draw the chat on the alternate screen or inline ("auto", "alternate", "inline")
+    pub chat_screen: ChatScreen,
+
+    /// Serve the JSON control socket for external tools (path exported as $PETONCLE_SOCKET)
+    pub control_socket: bool,
+
+    /// Answer chat messages with canned responses instead of calling the agent service
+    pub mock: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            agent_addr: DEFAULT_AGENT_ADDR.to_string(),
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            timeouts: TimeoutConfig::default(),
+            backends: Vec::new(),
+            paste_guard: PasteGuardConfig::default(),
+            summary_on_exit: false,
+            context_budget: 8_000,
+            context_commands: 20,
+            context_strategy: ContextStrategy::default(),
+            context_notes: true,
+            max_commands: DEFAULT_MAX_COMMANDS,
+            capture_output: true,
+            max_command_bytes: DEFAULT_MAX_COMMAND_BYTES,
+            capture_enabled: true,
+            capture_key: KeyBinding::function_key(3),
+            history: HistoryConfig::default(),
+            confirm_context: false,
+            on_failure: None,
+            export: ExportConfig::default(),
+            cast: CastConfig::default(),
+            timestamp_format: TimestampFormat::default(),
+            scrollback_bytes: 100_000,
+            scrollback_key: KeyBinding::default(),
+            complete_key: KeyBinding::function_key(4),
+            capture_debug_key: None,
+            chat_trigger: ChatTrigger::default(),
+            chat_keys: Vec::new(),
+            chord_timeout_ms: 1000,
+            pending_output: PendingOutputMode::default(),
+            send_on_idle_ms: None,
+            chat_idle_close_secs: None,
+            max_requests_per_minute: Some(20),
+            auto_scroll_threshold_lines: None,
+            user_name: "You".to_string(),
+            assistant_name: "Petoncle".to_string(),
+            theme: ThemeConfig::default(),
+            chat_screen: ChatScreen::default(),
+            control_socket: false,
+            mock: false,
+        }
+    }
+}
+
+impl Config {
+    /// Location of the config file ($XDG_CONFIG_HOME or ~/.config)
+    pub fn path() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(base.join("petoncle").join("config.toml"))
+    }
+
+    /// Load the config file, falling back to defaults when it doesn't exist
+    /// PETONCLE_AGENT_ADDR overrides the default agent address, PETONCLE_MOCK=1 enables mock mode
+    pub fn load() -> Result<Self> {
+        let mut config = match Self::path() {
+            Some(path) if path.exists() => {
+                debug!("Loading config from {}", path.display());
+                let content = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                toml::from_str(&content)
+                    .with_context(|| format!("Invalid config file {}", path.display()))?
+            }
+            _ => Self::default(),
+        };
+
+        if let Ok(addr) = std::env::var("PETONCLE_AGENT_ADDR") {
+            config.agent_addr = addr;
+        }
+        if env_flag("PETONCLE_MOCK") {
+            config.mock = true;
+        }
+
+        config.timeouts.validate().context("Invalid config")?;
+
+        Ok(config)
+    }
+
+    /// Point the chat at the agent given on the command line, in place of any configured `backends`
+    pub fn override_agent_addr(&mut self, addr: &str) {
+        if !self.backends.is_empty() {
+            debug!("--agent-addr given, ignoring {} configured backend(s)", self.backends.len());
+            self.backends.clear();
+        }
+        self.agent_addr = addr.to_string();
+    }
+
+    /// Backends available to the chat, in cycling order
+    pub fn backends(&self) -> Vec<BackendConfig> {
+        if self.backends.is_empty() {
+            vec![BackendConfig {
+                name: "default".to_string(),
+                address: self.agent_addr.clone(),
+                token: None,
+                max_message_bytes: self.max_message_bytes,
+                timeouts: self.timeouts,
+            }]
+        } else {
+            self.backends
+                .iter()
+                .cloned()
+                .map(|backend| BackendConfig {
+                    timeouts: self.timeouts,
+                    ..backend
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeouts_from_toml() {
+        let config: Config = toml::from_str("[timeouts]\nrequest_secs = 120\n").unwrap();
+        assert_eq!(config.timeouts.request_secs, 120);
+        assert_eq!(config.timeouts.connect_secs, 5);
+        assert_eq!(config.backends()[0].timeouts, config.timeouts);
+        assert_eq!(config.timeouts.effective_request_secs(), 120);
+
+        // With the defaults the request deadline is the one that applies
+        assert_eq!(TimeoutConfig::default().effective_request_secs(), 45);
+        let short_channel = TimeoutConfig {
+            channel_secs: 10,
+            ..TimeoutConfig::default()
+        };
+        assert_eq!(short_channel.effective_request_secs(), 10);
+
+        let zero = TimeoutConfig {
+            connect_secs: 0,
+            ..TimeoutConfig::default()
+        };
+        assert!(zero.validate().unwrap_err().to_string().contains("connect_secs"));
+    }
+
+    #[test]
+    fn test_agent_addr_override_replaces_backends() {
+        let mut config: Config = toml::from_str(
+            "[[backends]]\nname = \"local\"\naddress = \"http://127.0.0.1:50051\"\n\n\
+             [[backends]]\nname = \"remote\"\naddress = \"http://10.0.0.2:50051\"\n",
+        )
+        .unwrap();
+        config.override_agent_addr("http://[::1]:50052");
+
+        let backends = config.backends();
+        assert_eq!(backends.len(), 1);
+        assert_eq!(backends[0].address, "http://[::1]:50052");
+    }
+
+    #[test]
+    fn test_chat_trigger_from_toml() {
+        let config: Config = toml::from_str("chat_trigger = \"double\"\n").unwrap();
+        assert_eq!(config.chat_trigger, ChatTrigger::Double);
+        assert_eq!(Config::default().chat_trigger, ChatTrigger::Single);
+
+        let config: Config = toml::from_str("chat_keys = [\"f6\", \"ctrl+a c\"]\n").unwrap();
+        assert_eq!(config.chat_keys.len(), 2);
+        assert!(toml::from_str::<Config>("chat_keys = [\"c\"]\n").is_err());
+    }
+
+    #[test]
+    fn test_scrollback_key_from_toml() {
+        let config: Config = toml::from_str("scrollback_key = \"alt+s\"\n").unwrap();
+        assert_eq!(config.scrollback_key.to_string(), "Alt+S");
+        assert!(toml::from_str::<Config>("scrollback_key = \"s\"\n").is_err());
+    }
+
+    #[test]
+    fn test_capture_debug_key_unbound_by_default() {
+        assert!(Config::default().capture_debug_key.is_none());
+
+        let config: Config = toml::from_str("capture_debug_key = \"ctrl+g\"\n").unwrap();
+        assert_eq!(config.capture_debug_key.map(|key| key.to_string()).as_deref(), Some("Ctrl+G"));
+    }
+
+    #[test]
+    fn test_chat_screen_resolution() {
+        assert_eq!(ChatScreen::Auto.resolve(Some("xterm-256color")), ChatScreen::Alternate);
+        assert_eq!(ChatScreen::Auto.resolve(Some("dumb")), ChatScreen::Inline);
+        assert_eq!(ChatScreen::Auto.resolve(Some("linux")), ChatScreen::Inline);
+        assert_eq!(ChatScreen::Auto.resolve(None), ChatScreen::Inline);
+        assert_eq!(ChatScreen::Alternate.resolve(Some("dumb")), ChatScreen::Alternate);
+    }
+}
@@ -0,0 +1,252 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::runtime::Runtime;
+use tracing::{debug, info, warn, Span};
+
+use crate::capture::{CapturedCommand, CommandCapture};
+use crate::config::{BackendConfig, Config};
+use crate::grpc_client::AgentClient;
+
+/// Request received on the control socket, one JSON object per line
+/// e.g. `{"cmd":"ask","text":"why did make fail?"}` or `{"cmd":"get_history"}`
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Ask the agent a question, with captured commands as context
+    Ask { text: String },
+
+    /// List the captured commands
+    GetHistory,
+}
+
+/// Response written back on the control socket, one JSON object per line
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Answer { message: String, agent: String },
+    History { commands: Vec<HistoryEntry> },
+    Error { error: String },
+}
+
+/// A captured command as exposed over the control socket
+#[derive(Debug, Serialize)]
+pub struct HistoryEntry {
+    pub command: String,
+    pub exit_code: Option<i32>,
+    pub timestamp: String,
+    pub working_dir: String,
+    pub output: String,
+    pub pinned: bool,
+    pub note: Option<String>,
+}
+
+impl From<&CapturedCommand> for HistoryEntry {
+    fn from(cmd: &CapturedCommand) -> Self {
+        Self {
+            command: cmd.command.clone(),
+            exit_code: cmd.exit_code,
+            timestamp: cmd.timestamp.to_rfc3339(),
+            working_dir: cmd.working_dir.display().to_string(),
+            output: cmd.output.clone(),
+            pinned: cmd.pinned,
+            note: cmd.note.clone(),
+        }
+    }
+}
+
+/// State shared by every control connection
+struct Shared {
+    capture: Arc<Mutex<CommandCapture>>,
+    backend: Arc<Mutex<BackendConfig>>, // Follows /backend in the chat
+    config: Config,
+}
+
+/// Unix socket letting external tools drive the agent and read captured commands
+/// The socket file is removed when the server is dropped
+pub struct ControlServer {
+    path: PathBuf,
+}
+
+impl ControlServer {
+    /// Bind the socket and serve it from a background thread
+    /// `path` must be in a directory only the user can enter (the session's 0700 temp dir): the
+    /// socket exists with default permissions between bind and chmod
+    pub fn start(
+        path: PathBuf,
+        capture: Arc<Mutex<CommandCapture>>,
+        backend: Arc<Mutex<BackendConfig>>,
+        config: Config,
+    ) -> Result<Self> {
+        // A stale socket from a crashed session would make bind fail
+        if path.exists() {
+            std::fs::remove_file(&path).ok();
+        }
+
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+            .context("Failed to restrict control socket permissions")?;
+        info!("Control socket listening on {}", path.display());
+
+        let shared = Arc::new(Shared {
+            capture,
+            backend,
+            config,
+        });
+
+        // Connections are logged under the session span
+        let span = Span::current();
+        thread::spawn(move || {
+            let _entered = span.enter();
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let shared = shared.clone();
+                        let span = Span::current();
+                        thread::spawn(move || {
+                            let _entered = span.enter();
+                            if let Err(e) = handle_connection(stream, &shared) {
+                                debug!("Control connection closed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Control socket accept failed: {}", e),
+                }
+            }
+        });
+
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+/// Serve newline-delimited JSON requests until the client disconnects
+fn handle_connection(stream: UnixStream, shared: &Shared) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    let reader = BufReader::new(stream);
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => handle_request(request, shared),
+            Err(e) => ControlResponse::Error {
+                error: format!("Invalid request: {}", e),
+            },
+        };
+
+        let mut json = serde_json::to_string(&response)?;
+        json.push('\n');
+        writer.write_all(json.as_bytes())?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+fn handle_request(request: ControlRequest, shared: &Shared) -> ControlResponse {
+    match request {
+        ControlRequest::GetHistory => {
+            let commands = match shared.capture.lock() {
+                Ok(capture) => capture
+                    .get_commands()
+                    .iter()
+                    .chain(capture.current())
+                    .map(HistoryEntry::from)
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+            ControlResponse::History { commands }
+        }
+        ControlRequest::Ask { text } => ask(text, shared),
+    }
+}
+
+/// Send a question to the agent, blocking this connection's thread until it answers
+fn ask(text: String, shared: &Shared) -> ControlResponse {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let context = shared
+        .capture
+        .lock()
+        .map(|capture| {
+            capture.recent_context(
+                shared.config.context_strategy,
+                capture.current_dir().unwrap_or(&cwd),
+                shared.config.context_commands,
+                shared.config.context_budget,
+            )
+        })
+        .unwrap_or_default();
+
+    let Ok(backend) = shared.backend.lock().map(|backend| backend.clone()) else {
+        return ControlResponse::Error {
+            error: "backend unavailable".to_string(),
+        };
+    };
+    let result = Runtime::new().map_err(anyhow::Error::from).and_then(|runtime| {
+        let mut client = AgentClient::for_backend(&backend);
+        runtime.block_on(client.send_message(text, context))
+    });
+
+    match result {
+        Ok(response) => ControlResponse::Answer {
+            message: response.message,
+            agent: response.agent,
+        },
+        Err(e) => ControlResponse::Error { error: e.to_string() },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_requests() {
+        assert_eq!(
+            serde_json::from_str::<ControlRequest>(r#"{"cmd":"ask","text":"hello"}"#).unwrap(),
+            ControlRequest::Ask {
+                text: "hello".to_string()
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<ControlRequest>(r#"{"cmd":"get_history"}"#).unwrap(),
+            ControlRequest::GetHistory
+        );
+        assert!(serde_json::from_str::<ControlRequest>(r#"{"cmd":"rm"}"#).is_err());
+    }
+
+    #[test]
+    fn test_serialize_responses() {
+        let answer = ControlResponse::Answer {
+            message: "hi".to_string(),
+            agent: "general".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&answer).unwrap(),
+            r#"{"status":"answer","message":"hi","agent":"general"}"#
+        );
+
+        let error = ControlResponse::Error {
+            error: "boom".to_string(),
+        };
+        assert_eq!(serde_json::to_string(&error).unwrap(), r#"{"status":"error","error":"boom"}"#);
+    }
+}
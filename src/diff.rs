@@ -0,0 +1,35 @@
+use similar::TextDiff;
+
+/// Lines of unchanged text kept around each change
+const CONTEXT_LINES: usize = 3;
+
+/// Unified line diff between two texts, None when they're identical
+pub fn unified_line_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> Option<String> {
+    if old == new {
+        return None;
+    }
+    let diff = TextDiff::from_lines(old, new);
+    let mut unified = diff.unified_diff();
+    Some(
+        unified
+            .context_radius(CONTEXT_LINES)
+            .missing_newline_hint(false)
+            .header(old_label, new_label)
+            .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_line_diff() {
+        assert_eq!(unified_line_diff("a\nb\n", "a\nb\n", "#1", "#2"), None);
+
+        let diff = unified_line_diff("ok 1\nok 2\nok 3\n", "ok 1\nFAIL 2\nok 3\n", "#1", "#2").unwrap();
+        let lines: Vec<&str> = diff.lines().collect();
+        assert_eq!(lines[..3], ["--- #1", "+++ #2", "@@ -1,3 +1,3 @@"]);
+        assert_eq!(lines[3..], [" ok 1", "-ok 2", "+FAIL 2", " ok 3"]);
+    }
+}
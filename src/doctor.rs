@@ -0,0 +1,229 @@
+use std::io::IsTerminal;
+use std::path::Path;
+use std::process::Command;
+use tokio::runtime::Runtime;
+
+use crate::config::Config;
+use crate::grpc_client::AgentClient;
+use crate::hooks::Shell;
+use crate::session::SESSION_ID_VAR;
+
+/// Outcome of one environment check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// One line of the `petoncle doctor` report
+#[derive(Debug)]
+pub struct CheckResult {
+    name: String,
+    status: CheckStatus,
+    detail: String,
+
+    /// How to fix a warning or failure
+    hint: Option<String>,
+}
+
+impl CheckResult {
+    fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn problem(status: CheckStatus, name: impl Into<String>, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    /// Report line, with the remediation hint on the next line
+    fn render(&self) -> String {
+        let icon = match self.status {
+            CheckStatus::Pass => "✅",
+            CheckStatus::Warn => "⚠️ ",
+            CheckStatus::Fail => "❌",
+        };
+        let mut line = format!("{} {}: {}", icon, self.name, self.detail);
+        if let Some(ref hint) = self.hint {
+            line.push_str(&format!("\n   💡 {}", hint));
+        }
+        line
+    }
+}
+
+/// Check the environment, print the report and tell whether nothing failed
+pub fn run(shell: Shell, agent_addr: Option<&str>) -> bool {
+    println!("🩺 Petoncle doctor\n");
+
+    let mut results = check_shell(shell);
+    let mut config = match Config::load() {
+        Ok(config) => {
+            let location = Config::path().map(|p| p.display().to_string()).unwrap_or_default();
+            results.push(CheckResult::pass("Configuration", location));
+            config
+        }
+        Err(e) => {
+            results.push(CheckResult::problem(
+                CheckStatus::Fail,
+                "Configuration",
+                format!("{:#}", e),
+                "Corrigez le fichier ou supprimez-le pour revenir aux valeurs par défaut",
+            ));
+            Config::default()
+        }
+    };
+    if let Some(addr) = agent_addr {
+        config.override_agent_addr(addr);
+    }
+    results.extend(check_agents(&config));
+    results.push(check_terminal());
+    results.extend(check_session());
+    // Hooks and the session log both live in the temp dir
+    results.push(check_writable("Dossier temporaire (hooks, logs)", &std::env::temp_dir()));
+
+    for result in &results {
+        println!("{}", result.render());
+    }
+
+    let ok = all_passed(&results);
+    println!("\n{}", if ok { "Tout est prêt 🐚" } else { "Des vérifications ont échoué" });
+    ok
+}
+
+/// Warnings don't prevent Petoncle from running, failures do
+fn all_passed(results: &[CheckResult]) -> bool {
+    results.iter().all(|result| result.status != CheckStatus::Fail)
+}
+
+/// The shell is installed
+fn check_shell(shell: Shell) -> Vec<CheckResult> {
+    let program = shell.program();
+    let version = match Command::new(program).arg("--version").output() {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        _ => {
+            return vec![CheckResult::problem(
+                CheckStatus::Fail,
+                "Shell",
+                format!("{} introuvable", program),
+                format!("Installez {} et vérifiez qu'il est dans le PATH", program),
+            )];
+        }
+    };
+
+    vec![CheckResult::pass("Shell", version)]
+}
+
+/// Every configured backend accepts a connection
+fn check_agents(config: &Config) -> Vec<CheckResult> {
+    let runtime = match Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            return vec![CheckResult::problem(
+                CheckStatus::Fail,
+                "Service IA",
+                format!("runtime tokio indisponible: {}", e),
+                "Vérifiez les limites de threads du système",
+            )];
+        }
+    };
+
+    config
+        .backends()
+        .iter()
+        .map(|backend| {
+            let name = format!("Service IA [{}]", backend.name);
+            let mut client = AgentClient::for_backend(backend);
+            match runtime.block_on(client.connect()) {
+                Ok(()) => CheckResult::pass(name, format!("joignable sur {}", backend.address)),
+                Err(e) => CheckResult::problem(
+                    CheckStatus::Warn,
+                    name,
+                    format!("injoignable sur {} ({})", backend.address, e),
+                    "Démarrez le service: cd python && python agent_service.py",
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Interactive terminal able to show the chat overlay (alternate screen)
+/// OSC 133 markers are parsed by Petoncle itself, so only the terminal type matters
+fn check_terminal() -> CheckResult {
+    if !std::io::stdout().is_terminal() {
+        return CheckResult::problem(
+            CheckStatus::Warn,
+            "Terminal",
+            "la sortie n'est pas un terminal",
+            "Lancez petoncle depuis un terminal interactif",
+        );
+    }
+    match std::env::var("TERM") {
+        Ok(term) if !term.is_empty() && term != "dumb" => CheckResult::pass("Terminal", format!("TERM={}", term)),
+        _ => CheckResult::problem(
+            CheckStatus::Fail,
+            "Terminal",
+            "TERM absent ou \"dumb\", pas d'écran alternatif",
+            "Utilisez un émulateur de terminal complet (TERM=xterm-256color par exemple)",
+        ),
+    }
+}
+
+/// The Petoncle session doctor runs in, if any: its ID and log file, for bug reports
+fn check_session() -> Option<CheckResult> {
+    let id = std::env::var(SESSION_ID_VAR).ok()?;
+    let log = std::env::var("PETONCLE_LOG_FILE").unwrap_or_default();
+    Some(CheckResult::pass("Session Petoncle", format!("{} (logs: {})", id, log)))
+}
+
+/// A file can be created in `dir`
+fn check_writable(name: &str, dir: &Path) -> CheckResult {
+    let probe = dir.join(format!("petoncle-doctor-{}", std::process::id()));
+    match std::fs::write(&probe, b"ok") {
+        Ok(()) => {
+            std::fs::remove_file(&probe).ok();
+            CheckResult::pass(name, dir.display().to_string())
+        }
+        Err(e) => CheckResult::problem(
+            CheckStatus::Fail,
+            name,
+            format!("{} non inscriptible ({})", dir.display(), e),
+            "Vérifiez les permissions ou définissez TMPDIR vers un dossier inscriptible",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warnings_dont_fail_the_report() {
+        let warn = CheckResult::problem(CheckStatus::Warn, "Service IA", "injoignable", "Démarrez-le");
+        assert!(all_passed(&[CheckResult::pass("Shell", "zsh 5.9"), warn]));
+
+        let fail = CheckResult::problem(CheckStatus::Fail, "Terminal", "TERM absent", "Changez de terminal");
+        assert!(!all_passed(&[fail]));
+    }
+
+    #[test]
+    fn test_render_includes_hint() {
+        let result = CheckResult::problem(CheckStatus::Fail, "Terminal", "TERM absent", "Changez de terminal");
+        assert_eq!(result.render(), "❌ Terminal: TERM absent\n   💡 Changez de terminal");
+        assert_eq!(CheckResult::pass("Shell", "zsh 5.9").render(), "✅ Shell: zsh 5.9");
+    }
+
+    #[test]
+    fn test_temp_dir_writable() {
+        assert_eq!(check_writable("tmp", &std::env::temp_dir()).status, CheckStatus::Pass);
+    }
+}
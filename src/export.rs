@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::actions::shell_quote;
+use crate::capture::CapturedCommand;
+
+/// Most output lines kept in a snippet, from the end (where errors usually are)
+const SNIPPET_OUTPUT_LINES: usize = 200;
+
+/// Turn captured commands into a shell script replaying them in order
+/// Each command is preceded by a comment with its time, exit code, directory and note;
+/// failed and unfinished commands are left out when `skip_failed` (they are still mentioned)
+pub fn session_script<'a>(
+    commands: impl IntoIterator<Item = &'a CapturedCommand>,
+    skip_failed: bool,
+    session_id: Option<&str>,
+    generated: DateTime<Local>,
+) -> (String, usize) {
+    let mut body = String::new();
+    let mut exported = 0;
+    for cmd in commands {
+        let exit = match cmd.exit_code {
+            Some(code) => format!("exit {}", code),
+            None => "en cours".to_string(),
+        };
+        let header = format!(
+            "# {} | {} | {}",
+            cmd.timestamp.format("%H:%M:%S"),
+            exit,
+            cmd.working_dir.display()
+        );
+
+        if skip_failed && cmd.exit_code != Some(0) {
+            body.push_str(&format!("{} | ignorée: {}\n\n", header, cmd.command.replace('\n', " ")));
+            continue;
+        }
+
+        body.push_str(&header);
+        body.push('\n');
+        if let Some(ref note) = cmd.note {
+            body.push_str(&format!("# note: {}\n", note.replace('\n', " ")));
+        }
+        body.push_str(&cmd.command);
+        body.push_str("\n\n");
+        exported += 1;
+    }
+
+    let script = format!(
+        "#!/usr/bin/env zsh\n# Session Petoncle {}exportée le {}\n# {} commande(s){}\n\n{}",
+        session_id.map(|id| format!("{} ", id)).unwrap_or_default(),
+        generated.format("%Y-%m-%d %H:%M"),
+        exported,
+        if skip_failed { ", échecs ignorés" } else { "" },
+        body
+    );
+    (script, exported)
+}
+
+/// One captured command as a self-contained block for a bug report: where it ran, the command
+/// and its recorded output as comments
+/// The `cd` is commented out when the directory no longer exists (`dir_exists`)
+pub fn command_snippet(cmd: &CapturedCommand, dir_exists: bool) -> String {
+    let status = match cmd.exit_code {
+        Some(code) => format!("exit {}", code),
+        None => "toujours en cours, sortie partielle".to_string(),
+    };
+    let mut snippet = format!("# {} | {}\n", cmd.timestamp.format("%Y-%m-%d %H:%M:%S"), status);
+    if let Some(ref note) = cmd.note {
+        snippet.push_str(&format!("# note: {}\n", note.replace('\n', " ")));
+    }
+
+    let cd = format!("cd {}", shell_quote(&cmd.working_dir.display().to_string()));
+    if dir_exists {
+        snippet.push_str(&format!("{}\n", cd));
+    } else {
+        snippet.push_str(&format!("# {}  (répertoire introuvable)\n", cd));
+    }
+    snippet.push_str(&cmd.command);
+    snippet.push('\n');
+
+    let output = cmd.clean_output();
+    let lines: Vec<&str> = output.lines().collect();
+    if lines.iter().all(|line| line.trim().is_empty()) {
+        snippet.push_str("# (aucune sortie)\n");
+        return snippet;
+    }
+    snippet.push_str("# Sortie:\n");
+    let skipped = lines.len().saturating_sub(SNIPPET_OUTPUT_LINES);
+    if skipped > 0 {
+        snippet.push_str(&format!("# … {} ligne(s) omise(s)\n", skipped));
+    }
+    for line in &lines[skipped..] {
+        snippet.push_str(format!("# {}", line).trim_end());
+        snippet.push('\n');
+    }
+    snippet
+}
+
+/// Write the script and make it executable
+pub fn write_script(path: &Path, script: &str) -> Result<()> {
+    std::fs::write(path, script).with_context(|| format!("Impossible d'écrire {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("Impossible de rendre {} exécutable", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn command(line: &str, exit_code: Option<i32>) -> CapturedCommand {
+        let mut cmd = CapturedCommand::new(line.to_string(), PathBuf::from("/srv/app"));
+        cmd.exit_code = exit_code;
+        cmd
+    }
+
+    #[test]
+    fn test_script_skips_failed_commands() {
+        let mut build = command("make build", Some(0));
+        build.note = Some("étape de repro".to_string());
+        let commands = [build, command("make tset", Some(2)), command("make test", Some(0))];
+
+        let (script, exported) = session_script(&commands, true, Some("3f9a0c21"), Local::now());
+        assert_eq!(exported, 2);
+        assert!(script.starts_with("#!/usr/bin/env zsh\n# Session Petoncle 3f9a0c21 exportée le "));
+        assert!(script.contains("# note: étape de repro\nmake build\n"));
+        assert!(script.contains("| exit 2 | /srv/app | ignorée: make tset\n"));
+        assert!(!script.contains("\nmake tset\n"));
+        assert!(script.find("make build").unwrap() < script.find("make test").unwrap());
+
+        let (script, exported) = session_script(&commands, false, None, Local::now());
+        assert_eq!(exported, 3);
+        assert!(script.contains("\nmake tset\n"));
+    }
+
+    #[test]
+    fn test_command_snippet() {
+        let mut cmd = command("make test", Some(2));
+        cmd.working_dir = PathBuf::from("/srv/it's");
+        cmd.append_output("\x1b[31mFAIL\x1b[0m\r\n\r\n1 failed\r\n");
+        let snippet = command_snippet(&cmd, true);
+        assert!(snippet.starts_with("# "));
+        assert!(snippet.ends_with(
+            "| exit 2\ncd '/srv/it'\\''s'\nmake test\n# Sortie:\n# FAIL\n#\n# 1 failed\n"
+        ));
+
+        let running = command("tail -f log", None);
+        let snippet = command_snippet(&running, false);
+        assert!(snippet.contains(
+            "| toujours en cours, sortie partielle\n# cd '/srv/app'  (répertoire introuvable)\n"
+        ));
+        assert!(snippet.ends_with("tail -f log\n# (aucune sortie)\n"));
+    }
+
+    #[test]
+    fn test_written_script_is_executable() {
+        let path = std::env::temp_dir().join(format!("petoncle-export-{}.sh", std::process::id()));
+        write_script(&path, "#!/bin/sh\ntrue\n").unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+        std::fs::remove_file(&path).ok();
+
+        assert!(write_script(Path::new("/nonexistent/dir/x.sh"), "").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tracing::{debug, warn, Span};
+
+use crate::capture::{truncate_start, CapturedCommand, CommandSink};
+
+/// Most output carried by an event, the end of it is kept (where errors usually are)
+const MAX_EVENT_OUTPUT: usize = 16 * 1024;
+
+/// Hooks running at once, failures past it are dropped rather than piling up processes
+const MAX_RUNNING_HOOKS: usize = 4;
+
+/// A captured command that exited non-zero, as given to the hook (JSON on stdin)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FailureEvent {
+    pub command: String,
+    pub exit_code: i32,
+    pub working_dir: String,
+
+    /// Output without escape sequences, cut from the start past `MAX_EVENT_OUTPUT`
+    pub output: String,
+
+    /// Start time (RFC 3339)
+    pub started: String,
+    pub duration_ms: Option<i64>,
+    pub session_id: String,
+}
+
+impl FailureEvent {
+    /// Event for a finished command, None unless it failed
+    pub fn from_command(cmd: &CapturedCommand, session_id: &str) -> Option<Self> {
+        let exit_code = cmd.exit_code.filter(|&code| code != 0)?;
+        Some(Self {
+            command: cmd.command.clone(),
+            exit_code,
+            working_dir: cmd.working_dir.display().to_string(),
+            output: truncate_start(&cmd.clean_output(), MAX_EVENT_OUTPUT),
+            started: cmd.timestamp.to_rfc3339(),
+            duration_ms: cmd.duration().map(|d| d.num_milliseconds()),
+            session_id: session_id.to_string(),
+        })
+    }
+}
+
+/// Runs the configured `on_failure` command whenever a captured command exits non-zero
+/// Each run happens on its own thread: a slow or stuck hook never stalls the PTY read loop
+pub struct FailureHook {
+    command: String,
+    session_id: String,
+    running: Arc<AtomicUsize>,
+}
+
+impl FailureHook {
+    pub fn new(command: String, session_id: String) -> Self {
+        Self {
+            command,
+            session_id,
+            running: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl CommandSink for FailureHook {
+    fn on_command(&mut self, cmd: &CapturedCommand) {
+        let Some(event) = FailureEvent::from_command(cmd, &self.session_id) else {
+            return;
+        };
+        if self.running.fetch_add(1, Ordering::SeqCst) >= MAX_RUNNING_HOOKS {
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            warn!(
+                "Failure hook skipped for {:?}: {} hooks still running",
+                event.command, MAX_RUNNING_HOOKS
+            );
+            return;
+        }
+
+        let command = self.command.clone();
+        let running = self.running.clone();
+        let span = Span::current();
+        thread::spawn(move || {
+            let _entered = span.enter();
+            match run_hook(&command, &event) {
+                Ok(()) => debug!("Failure hook ran for {:?}", event.command),
+                Err(e) => warn!("Failure hook {:?} failed: {:#}", command, e),
+            }
+            running.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Run the hook through `sh -c` with the event as JSON on stdin, its main fields also in the
+/// environment (PETONCLE_FAILED_COMMAND, PETONCLE_EXIT_CODE) for one-liners
+pub fn run_hook(command: &str, event: &FailureEvent) -> Result<()> {
+    let json = serde_json::to_string(event)?;
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("PETONCLE_FAILED_COMMAND", &event.command)
+        .env("PETONCLE_EXIT_CODE", event.exit_code.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Failed to start the hook")?;
+
+    // A hook that doesn't read its stdin closes the pipe early, that's fine
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(json.as_bytes()).ok();
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("exited with {}", status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::CommandCapture;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_failure_event_and_hook() {
+        let mut capture = CommandCapture::new();
+        let cwd = PathBuf::from("/home/user");
+        capture.process_output(
+            "\x1b]133;C;make\x07\x1b[31merror\x1b[0m: missing\n\x1b]133;D;2\x07\
+             \x1b]133;C;ls\x07a\n\x1b]133;D;0\x07",
+            &cwd,
+        );
+        // The last command stays current until the next one starts
+        assert_eq!(FailureEvent::from_command(capture.get(1).unwrap(), "abcd1234"), None);
+        let event = FailureEvent::from_command(capture.get(0).unwrap(), "abcd1234").unwrap();
+        assert_eq!(event.exit_code, 2);
+        assert_eq!(event.output, "error: missing\n");
+
+        let path = std::env::temp_dir().join(format!("petoncle-failure-hook-{}", std::process::id()));
+        let hook = format!("cat > '{0}'; echo \"$PETONCLE_EXIT_CODE\" >> '{0}'", path.display());
+        run_hook(&hook, &event).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert!(written.starts_with(r#"{"command":"make","exit_code":2,"#));
+        assert!(written.ends_with("}2\n"));
+
+        assert!(run_hook("exit 3", &event).is_err());
+    }
+}
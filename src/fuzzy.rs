@@ -0,0 +1,82 @@
+/// Score of each matched character
+const MATCH_SCORE: i64 = 16;
+
+/// Bonus for a match at the start of a word (`s` in `git status`)
+const WORD_START_BONUS: i64 = 8;
+
+/// Bonus for a match right after the previous one
+const CONSECUTIVE_BONUS: i64 = 4;
+
+/// Penalty for each character skipped between two matches
+const GAP_PENALTY: i64 = 1;
+
+/// Match `query` as a case-insensitive subsequence of `text` (`gst` matches `git status`)
+/// Returns the score (higher is better) and the char positions of the matched characters,
+/// or None when some query character can't be found in order
+pub fn fuzzy_match(query: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).filter(|c| !c.is_whitespace()).collect();
+    let text: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = text.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+
+    let first = *query.first()?;
+    // Greedy from every possible start of the first character, keep the best alignment
+    (0..lower.len())
+        .filter(|&start| lower[start] == first)
+        .filter_map(|start| match_from(&query, &text, &lower, start))
+        .max_by_key(|(score, positions)| (*score, std::cmp::Reverse(positions[0])))
+}
+
+fn match_from(query: &[char], text: &[char], lower: &[char], start: usize) -> Option<(i64, Vec<usize>)> {
+    let mut positions = Vec::with_capacity(query.len());
+    let mut score = 0;
+    let mut next = start;
+
+    for &wanted in query {
+        let pos = (next..lower.len()).find(|&i| lower[i] == wanted)?;
+        score += MATCH_SCORE;
+        if is_word_start(text, pos) {
+            score += WORD_START_BONUS;
+        }
+        if let Some(&previous) = positions.last() {
+            if pos == previous + 1 {
+                score += CONSECUTIVE_BONUS;
+            } else {
+                score -= (pos - previous - 1) as i64 * GAP_PENALTY;
+            }
+        }
+        positions.push(pos);
+        next = pos + 1;
+    }
+    Some((score, positions))
+}
+
+/// First character of the text or of a word (after a space or a path/flag separator)
+fn is_word_start(text: &[char], pos: usize) -> bool {
+    pos == 0 || matches!(text[pos - 1], ' ' | '/' | '-' | '_' | '.' | '|' | ';')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subsequence_positions() {
+        assert_eq!(fuzzy_match("gst", "git status").map(|(_, p)| p), Some(vec![0, 4, 5]));
+        assert_eq!(fuzzy_match("GST", "git status").map(|(_, p)| p), Some(vec![0, 4, 5]));
+        assert_eq!(fuzzy_match("sg", "git status"), None);
+        assert_eq!(fuzzy_match("", "git status"), None);
+    }
+
+    #[test]
+    fn test_word_starts_rank_higher() {
+        let score = |text| fuzzy_match("gst", text).unwrap().0;
+        assert!(score("git status") > score("grep -r pst"));
+        assert!(score("git stash") > score("gitstuff"));
+    }
+
+    #[test]
+    fn test_best_alignment_is_kept() {
+        // Greedy from the first 'c' scatters the match, the last start matches "cargo" whole
+        assert_eq!(fuzzy_match("cargo", "cd car && cargo").map(|(_, p)| p), Some(vec![10, 11, 12, 13, 14]));
+    }
+}
@@ -1,7 +1,10 @@
 use anyhow::Result;
+use std::fmt;
 use std::time::Duration;
 use tracing::{debug, error, info, warn};
 
+use crate::config::{BackendConfig, TimeoutConfig, DEFAULT_MAX_MESSAGE_BYTES};
+
 // Include generated proto code
 pub mod chat {
     tonic::include_proto!("petoncle");
@@ -10,11 +13,45 @@ pub mod chat {
 use chat::chat_service_client::ChatServiceClient;
 use chat::{ChatRequest, ChatResponse};
 
+/// A request or response exceeded a gRPC message size limit
+/// Retrying can't help, the context has to be trimmed
+#[derive(Debug)]
+pub struct MessageTooLarge(pub String);
+
+impl fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Message trop volumineux: {}", self.0)
+    }
+}
+
+impl std::error::Error for MessageTooLarge {}
+
+/// Deadlines come from the server (DeadlineExceeded) or from tonic's own timeout (Cancelled)
+fn is_deadline_exceeded(status: &tonic::Status) -> bool {
+    match status.code() {
+        tonic::Code::DeadlineExceeded => true,
+        tonic::Code::Cancelled => status.message().contains("Timeout expired"),
+        _ => false,
+    }
+}
+
+/// Size errors come from tonic (OutOfRange) or from the server (ResourceExhausted)
+fn is_message_too_large(status: &tonic::Status) -> bool {
+    let message = status.message().to_lowercase();
+    matches!(status.code(), tonic::Code::OutOfRange | tonic::Code::ResourceExhausted)
+        && (message.contains("too large") || message.contains("larger than max"))
+}
+
 /// gRPC client for communicating with Python agent service
+#[derive(Clone)]
 pub struct AgentClient {
     client: Option<ChatServiceClient<tonic::transport::Channel>>,
     server_addr: String,
+    token: Option<String>,
     max_retries: u32,
+    max_decoding_message_size: usize,
+    max_encoding_message_size: usize,
+    timeouts: TimeoutConfig,
 }
 
 impl AgentClient {
@@ -22,10 +59,47 @@ impl AgentClient {
         Self {
             client: None,
             server_addr: server_addr.to_string(),
+            token: None,
             max_retries: 3,  // Retry up to 3 times
+            max_decoding_message_size: DEFAULT_MAX_MESSAGE_BYTES,
+            max_encoding_message_size: DEFAULT_MAX_MESSAGE_BYTES,
+            timeouts: TimeoutConfig::default(),
         }
     }
 
+    /// Client for a configured backend (address, token and size limits)
+    pub fn for_backend(backend: &BackendConfig) -> Self {
+        Self::new(&backend.address)
+            .with_token(backend.token.clone())
+            .with_max_message_sizes(backend.max_message_bytes, backend.max_message_bytes)
+            .with_timeouts(backend.timeouts)
+    }
+
+    /// Override the request, connect and channel deadlines
+    pub fn with_timeouts(mut self, timeouts: TimeoutConfig) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Override the largest response (decoding) and request (encoding) sizes, in bytes
+    pub fn with_max_message_sizes(mut self, decoding: usize, encoding: usize) -> Self {
+        self.max_decoding_message_size = decoding;
+        self.max_encoding_message_size = encoding;
+        self
+    }
+
+    /// Override the number of retries (0 for a one-shot request)
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Attach a bearer token sent with every request
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
     /// Connect to the agent service
     pub async fn connect(&mut self) -> Result<()> {
         let addr = format!("http://{}", self.server_addr);
@@ -33,11 +107,13 @@ impl AgentClient {
 
         // Create endpoint with timeout configuration
         let channel = tonic::transport::Channel::from_shared(addr)?
-            .timeout(Duration::from_secs(10))  // 10s timeout for connection
-            .connect_timeout(Duration::from_secs(5))  // 5s timeout for initial connect
+            .timeout(Duration::from_secs(self.timeouts.channel_secs))
+            .connect_timeout(Duration::from_secs(self.timeouts.connect_secs))
             .connect()
             .await?;
-        let client = ChatServiceClient::new(channel);
+        let client = ChatServiceClient::new(channel)
+            .max_decoding_message_size(self.max_decoding_message_size)
+            .max_encoding_message_size(self.max_encoding_message_size);
         self.client = Some(client);
         info!("Successfully connected to gRPC service");
         Ok(())
@@ -48,8 +124,31 @@ impl AgentClient {
         &mut self,
         message: String,
         context: Vec<String>,
+    ) -> Result<ChatResponse> {
+        self.send_message_reporting(message, context, &mut |_, _, _| {}).await
+    }
+
+    /// Same as `send_message`, calling `on_retry(next_attempt, attempts, delay)` before each
+    /// backoff so the caller can show that the service is being reached again (attempts are 1-based)
+    pub async fn send_message_reporting(
+        &mut self,
+        message: String,
+        context: Vec<String>,
+        on_retry: &mut dyn FnMut(u32, u32, Duration),
     ) -> Result<ChatResponse> {
         let mut last_error = None;
+        let attempts = self.max_retries + 1;
+
+        // Build the authorization header once, it's the same for every attempt
+        let authorization: Option<tonic::metadata::MetadataValue<tonic::metadata::Ascii>> =
+            match &self.token {
+                Some(token) => Some(
+                    format!("Bearer {}", token)
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid backend token"))?,
+                ),
+                None => None,
+            };
 
         // Retry loop with exponential backoff
         for attempt in 0..=self.max_retries {
@@ -65,6 +164,7 @@ impl AgentClient {
                             // Exponential backoff: 1s, 2s, 4s
                             let backoff = Duration::from_secs(2u64.pow(attempt));
                             debug!("Retrying in {:?}", backoff);
+                            on_retry(attempt + 2, attempts, backoff);
                             tokio::time::sleep(backoff).await;
                             continue;
                         }
@@ -78,8 +178,12 @@ impl AgentClient {
                 context: context.clone(),
             });
 
-            // Set timeout for this request (45 seconds to account for Mistral API timeout)
-            request.set_timeout(Duration::from_secs(45));
+            // Deadline for this request (the agent may wait on a slow LLM API)
+            request.set_timeout(Duration::from_secs(self.timeouts.request_secs));
+
+            if let Some(ref value) = authorization {
+                request.metadata_mut().insert("authorization", value.clone());
+            }
 
             match self
                 .client
@@ -92,16 +196,29 @@ impl AgentClient {
                     debug!("Successfully received response from gRPC service");
                     return Ok(response.into_inner());
                 }
+                Err(e) if is_message_too_large(&e) => {
+                    // Deterministic failure, the same message would fail again
+                    error!("gRPC message size limit exceeded: {}", e.message());
+                    return Err(MessageTooLarge(e.message().to_string()).into());
+                }
                 Err(e) => {
                     // Connection lost, reset client for reconnection
                     error!("gRPC request failed (attempt {}): {}", attempt + 1, e);
                     self.client = None;
-                    last_error = Some(e.into());
+                    last_error = Some(if is_deadline_exceeded(&e) {
+                        anyhow::anyhow!(
+                            "Délai dépassé: pas de réponse après {}s (timeouts.request_secs / timeouts.channel_secs)",
+                            self.timeouts.effective_request_secs()
+                        )
+                    } else {
+                        e.into()
+                    });
 
                     if attempt < self.max_retries {
                         // Exponential backoff before retry
                         let backoff = Duration::from_secs(2u64.pow(attempt));
                         debug!("Retrying in {:?}", backoff);
+                        on_retry(attempt + 2, attempts, backoff);
                         tokio::time::sleep(backoff).await;
                     }
                 }
@@ -112,9 +229,27 @@ impl AgentClient {
         error!("All retry attempts exhausted: {}", final_error);
         Err(final_error)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_message_too_large() {
+        let decode = tonic::Status::out_of_range(
+            "Error, message length too large: found 20000000 bytes, the limit is: 16777216 bytes",
+        );
+        let server = tonic::Status::resource_exhausted("Received message larger than max (20000000 vs. 4194304)");
+        assert!(is_message_too_large(&decode));
+        assert!(is_message_too_large(&server));
+        assert!(!is_message_too_large(&tonic::Status::unavailable("connection refused")));
+    }
 
-    /// Check if connected to service
-    pub fn is_connected(&self) -> bool {
-        self.client.is_some()
+    #[test]
+    fn test_is_deadline_exceeded() {
+        assert!(is_deadline_exceeded(&tonic::Status::deadline_exceeded("too slow")));
+        assert!(is_deadline_exceeded(&tonic::Status::cancelled("Timeout expired")));
+        assert!(!is_deadline_exceeded(&tonic::Status::cancelled("client went away")));
     }
 }
@@ -0,0 +1,240 @@
+use anyhow::{Context, Result};
+use chrono::SecondsFormat;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::capture::{CapturedCommand, CommandSink};
+
+/// A captured command as stored in the JSONL history, one per line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    /// Start time (RFC 3339, nanoseconds), also identifies the command
+    pub started: String,
+    pub command: String,
+    pub working_dir: String,
+    pub exit_code: Option<i32>,
+    pub output: String,
+
+    /// False for a command that never finished (shell exit, crash recovery)
+    pub complete: bool,
+
+    /// The user's annotation, absent from records written before notes existed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl From<&CapturedCommand> for HistoryRecord {
+    fn from(cmd: &CapturedCommand) -> Self {
+        Self {
+            started: cmd.timestamp.to_rfc3339_opts(SecondsFormat::Nanos, false),
+            command: cmd.command.clone(),
+            working_dir: cmd.working_dir.display().to_string(),
+            exit_code: cmd.exit_code,
+            output: cmd.output.clone(),
+            complete: cmd.is_complete(),
+            note: cmd.note.clone(),
+        }
+    }
+}
+
+/// Appends finished commands to a JSONL file
+/// A long-running command (`tail -f`) is checkpointed to a side file while it runs, so a crash
+/// doesn't lose it: the checkpoint is replaced by the final record, or recovered on next start
+pub struct HistorySink {
+    file: File,
+    path: PathBuf,
+    checkpoint_path: PathBuf,
+    checkpoint_interval: Duration,
+    last_checkpoint: Option<Instant>,
+}
+
+impl HistorySink {
+    /// Open (or create) the history file, recovering a checkpoint left by a crashed session
+    pub fn open(path: PathBuf, checkpoint_interval: Duration) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let checkpoint_path = checkpoint_path(&path);
+        recover_checkpoint(&path, &checkpoint_path)?;
+
+        let file = private_file()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open history {}", path.display()))?;
+        info!("Recording command history to {}", path.display());
+
+        Ok(Self {
+            file,
+            path,
+            checkpoint_path,
+            checkpoint_interval,
+            last_checkpoint: None,
+        })
+    }
+
+    fn append(&mut self, record: &HistoryRecord) -> Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Replace the checkpoint atomically (write to a temp file, then rename)
+    fn write_checkpoint(&self, record: &HistoryRecord) -> Result<()> {
+        let tmp = self.checkpoint_path.with_extension("tmp");
+        // A leftover temp file would keep its old permissions
+        std::fs::remove_file(&tmp).ok();
+        private_file()
+            .create_new(true)
+            .write(true)
+            .open(&tmp)?
+            .write_all(serde_json::to_string(record)?.as_bytes())?;
+        std::fs::rename(&tmp, &self.checkpoint_path)?;
+        Ok(())
+    }
+}
+
+impl CommandSink for HistorySink {
+    fn on_command(&mut self, cmd: &CapturedCommand) {
+        if let Err(e) = self.append(&HistoryRecord::from(cmd)) {
+            warn!("Failed to write history to {}: {:#}", self.path.display(), e);
+        }
+        // The final record supersedes the checkpoint
+        std::fs::remove_file(&self.checkpoint_path).ok();
+        self.last_checkpoint = None;
+    }
+
+    fn on_progress(&mut self, cmd: &CapturedCommand) {
+        let due = self
+            .last_checkpoint
+            .is_none_or(|last| last.elapsed() >= self.checkpoint_interval);
+        if !due {
+            return;
+        }
+        self.last_checkpoint = Some(Instant::now());
+        if let Err(e) = self.write_checkpoint(&HistoryRecord::from(cmd)) {
+            warn!("Failed to checkpoint {:?}: {:#}", cmd.command, e);
+        }
+    }
+}
+
+/// Options for files created readable by the user only: commands and outputs can hold secrets
+fn private_file() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.mode(0o600);
+    options
+}
+
+/// Side file holding the running command, next to the history (`history.jsonl.checkpoint`)
+fn checkpoint_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".checkpoint");
+    PathBuf::from(name)
+}
+
+/// Move a leftover checkpoint into the history as an incomplete record,
+/// unless its final record made it to the file before the crash
+fn recover_checkpoint(path: &Path, checkpoint_path: &Path) -> Result<()> {
+    let Ok(content) = std::fs::read_to_string(checkpoint_path) else {
+        return Ok(());
+    };
+
+    if let Ok(mut record) = serde_json::from_str::<HistoryRecord>(&content) {
+        let already_final = load(path)?.iter().any(|r| r.started == record.started);
+        if !already_final {
+            warn!("Recovering interrupted command {:?} from checkpoint", record.command);
+            record.complete = false;
+            let mut file = private_file().create(true).append(true).open(path)?;
+            writeln!(file, "{}", serde_json::to_string(&record)?)?;
+        }
+    }
+
+    std::fs::remove_file(checkpoint_path).ok();
+    Ok(())
+}
+
+/// Read the history, skipping malformed lines (e.g. a line cut short by a crash)
+pub fn load(path: &Path) -> Result<Vec<HistoryRecord>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read history {}", path.display())),
+    };
+
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(|line| line.ok())
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn temp_history(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("petoncle-history-{}-{}", std::process::id(), name));
+        std::fs::remove_dir_all(&dir).ok();
+        dir.join("history.jsonl")
+    }
+
+    fn running_command() -> CapturedCommand {
+        let mut cmd = CapturedCommand::new("tail -f app.log".to_string(), PathBuf::from("/var/log"));
+        cmd.append_output("line 1\n");
+        cmd
+    }
+
+    #[test]
+    fn test_checkpoint_superseded_by_final_record() {
+        let path = temp_history("final");
+        let mut sink = HistorySink::open(path.clone(), Duration::ZERO).unwrap();
+        let mut cmd = running_command();
+
+        sink.on_progress(&cmd);
+        assert!(checkpoint_path(&path).exists());
+        for file in [&path, &checkpoint_path(&path)] {
+            assert_eq!(std::fs::metadata(file).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        assert!(load(&path).unwrap().is_empty());
+
+        cmd.set_exit_code(130);
+        sink.on_command(&cmd);
+        assert!(!checkpoint_path(&path).exists());
+
+        let records = load(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].complete);
+        assert_eq!(records[0].exit_code, Some(130));
+    }
+
+    #[test]
+    fn test_recover_checkpoint_after_crash() {
+        let path = temp_history("crash");
+        {
+            let mut sink = HistorySink::open(path.clone(), Duration::ZERO).unwrap();
+            sink.on_progress(&running_command());
+            // Dropped without a final record, as if the process had died
+        }
+
+        HistorySink::open(path.clone(), Duration::ZERO).unwrap();
+        let records = load(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].command, "tail -f app.log");
+        assert_eq!(records[0].output, "line 1\n");
+        assert!(!records[0].complete);
+        assert!(!checkpoint_path(&path).exists());
+
+        // Recovered only once
+        HistorySink::open(path.clone(), Duration::ZERO).unwrap();
+        assert_eq!(load(&path).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,263 @@
+use anyhow::{bail, Context, Result};
+use std::ffi::OsString;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Shells Petoncle knows how to instrument with OSC 133 hooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Shell {
+    #[default]
+    Zsh,
+}
+
+impl FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "zsh" => Ok(Shell::Zsh),
+            "bash" | "fish" => bail!("Shell not supported yet: {} (only zsh is)", name),
+            _ => bail!("Unknown shell: {}", name),
+        }
+    }
+}
+
+impl Shell {
+    /// Executable spawned in the PTY
+    pub fn program(self) -> &'static str {
+        match self {
+            Shell::Zsh => "zsh",
+        }
+    }
+}
+
+/// Environment variable holding the user's own ZDOTDIR, replaced by Petoncle's directory
+pub const USER_ZDOTDIR_VAR: &str = "PETONCLE_USER_ZDOTDIR";
+
+/// Inherited variables kept by `--clean-env`: the shell still finds its programs and the
+/// user's config. TERM, ZDOTDIR and the PETONCLE_* variables are set by Petoncle itself.
+pub const CLEAN_ENV_KEPT: &[&str] = &["PATH", "HOME"];
+
+/// The part of `inherited` a `--clean-env` shell starts with
+pub fn clean_environment(inherited: impl IntoIterator<Item = (OsString, OsString)>) -> Vec<(OsString, OsString)> {
+    inherited
+        .into_iter()
+        .filter(|(key, _)| CLEAN_ENV_KEPT.iter().any(|kept| key == kept))
+        .collect()
+}
+
+/// Startup files injected into the shell, as (file name, content) pairs
+/// For zsh, ZDOTDIR points at the directory they are written to
+pub fn hook_files(shell: Shell) -> Vec<(&'static str, &'static str)> {
+    match shell {
+        Shell::Zsh => vec![(".zshenv", ZSH_ENV), (".zshrc", ZSH_RC)],
+    }
+}
+
+/// Write the startup files into `dir`
+pub fn write_hook_files(shell: Shell, dir: &Path) -> Result<()> {
+    for (name, content) in hook_files(shell) {
+        std::fs::write(dir.join(name), content).with_context(|| format!("Failed to write {}", name))?;
+    }
+    Ok(())
+}
+
+/// Every startup file with a header, for `--print-hooks`
+pub fn hook_script(shell: Shell) -> String {
+    hook_files(shell)
+        .into_iter()
+        .map(|(name, content)| format!("# ==> {} <==\n{}", name, content))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Runs the user's .zshenv as if ZDOTDIR hadn't been changed, and remembers where it
+/// points the rest of the config (a .zshenv setting ZDOTDIR to ~/.config/zsh is common)
+const ZSH_ENV: &str = r#"# Petoncle: zsh found this file because ZDOTDIR points at Petoncle's temporary directory
+_petoncle_env_dir=$ZDOTDIR
+ZDOTDIR=${PETONCLE_USER_ZDOTDIR:-$HOME}
+if [[ -f "$ZDOTDIR/.zshenv" && ! "$ZDOTDIR/.zshenv" -ef "$_petoncle_env_dir/.zshenv" ]]; then
+    source "$ZDOTDIR/.zshenv"
+fi
+
+# The user's .zshenv may have moved the rest of their config, .zshrc is read from there
+typeset -g _petoncle_user_zdotdir=$ZDOTDIR
+ZDOTDIR=$_petoncle_env_dir
+unset _petoncle_env_dir
+"#;
+
+/// Sources the user's .zshrc, then installs the command tracking hooks
+/// The hooks are installed again at the first prompt, so they end up last whatever the
+/// user's config deferred (plugin managers, instant prompts, frameworks resetting hook arrays)
+const ZSH_RC: &str = r#"# Petoncle: zsh loads this file instead of the user's .zshrc, which it sources first
+
+# Sourced from within the user's config (e.g. a "source $ZDOTDIR/.zshrc"): nothing to do
+if [[ -n $_petoncle_sourcing ]]; then
+    return 0
+fi
+
+typeset -g _petoncle_dir=$ZDOTDIR
+_petoncle_user_dir=${_petoncle_user_zdotdir:-${PETONCLE_USER_ZDOTDIR:-$HOME}}
+
+# Source user's real .zshrc first (so our hooks don't get overwritten), with their ZDOTDIR
+if [[ -f "$_petoncle_user_dir/.zshrc" && ! "$_petoncle_user_dir/.zshrc" -ef "$_petoncle_dir/.zshrc" ]]; then
+    typeset -g _petoncle_sourcing=1
+    ZDOTDIR=$_petoncle_user_dir
+    source "$_petoncle_user_dir/.zshrc"
+    unset _petoncle_sourcing
+fi
+
+# Nested shells (zsh, exec zsh) come back through these files and keep the hooks
+ZDOTDIR=$_petoncle_dir
+unset _petoncle_user_dir
+
+# Petoncle command tracking hooks (defined after user config)
+petoncle_preexec() {
+    # OSC 133;C marks command start
+    printf '\033]133;C;%s\007' "$1"
+}
+
+# Percent-encode a path into $REPLY for a file:// URI
+# Only ASCII is escaped: non-ASCII characters go through as UTF-8, which the parser keeps as is
+petoncle_encode_path() {
+    emulate -L zsh
+    local c hex i
+    REPLY=
+    for (( i = 1; i <= ${#1}; i++ )); do
+        c=${1[i]}
+        case $c in
+            [A-Za-z0-9/._~-]|[^[:ascii:]]) REPLY+=$c ;;
+            *) printf -v hex '%%%02X' "'$c"; REPLY+=$hex ;;
+        esac
+    done
+}
+
+petoncle_precmd() {
+    # OSC 133;D marks command end with exit code
+    printf '\033]133;D;%s\007' "$?"
+    # OSC 7 reports the working directory the next command runs in
+    # (encoded: a space, % or control character would garble the URI)
+    local REPLY
+    petoncle_encode_path "$PWD"
+    printf '\033]7;file://%s%s\007' "$HOST" "$REPLY"
+    # OSC 133;A marks the start of the prompt
+    printf '\033]133;A\007'
+}
+
+# OSC 133;B at the end of the prompt marks where the typed command line starts
+petoncle_mark_prompt() {
+    [[ $PS1 == *$'\e]133;B\a'* ]] || PS1+=$'%{\e]133;B\a%}'
+}
+
+# The hook arrays run alongside the user's own preexec/precmd functions, never replacing them
+petoncle_install_hooks() {
+    # precmd goes first, it must read $? before other hooks run commands
+    # petoncle_mark_prompt goes last, prompt themes rebuild PS1 in their own precmd hooks
+    precmd_functions=(
+        petoncle_precmd
+        ${${precmd_functions:#petoncle_precmd}:#petoncle_mark_prompt}
+        petoncle_mark_prompt
+    )
+    preexec_functions=(${preexec_functions:#petoncle_preexec} petoncle_preexec)
+}
+
+# One-shot precmd hook: reinstall once everything deferred by the user's config has run
+petoncle_deferred_install() {
+    precmd_functions=(${precmd_functions:#petoncle_deferred_install})
+    petoncle_install_hooks
+}
+
+petoncle_install_hooks
+precmd_functions+=(petoncle_deferred_install)
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_zsh_hooks_emit_command_markers() {
+        let script = hook_script(Shell::Zsh);
+        assert!(script.contains("# ==> .zshenv <=="));
+        assert!(script.contains("source \"$_petoncle_user_dir/.zshrc\""));
+        assert!(script.contains(r"\033]133;C;%s\007"));
+        assert!(script.contains(r"\033]133;D;%s\007"));
+        assert!(script.contains(r"\033]133;A\007"));
+        assert!(script.contains(r"PS1+=$'%{\e]133;B\a%}'"));
+        assert!(script.contains(r"\033]7;file://%s%s\007"));
+        assert!(script.contains(r#"petoncle_encode_path "$PWD""#));
+    }
+
+    #[test]
+    fn test_clean_environment() {
+        let vars = |pairs: &[(&str, &str)]| -> Vec<(OsString, OsString)> {
+            pairs.iter().map(|&(key, value)| (key.into(), value.into())).collect()
+        };
+        let inherited = vars(&[("PATH", "/usr/bin"), ("AWS_SECRET_ACCESS_KEY", "x"), ("HOME", "/home/u")]);
+        assert_eq!(clean_environment(inherited), vars(&[("PATH", "/usr/bin"), ("HOME", "/home/u")]));
+    }
+
+    #[test]
+    fn test_parse_shell() {
+        assert_eq!("zsh".parse::<Shell>().unwrap(), Shell::Zsh);
+        assert!("bash".parse::<Shell>().is_err());
+    }
+
+    /// Hooks survive a framework-style config (oh-my-zsh, prezto): ZDOTDIR moved by .zshenv,
+    /// hook arrays reset, user precmd/preexec functions, a config re-sourcing $ZDOTDIR/.zshrc
+    /// Skipped when zsh isn't installed
+    #[test]
+    fn test_zsh_hooks_survive_framework_config() {
+        if Command::new("zsh").arg("--version").output().is_err() {
+            return;
+        }
+
+        let root = std::env::temp_dir().join(format!("petoncle-hooks-{}", std::process::id()));
+        let home = root.join("home");
+        let user_zdotdir = home.join(".config").join("zsh");
+        let petoncle_dir = root.join("petoncle");
+        for dir in [&user_zdotdir, &petoncle_dir] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        std::fs::write(home.join(".zshenv"), "export ZDOTDIR=\"$HOME/.config/zsh\"\n").unwrap();
+        std::fs::write(
+            user_zdotdir.join(".zshrc"),
+            r#"
+autoload -Uz add-zsh-hook
+precmd_functions=()
+user_precmd() { :; }
+add-zsh-hook precmd user_precmd
+precmd() { :; }
+preexec() { :; }
+[[ -n $USER_RC ]] || { USER_RC=1; source "$ZDOTDIR/.zshrc" }
+"#,
+        )
+        .unwrap();
+        write_hook_files(Shell::Zsh, &petoncle_dir).unwrap();
+
+        let output = Command::new("zsh")
+            .args([
+                "-i",
+                "-c",
+                r#"print -r -- "$USER_RC|$precmd_functions|$preexec_functions|${+functions[precmd]}|$ZDOTDIR""#,
+            ])
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", &home)
+            .env("ZDOTDIR", &petoncle_dir)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let last = stdout.lines().last().unwrap_or_default();
+
+        assert_eq!(
+            last,
+            format!(
+                "1|petoncle_precmd user_precmd petoncle_mark_prompt petoncle_deferred_install|petoncle_preexec|1|{}",
+                petoncle_dir.display()
+            )
+        );
+        std::fs::remove_dir_all(&root).ok();
+    }
+}
@@ -0,0 +1,405 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use serde::Deserialize;
+use std::fmt;
+
+/// xterm codes for F5-F12, sent as ESC [ <code> ~ (16 and 22 are not used)
+const F5_TO_F12_CODES: [&str; 8] = ["15", "17", "18", "19", "20", "21", "23", "24"];
+
+/// Whether a key event is typed input: presses and auto-repeats
+/// Terminals speaking the Kitty keyboard protocol (or Windows consoles) also report releases,
+/// handling those as presses would send every key twice
+pub fn is_key_input(key_event: &KeyEvent) -> bool {
+    key_event.kind != KeyEventKind::Release
+}
+
+/// Whether a key event is the initial press, not an auto-repeat of a held key
+/// Hotkeys act on presses only: holding the capture toggle mustn't flip it on and off
+pub fn is_key_press(key_event: &KeyEvent) -> bool {
+    key_event.kind == KeyEventKind::Press
+}
+
+/// Convert crossterm KeyEvent to bytes to send to PTY
+/// Alt/Meta is sent as an ESC prefix (xterm metaSendsEscape), e.g. Alt+b -> ESC b
+/// Keys without a legacy encoding (media keys, lone modifiers) give no bytes
+pub fn key_event_to_bytes(key_event: KeyEvent) -> Vec<u8> {
+    if let Some(bytes) = modified_special_key(key_event) {
+        return bytes;
+    }
+    let bytes = unmodified_bytes(key_event);
+    if key_event.modifiers.contains(KeyModifiers::ALT) && !bytes.is_empty() {
+        [&[27][..], &bytes].concat()
+    } else {
+        bytes
+    }
+}
+
+/// Navigation and function keys with Shift or Ctrl in xterm's form, with the modifiers as a
+/// parameter (Ctrl+Left -> ESC [1;5D, Shift+F5 -> ESC [15;2~): shells bind these for word motion
+fn modified_special_key(key_event: KeyEvent) -> Option<Vec<u8>> {
+    let modifiers = key_event.modifiers;
+    if !modifiers.intersects(KeyModifiers::SHIFT | KeyModifiers::CONTROL) {
+        return None;
+    }
+    // 1 + Shift (1) + Alt (2) + Ctrl (4)
+    let param = 1
+        + u8::from(modifiers.contains(KeyModifiers::SHIFT))
+        + 2 * u8::from(modifiers.contains(KeyModifiers::ALT))
+        + 4 * u8::from(modifiers.contains(KeyModifiers::CONTROL));
+
+    let sequence = match key_event.code {
+        KeyCode::Up => format!("\x1b[1;{}A", param),
+        KeyCode::Down => format!("\x1b[1;{}B", param),
+        KeyCode::Right => format!("\x1b[1;{}C", param),
+        KeyCode::Left => format!("\x1b[1;{}D", param),
+        KeyCode::Home => format!("\x1b[1;{}H", param),
+        KeyCode::End => format!("\x1b[1;{}F", param),
+        KeyCode::Insert => format!("\x1b[2;{}~", param),
+        KeyCode::Delete => format!("\x1b[3;{}~", param),
+        KeyCode::PageUp => format!("\x1b[5;{}~", param),
+        KeyCode::PageDown => format!("\x1b[6;{}~", param),
+        KeyCode::F(n @ 1..=4) => format!("\x1b[1;{}{}", param, (b'P' + n - 1) as char),
+        KeyCode::F(n @ 5..=12) => format!("\x1b[{};{}~", F5_TO_F12_CODES[(n - 5) as usize], param),
+        _ => return None,
+    };
+    Some(sequence.into_bytes())
+}
+
+/// Encoding of the key ignoring the Alt modifier
+fn unmodified_bytes(key_event: KeyEvent) -> Vec<u8> {
+    match key_event.code {
+        KeyCode::Char(c) => {
+            if key_event.modifiers.contains(KeyModifiers::CONTROL) {
+                // Handle Ctrl+ combinations, Ctrl+Shift+letter (reported by CSI-u terminals)
+                // is the same control character as in legacy terminals
+                match c {
+                    'a'..='z' | 'A'..='Z' => vec![c.to_ascii_lowercase() as u8 - b'a' + 1],
+                    '@' | ' ' => vec![0],
+                    '[' => vec![27],
+                    '\\' => vec![28],
+                    ']' => vec![29],
+                    '^' => vec![30],
+                    '_' => vec![31],
+                    '?' => vec![127],
+                    _ => c.to_string().into_bytes(),
+                }
+            } else {
+                c.to_string().into_bytes()
+            }
+        }
+        KeyCode::Enter => vec![b'\r'],
+        KeyCode::Backspace => vec![127],
+        KeyCode::Tab => vec![b'\t'],
+        KeyCode::BackTab => vec![27, 91, 90],
+        KeyCode::Esc => vec![27],
+        KeyCode::Up => vec![27, 91, 65],
+        KeyCode::Down => vec![27, 91, 66],
+        KeyCode::Right => vec![27, 91, 67],
+        KeyCode::Left => vec![27, 91, 68],
+        KeyCode::Home => vec![27, 91, 72],
+        KeyCode::End => vec![27, 91, 70],
+        KeyCode::PageUp => vec![27, 91, 53, 126],
+        KeyCode::PageDown => vec![27, 91, 54, 126],
+        KeyCode::Delete => vec![27, 91, 51, 126],
+        KeyCode::Insert => vec![27, 91, 50, 126],
+        KeyCode::F(n) => match n {
+            // F1-F4 use SS3 sequences (ESC O P..S)
+            1 => vec![27, 79, 80],
+            2 => vec![27, 79, 81],
+            3 => vec![27, 79, 82],
+            4 => vec![27, 79, 83],
+            5..=12 => format!("\x1b[{}~", F5_TO_F12_CODES[(n - 5) as usize]).into_bytes(),
+            _ => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+/// A configurable hotkey such as "f2", "ctrl+o" or "alt+s"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeyBinding {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    /// Whether a key press is this binding (Shift is ignored, it's part of the character)
+    pub fn matches(&self, key_event: &KeyEvent) -> bool {
+        let modifiers = key_event.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT);
+        let code = match key_event.code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code,
+        };
+        code == self.code && modifiers == self.modifiers
+    }
+
+    /// A function key without modifiers (F1-F12)
+    pub const fn function_key(n: u8) -> Self {
+        Self {
+            code: KeyCode::F(n),
+            modifiers: KeyModifiers::NONE,
+        }
+    }
+}
+
+impl Default for KeyBinding {
+    /// F2: rarely used by shells, and free in most terminal emulators
+    fn default() -> Self {
+        Self::function_key(2)
+    }
+}
+
+impl KeyBinding {
+    /// Parse "ctrl+o", "f2"... `bare_chars` allows a character without modifier ("c")
+    fn parse(spec: &str, bare_chars: bool) -> Result<Self, String> {
+        let lower = spec.trim().to_ascii_lowercase();
+        let mut parts: Vec<&str> = lower.split('+').collect();
+        let key = parts.pop().unwrap_or_default();
+
+        let mut modifiers = KeyModifiers::NONE;
+        for part in parts {
+            match part {
+                "ctrl" | "control" => modifiers |= KeyModifiers::CONTROL,
+                "alt" | "meta" => modifiers |= KeyModifiers::ALT,
+                _ => return Err(format!("unknown modifier {:?} in key {:?}", part, spec)),
+            }
+        }
+
+        let code = match key {
+            k if k.chars().count() == 1 => KeyCode::Char(k.chars().next().unwrap_or_default()),
+            k if k.starts_with('f') => match k[1..].parse::<u8>() {
+                Ok(n @ 1..=12) => KeyCode::F(n),
+                _ => return Err(format!("unknown key {:?}", spec)),
+            },
+            _ => return Err(format!("unknown key {:?}", spec)),
+        };
+
+        // A bare character would be swallowed while typing in the shell
+        if matches!(code, KeyCode::Char(_)) && modifiers.is_empty() && !bare_chars {
+            return Err(format!("key {:?} needs ctrl+ or alt+", spec));
+        }
+        Ok(Self { code, modifiers })
+    }
+}
+
+impl TryFrom<String> for KeyBinding {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        Self::parse(&spec, false)
+    }
+}
+
+/// A key, or two keys pressed one after the other (tmux style), such as "f6" or "ctrl+a c"
+/// The second key may be a bare character: it's only taken after the first one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeyChord {
+    pub first: KeyBinding,
+    pub second: Option<KeyBinding>,
+}
+
+impl TryFrom<String> for KeyChord {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        match spec.split_whitespace().collect::<Vec<_>>()[..] {
+            [first] => Ok(Self {
+                first: KeyBinding::parse(first, false)?,
+                second: None,
+            }),
+            [first, second] => Ok(Self {
+                first: KeyBinding::parse(first, false)?,
+                second: Some(KeyBinding::parse(second, true)?),
+            }),
+            _ => Err(format!("key chord {:?} must be one or two keys", spec)),
+        }
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        if let Some(second) = self.second {
+            write!(f, " puis {}", second)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt+")?;
+        }
+        match self.code {
+            KeyCode::F(n) => write!(f, "F{}", n),
+            KeyCode::Char(c) => write!(f, "{}", c.to_ascii_uppercase()),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn test_key_encoding_table() {
+        let none = KeyModifiers::NONE;
+        let ctrl = KeyModifiers::CONTROL;
+
+        let cases: &[(KeyEvent, &[u8])] = &[
+            // Plain and multi-byte characters
+            (key(KeyCode::Char('a'), none), b"a"),
+            (key(KeyCode::Char('é'), none), "é".as_bytes()),
+            // Control characters
+            (key(KeyCode::Char('a'), ctrl), &[1]),
+            (key(KeyCode::Char('c'), ctrl), &[3]),
+            (key(KeyCode::Char('z'), ctrl), &[26]),
+            (key(KeyCode::Char('@'), ctrl), &[0]),
+            (key(KeyCode::Char('['), ctrl), &[27]),
+            (key(KeyCode::Char('\\'), ctrl), &[28]),
+            (key(KeyCode::Char(']'), ctrl), &[29]),
+            (key(KeyCode::Char('^'), ctrl), &[30]),
+            (key(KeyCode::Char('_'), ctrl), &[31]),
+            // Editing keys
+            (key(KeyCode::Enter, none), b"\r"),
+            (key(KeyCode::Backspace, none), &[127]),
+            (key(KeyCode::Tab, none), b"\t"),
+            (key(KeyCode::BackTab, KeyModifiers::SHIFT), b"\x1b[Z"),
+            (key(KeyCode::Esc, none), b"\x1b"),
+            // Cursor and navigation keys
+            (key(KeyCode::Up, none), b"\x1b[A"),
+            (key(KeyCode::Down, none), b"\x1b[B"),
+            (key(KeyCode::Right, none), b"\x1b[C"),
+            (key(KeyCode::Left, none), b"\x1b[D"),
+            (key(KeyCode::Home, none), b"\x1b[H"),
+            (key(KeyCode::End, none), b"\x1b[F"),
+            (key(KeyCode::PageUp, none), b"\x1b[5~"),
+            (key(KeyCode::PageDown, none), b"\x1b[6~"),
+            (key(KeyCode::Delete, none), b"\x1b[3~"),
+            (key(KeyCode::Insert, none), b"\x1b[2~"),
+            // Function keys
+            (key(KeyCode::F(1), none), b"\x1bOP"),
+            (key(KeyCode::F(4), none), b"\x1bOS"),
+            (key(KeyCode::F(5), none), b"\x1b[15~"),
+            (key(KeyCode::F(12), none), b"\x1b[24~"),
+            (key(KeyCode::F(13), none), b""),
+        ];
+
+        for (event, expected) in cases {
+            assert_eq!(key_event_to_bytes(*event), expected.to_vec(), "encoding {:?}", event);
+        }
+    }
+
+    #[test]
+    fn test_alt_prefixes_escape() {
+        let alt = KeyModifiers::ALT;
+        assert_eq!(key_event_to_bytes(key(KeyCode::Char('b'), alt)), b"\x1bb".to_vec());
+        assert_eq!(key_event_to_bytes(key(KeyCode::Char('f'), alt)), b"\x1bf".to_vec());
+        assert_eq!(key_event_to_bytes(key(KeyCode::Left, alt)), b"\x1b\x1b[D".to_vec());
+        assert_eq!(key_event_to_bytes(key(KeyCode::Backspace, alt)), vec![27, 127]);
+
+        // Ctrl+Alt combines both
+        assert_eq!(
+            key_event_to_bytes(key(KeyCode::Char('a'), alt | KeyModifiers::CONTROL)),
+            vec![27, 1]
+        );
+
+        // Keys without an encoding stay empty
+        assert!(key_event_to_bytes(key(KeyCode::F(20), alt)).is_empty());
+    }
+
+    #[test]
+    fn test_modified_keys_from_csi_u_terminals() {
+        let ctrl = KeyModifiers::CONTROL;
+        let shift = KeyModifiers::SHIFT;
+
+        assert_eq!(key_event_to_bytes(key(KeyCode::Left, ctrl)), b"\x1b[1;5D".to_vec());
+        assert_eq!(key_event_to_bytes(key(KeyCode::Right, ctrl | shift)), b"\x1b[1;6C".to_vec());
+        assert_eq!(key_event_to_bytes(key(KeyCode::Up, shift | KeyModifiers::ALT)), b"\x1b[1;4A".to_vec());
+        assert_eq!(key_event_to_bytes(key(KeyCode::Delete, ctrl)), b"\x1b[3;5~".to_vec());
+        assert_eq!(key_event_to_bytes(key(KeyCode::F(2), shift)), b"\x1b[1;2Q".to_vec());
+        assert_eq!(key_event_to_bytes(key(KeyCode::F(5), shift)), b"\x1b[15;2~".to_vec());
+        assert_eq!(key_event_to_bytes(key(KeyCode::BackTab, shift)), b"\x1b[Z".to_vec());
+
+        // Ctrl+Shift+letter, upper or lower case depending on the terminal
+        assert_eq!(key_event_to_bytes(key(KeyCode::Char('C'), ctrl | shift)), vec![3]);
+        assert_eq!(key_event_to_bytes(key(KeyCode::Char('c'), ctrl | shift)), vec![3]);
+        assert_eq!(key_event_to_bytes(key(KeyCode::Char(' '), ctrl)), vec![0]);
+
+        let mut release = key(KeyCode::Char('a'), KeyModifiers::NONE);
+        release.kind = KeyEventKind::Release;
+        assert!(!is_key_input(&release));
+        release.kind = KeyEventKind::Repeat;
+        assert!(is_key_input(&release));
+        assert!(!is_key_press(&release));
+        assert!(is_key_press(&key(KeyCode::F(3), KeyModifiers::NONE)));
+    }
+
+    #[test]
+    fn test_key_binding_parse_and_match() {
+        let f2 = KeyBinding::try_from("F2".to_string()).unwrap();
+        assert!(f2.matches(&key(KeyCode::F(2), KeyModifiers::NONE)));
+        assert!(!f2.matches(&key(KeyCode::F(2), KeyModifiers::CONTROL)));
+        assert_eq!(f2.to_string(), "F2");
+
+        let ctrl_o = KeyBinding::try_from("ctrl+o".to_string()).unwrap();
+        assert!(ctrl_o.matches(&key(KeyCode::Char('o'), KeyModifiers::CONTROL)));
+        assert!(!ctrl_o.matches(&key(KeyCode::Char('o'), KeyModifiers::NONE)));
+        assert_eq!(ctrl_o.to_string(), "Ctrl+O");
+
+        assert!(KeyBinding::try_from("s".to_string()).is_err());
+        assert!(KeyBinding::try_from("hyper+s".to_string()).is_err());
+        assert!(KeyBinding::try_from("f13".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_key_chord_parse() {
+        let chord = KeyChord::try_from("ctrl+a c".to_string()).unwrap();
+        assert!(chord.first.matches(&key(KeyCode::Char('a'), KeyModifiers::CONTROL)));
+        assert!(chord.second.unwrap().matches(&key(KeyCode::Char('c'), KeyModifiers::NONE)));
+        assert_eq!(chord.to_string(), "Ctrl+A puis C");
+
+        assert_eq!(KeyChord::try_from("f6".to_string()).unwrap().second, None);
+        assert!(KeyChord::try_from("c ctrl+a".to_string()).is_err());
+        assert!(KeyChord::try_from("ctrl+a b c".to_string()).is_err());
+        assert!(KeyChord::try_from(String::new()).is_err());
+    }
+
+    #[test]
+    fn test_function_keys() {
+        let expected: [&[u8]; 12] = [
+            b"\x1bOP",
+            b"\x1bOQ",
+            b"\x1bOR",
+            b"\x1bOS",
+            b"\x1b[15~",
+            b"\x1b[17~",
+            b"\x1b[18~",
+            b"\x1b[19~",
+            b"\x1b[20~",
+            b"\x1b[21~",
+            b"\x1b[23~",
+            b"\x1b[24~",
+        ];
+
+        for (i, bytes) in expected.iter().enumerate() {
+            let n = i as u8 + 1;
+            assert_eq!(
+                key_event_to_bytes(key(KeyCode::F(n), KeyModifiers::NONE)),
+                bytes.to_vec(),
+                "F{}",
+                n
+            );
+        }
+    }
+}
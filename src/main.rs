@@ -1,32 +1,123 @@
+mod actions;
+mod attach;
+mod bench;
+mod broadcast;
 mod capture;
+mod capture_debug;
+mod cast;
 mod chat;
+mod clipboard;
+mod cli;
+mod commands;
+mod complete;
+mod config;
+mod control;
+mod diff;
+mod doctor;
+mod export;
+mod failure_hook;
+mod fuzzy;
 mod grpc_client;
+mod history;
+mod hooks;
+mod keys;
+mod markdown;
+mod mock;
+mod paste_guard;
+mod pending;
+mod resize;
+mod scrollback;
+mod session;
+mod theme;
+mod transport;
+mod trigger;
+mod viewer;
 
-use anyhow::{Context, Result};
-use capture::CommandCapture;
+use anyhow::{anyhow, bail, Context, Result};
+use broadcast::{BroadcastWriter, Follower};
+use capture::{CapturedCommand, CommandCapture, CommandSink};
+use cast::CastRecorder;
 use chat::{ChatLoopResult, ChatState};
+use complete::{Completion, LineTracker};
+use cli::{Cli, Command};
+use failure_hook::FailureHook;
+use grpc_client::AgentClient;
+use history::HistorySink;
+use keys::{is_key_input, is_key_press, key_event_to_bytes, KeyBinding, KeyChord};
+use config::{BackendConfig, ChatScreen, Config};
+use control::ControlServer;
 use crossterm::{
-    event::{self, Event, KeyCode, KeyModifiers},
+    event::{self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use portable_pty::{native_pty_system, CommandBuilder, PtySize};
-use ratatui::{backend::CrosstermBackend, Terminal};
+use paste_guard::PasteGuard;
+use pending::OutputGate;
+use portable_pty::{native_pty_system, CommandBuilder, MasterPty, PtySize};
+use resize::ResizeDebounce;
+use scrollback::Scrollback;
+use theme::Theme;
+use ratatui::{backend::CrosstermBackend, Terminal, TerminalOptions, Viewport};
+use std::ffi::OsString;
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{self, ErrorKind, Read, Stdout, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
-use tracing::{debug, error, info, warn};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, info_span, warn, Span};
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
+use trigger::{BangAction, ChatTrigger, ChordAction, ChordState, TriggerState};
+use viewer::ScrollbackView;
+
+/// How long shutdown waits for the output thread to write the shell's last bytes
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// errno of a write to a PTY master whose shell side is closed (Linux, macOS)
+const EIO: i32 = 5;
 
 /// Main entry point for Petoncle terminal wrapper
 fn main() -> Result<()> {
+    let cli = Cli::parse_args();
+    let args = &cli.session;
+
+    match cli.command {
+        // Environment report instead of a session
+        Some(Command::Doctor) => {
+            if !doctor::run(cli.shell, cli.agent_addr.as_deref()) {
+                bail!("petoncle doctor found problems");
+            }
+            return Ok(());
+        }
+        Some(Command::Ask { ref question }) => return ask(&question.join(" "), cli.agent_addr.as_deref()),
+        None => {}
+    }
+
+    // Dry run: show what would be injected into the shell
+    if args.print_hooks {
+        print!("{}", hooks::hook_script(cli.shell));
+        return Ok(());
+    }
+
+    // Capture overhead measurement instead of a session
+    if args.bench {
+        return bench::run(cli.shell);
+    }
+
+    // Checked before raw mode, while the error can still be printed plainly
+    if let Some(ref dir) = args.cwd
+        && !dir.is_dir()
+    {
+        bail!("--cwd: {} is not a directory", dir.display());
+    }
+
     // Initialize tracing subscriber
     // Use RUST_LOG environment variable to control log level
     // Example: RUST_LOG=petoncle=debug cargo run
-    let log_file = std::env::temp_dir().join(format!("petoncle-{}.log", std::process::id()));
+    let session_id = session::new_session_id();
+    let log_file = std::env::temp_dir().join(format!("petoncle-{}.log", session_id));
     let log_file_display = log_file.clone();
 
     let file_layer = fmt::layer()
@@ -45,15 +136,61 @@ fn main() -> Result<()> {
         .with(file_layer)
         .init();
 
+    // Every line logged from here carries the session ID, threads enter the span explicitly
+    let _session_span = info_span!("session", id = %session_id).entered();
+
     info!("🐚 Petoncle starting - AI-Powered Terminal Wrapper");
 
-    println!("🐚 Petoncle - AI-Powered Terminal Wrapper");
-    println!("💡 Appuyez sur '!' pour ouvrir le chat AI");
-    println!("📝 Logs: {}", log_file_display.display());
-    println!("Starting zsh session...\n");
+    // Load user configuration, a broken config file shouldn't prevent the shell from starting
+    let mut config = Config::load().unwrap_or_else(|e| {
+        warn!("Failed to load config, using defaults: {:#}", e);
+        Config::default()
+    });
+    apply_cli_overrides(&mut config, &cli);
 
-    // Small delay to let message display before raw mode
-    thread::sleep(Duration::from_millis(100));
+    info!("Logging to {}", log_file_display.display());
+
+    if !args.quiet {
+        println!("🐚 Petoncle - AI-Powered Terminal Wrapper");
+        match config.chat_trigger {
+            ChatTrigger::Single => {
+                println!("💡 Appuyez sur '!' pour ouvrir le chat AI ('\\!' pour un '!' littéral)")
+            }
+            ChatTrigger::Double => println!("💡 Appuyez deux fois sur '!' pour ouvrir le chat AI"),
+            ChatTrigger::Off => println!("💡 Chat AI désactivé ('!' va au shell)"),
+        }
+        if !config.chat_keys.is_empty() {
+            let keys: Vec<String> = config.chat_keys.iter().map(KeyChord::to_string).collect();
+            println!("💬 Chat AI aussi avec: {}", keys.join(", "));
+        }
+        println!("📜 {} pour parcourir la sortie du shell", config.scrollback_key);
+        println!("✨ {} pour faire compléter la commande en cours par l'agent", config.complete_key);
+        if let Some(key) = config.capture_debug_key {
+            println!("🔎 {} pour voir les marqueurs du shell", key);
+        }
+        println!(
+            "⏺ Capture des commandes: {} ({} pour basculer)",
+            capture::recording_indicator(config.capture_enabled),
+            config.capture_key
+        );
+        if let Some(count) = args.split {
+            println!("🔀 Diffusion: la saisie va à {} shells, seul le premier est affiché", count);
+        }
+        println!("📝 Logs: {} (session {})", log_file_display.display(), session_id);
+        match args.wrapped() {
+            Some((program, _)) => {
+                println!(
+                    "⚠️ {} lancé sans les hooks du shell: commandes capturées seulement via OSC 133",
+                    program
+                );
+                println!("Starting {}...\n", program);
+            }
+            None => println!("Starting zsh session...\n"),
+        }
+
+        // Small delay to let message display before raw mode
+        thread::sleep(Duration::from_millis(100));
+    }
 
     // Get terminal size
     let (cols, rows) = crossterm::terminal::size().unwrap_or((80, 24));
@@ -63,97 +200,117 @@ fn main() -> Result<()> {
     let pty_system = native_pty_system();
 
     // Create a new PTY with actual terminal size
-    let pair = pty_system
-        .openpty(PtySize {
-            rows,
-            cols,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
-        .context("Failed to create PTY")?;
+    let pty_size = PtySize {
+        rows,
+        cols,
+        pixel_width: 0,
+        pixel_height: 0,
+    };
+    let pair = pty_system.openpty(pty_size).context("Failed to create PTY")?;
     info!("PTY created successfully");
 
-    // Create temporary directory for zsh hooks
+    // Create temporary directory for zsh hooks (and the control socket)
+    // Owner-only: the control socket is bound in it before its own permissions are restricted
     let temp_dir = std::env::temp_dir().join(format!("petoncle-{}", std::process::id()));
-    fs::create_dir_all(&temp_dir).context("Failed to create temp dir for hooks")?;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&temp_dir)
+        .context("Failed to create temp dir for hooks")?;
+    fs::set_permissions(&temp_dir, fs::Permissions::from_mode(0o700))
+        .context("Failed to restrict temp dir permissions")?;
     debug!("Created temp directory: {}", temp_dir.display());
 
-    // Create temporary .zshrc with our hooks + source user's real config
-    let temp_zshrc = temp_dir.join(".zshrc");
-    let zsh_hooks_content = r#"# Source user's real .zshrc first (so our hooks don't get overwritten)
-if [ -f "$HOME/.zshrc" ]; then
-    source "$HOME/.zshrc"
-fi
-
-# Petoncle command tracking hooks (defined after user config)
-# Use add-zsh-hook if available to avoid overwriting user hooks
-if (( $+functions[add-zsh-hook] )); then
-    # Use add-zsh-hook to add our hooks without overwriting existing ones
-    petoncle_preexec() {
-        # OSC 133;C marks command start
-        printf '\033]133;C;%s\007' "$1"
-    }
-
-    petoncle_precmd() {
-        # OSC 133;D marks command end with exit code
-        printf '\033]133;D;%s\007' "$?"
-    }
-
-    add-zsh-hook preexec petoncle_preexec
-    add-zsh-hook precmd petoncle_precmd
-else
-    # Fallback: save existing hooks and call them
-    if (( $+functions[preexec] )); then
-        functions[_petoncle_user_preexec]=$functions[preexec]
-    fi
-    if (( $+functions[precmd] )); then
-        functions[_petoncle_user_precmd]=$functions[precmd]
-    fi
-
-    preexec() {
-        # Call user's preexec if it exists
-        if (( $+functions[_petoncle_user_preexec] )); then
-            _petoncle_user_preexec "$@"
-        fi
-        # OSC 133;C marks command start
-        printf '\033]133;C;%s\007' "$1"
-    }
-
-    precmd() {
-        # Call user's precmd if it exists
-        if (( $+functions[_petoncle_user_precmd] )); then
-            _petoncle_user_precmd "$@"
-        fi
-        # OSC 133;D marks command end with exit code
-        printf '\033]133;D;%s\007' "$?"
-    }
-fi
-"#;
-    fs::write(&temp_zshrc, zsh_hooks_content).context("Failed to write temp .zshrc")?;
-
-    // Spawn zsh shell with ZDOTDIR pointing to our temp directory
-    let mut cmd = CommandBuilder::new("zsh");
-    cmd.env("TERM", "xterm-256color");
-    cmd.env("ZDOTDIR", &temp_dir); // zsh will load .zshrc from here
-
-    // Start in the same directory where Petoncle was launched
-    if let Ok(cwd) = std::env::current_dir() {
+    let mut cmd = match args.wrapped() {
+        // Any other program runs as is, its startup isn't ours to hook
+        Some((program, program_args)) => {
+            let mut cmd = CommandBuilder::new(program);
+            cmd.args(program_args);
+            cmd
+        }
+        None => {
+            // Temporary .zshenv/.zshrc with our hooks, sourcing the user's real config
+            hooks::write_hook_files(cli.shell, &temp_dir).context("Failed to write shell startup files")?;
+            CommandBuilder::new(cli.shell.program())
+        }
+    };
+    let program = args.wrapped().map_or(cli.shell.program(), |(program, _)| program);
+    if args.clean_env {
+        cmd.env_clear();
+        for (key, value) in hooks::clean_environment(std::env::vars_os()) {
+            cmd.env(key, value);
+        }
+    }
+    // Set before Petoncle's own variables, which the hooks depend on; TERM can be overridden
+    let env_var = |name: &str| args.env.iter().find(|(key, _)| key == name).map(|(_, value)| OsString::from(value));
+    for (key, value) in &args.env {
+        cmd.env(key, value);
+    }
+    if env_var("TERM").is_none() {
+        cmd.env("TERM", "xterm-256color");
+    }
+    if args.wrapped().is_none() {
+        cmd.env("ZDOTDIR", &temp_dir); // zsh will load .zshenv and .zshrc from here
+
+        // The startup files find the user's config through this (already set when Petoncle is nested)
+        if let Some(user_zdotdir) = env_var("ZDOTDIR")
+            .or_else(|| std::env::var_os(hooks::USER_ZDOTDIR_VAR))
+            .or_else(|| std::env::var_os("ZDOTDIR"))
+        {
+            cmd.env(hooks::USER_ZDOTDIR_VAR, user_zdotdir);
+        }
+    }
+    cmd.env("PETONCLE_LOG_FILE", &log_file_display); // Log path stays reachable in quiet mode
+    cmd.env(session::SESSION_ID_VAR, &session_id);
+
+    // Tools run inside the shell find the control socket through the environment
+    let socket_path = temp_dir.join("control.sock");
+    if config.control_socket {
+        cmd.env("PETONCLE_SOCKET", &socket_path);
+    }
+
+    // Start in the same directory where Petoncle was launched, unless --cwd says otherwise
+    if let Some(ref dir) = args.cwd {
+        cmd.cwd(dir);
+    } else if let Ok(cwd) = std::env::current_dir() {
         cmd.cwd(cwd);
     }
 
+    // Broadcast mode: the other shells get the same environment and every keystroke
+    let mut followers = Vec::new();
+    let mut follower_writers = Vec::new();
+    for number in 2..=usize::from(args.split.unwrap_or(1)) {
+        let (follower, writer) = Follower::spawn(&*pty_system, pty_size, cmd.clone(), number)
+            .with_context(|| format!("Failed to start shell {} for --split", number))?;
+        followers.push(follower);
+        follower_writers.push(writer);
+    }
+
     let mut child = pair
         .slave
         .spawn_command(cmd)
-        .context("Failed to spawn zsh")?;
-    info!("zsh shell spawned successfully");
+        .with_context(|| format!("Failed to spawn {}", program))?;
+    info!("{} spawned successfully", program);
 
     // Get reader and writer from master PTY
     let mut reader = pair.master.try_clone_reader()?;
-    let writer = Arc::new(Mutex::new(pair.master.take_writer()?));
+    let mut shell_writer = pair.master.take_writer()?;
+    if !follower_writers.is_empty() {
+        info!("Broadcasting input to {} shells", follower_writers.len() + 1);
+        shell_writer = Box::new(BroadcastWriter::new(shell_writer, follower_writers));
+    }
+    let writer = Arc::new(Mutex::new(shell_writer));
+
+    // -e: typed ahead, the shell runs it once its startup files are loaded
+    if let Some(ref command) = args.exec
+        && let Err(e) = write_to_shell(&writer, format!("{}\r", command).as_bytes())
+    {
+        warn!("Failed to send the -e command: {}", e);
+    }
     let writer_clone = writer.clone();
 
     // Shared buffer for shell output
-    let output_buffer = Arc::new(Mutex::new(Vec::new()));
+    let output_buffer = Arc::new(Mutex::new(Scrollback::new(config.scrollback_bytes)));
     let output_buffer_clone = output_buffer.clone();
 
     // Shared flag to signal shutdown
@@ -161,23 +318,98 @@ fi
     let running_clone1 = running.clone();
     let running_clone2 = running.clone();
 
-    // Shared flag to pause output during chat
-    let output_paused = Arc::new(AtomicBool::new(false));
-    let output_paused_clone = output_paused.clone();
+    // Shell output is held while an overlay covers the shell
+    let output_gate = Arc::new(OutputGate::new(config.pending_output));
+    let output_gate_clone = output_gate.clone();
+
+    // Whether the shell enabled bracketed paste (ESC[?2004h), so pastes are forwarded the way it expects
+    let bracketed_paste = Arc::new(AtomicBool::new(false));
+    let bracketed_paste_clone = bracketed_paste.clone();
+
+    let paste = PasteInput {
+        guard: PasteGuard::new(&config.paste_guard),
+        bracketed: bracketed_paste,
+    };
+
+    // Create command capture system
+    let mut capture = CommandCapture::new()
+        .with_max_commands(config.max_commands)
+        .with_output_capture(config.capture_output)
+        .with_max_command_bytes(config.max_command_bytes)
+        .with_notes_in_context(config.context_notes)
+        .with_recording(config.capture_enabled);
+    capture.add_sink(Box::new(LogSink));
+    if let Some(ref hook) = config.on_failure {
+        capture.add_sink(Box::new(FailureHook::new(hook.clone(), session_id.clone())));
+    }
+    if config.history.enabled {
+        match config.history.resolved_path() {
+            Some(path) => match HistorySink::open(path, Duration::from_secs(config.history.checkpoint_secs)) {
+                Ok(sink) => capture.add_sink(Box::new(sink)),
+                Err(e) => warn!("Command history disabled: {:#}", e),
+            },
+            None => warn!("Command history disabled: no data directory"),
+        }
+    }
+    let command_capture = Arc::new(Mutex::new(capture));
 
     // Create persistent chat state
-    let chat_state = Arc::new(Mutex::new(ChatState::new()));
+    let mut chat = ChatState::new(&config);
+    chat.set_log_file(log_file_display.clone());
+    chat.set_session_id(session_id.clone());
+    chat.set_pty_writer(writer.clone());
+    chat.set_command_capture(command_capture.clone());
+    chat.set_output_gate(output_gate.clone());
+    let chat_state = Arc::new(Mutex::new(chat));
     let chat_state_clone = chat_state.clone();
 
-    // Create command capture system
-    let command_capture = Arc::new(Mutex::new(CommandCapture::new()));
+    // Optional control socket, removed when the server is dropped at the end of main
+    let _control_server = if config.control_socket {
+        let backend = chat_state.lock().map_err(|_| anyhow!("chat state unavailable"))?.shared_backend();
+        match ControlServer::start(socket_path, command_capture.clone(), backend, config.clone()) {
+            Ok(server) => {
+                debug!("Control socket ready at {}", server.path().display());
+                Some(server)
+            }
+            Err(e) => {
+                warn!("Control socket disabled: {:#}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
     let command_capture_clone = command_capture.clone();
 
     // Enable raw mode for proper terminal handling
     enable_raw_mode().context("Failed to enable raw mode")?;
 
+    // Receive pastes as a single event instead of individual keystrokes
+    execute!(std::io::stdout(), EnableBracketedPaste).ok();
+
+    // Only the markers of our own hooks are stripped, a wrapped program's belong to the recording
+    let mut cast = config.cast.path.as_deref().and_then(|path| {
+        let path = attach::expand_home(path);
+        let strip_markers = config.cast.strip_markers && args.wrapped().is_none();
+        match CastRecorder::create(&path, cols, rows, strip_markers) {
+            Ok(recorder) => {
+                info!("Recording the session to {}", path.display());
+                Some(recorder)
+            }
+            Err(e) => {
+                warn!("Session recording disabled: {:#}", e);
+                None
+            }
+        }
+    });
+
+    // Signalled once the output thread has written everything it read, so shutdown can wait for it
+    let (output_done_tx, output_done_rx) = mpsc::channel::<()>();
+
     // Thread to read from PTY and print to stdout
+    let session_span = Span::current();
     let output_thread = thread::spawn(move || {
+        let _entered = session_span.enter();
         let mut buf = [0u8; 8192];
         loop {
             if !running_clone1.load(Ordering::Relaxed) {
@@ -194,31 +426,36 @@ fi
                 Ok(n) => {
                     let data = &buf[..n];
 
-                    // Convert bytes to string for command capture
-                    if let Ok(text) = std::str::from_utf8(data) {
-                        // Process output for command capture with OSC 133 sequences
-                        let cwd = std::env::current_dir().unwrap_or_default();
-                        if let Ok(mut capture) = command_capture_clone.lock() {
-                            capture.process_output(text, &cwd);
-                        }
+                    if let Some(enabled) = bracketed_paste_mode(data) {
+                        bracketed_paste_clone.store(enabled, Ordering::Relaxed);
                     }
 
-                    // Store in buffer for RAG (will be used later)
-                    if let Ok(mut buffer) = output_buffer_clone.lock() {
-                        buffer.extend_from_slice(data);
+                    // Process output for command capture with OSC 133 sequences
+                    let cwd = std::env::current_dir().unwrap_or_default();
+                    if let Ok(mut capture) = command_capture_clone.lock() {
+                        capture.process_bytes(data, &cwd);
+                    }
 
-                        // Keep last 100KB to avoid unbounded growth
-                        if buffer.len() > 100_000 {
-                            buffer.drain(..50_000);
-                        }
+                    // Keep recent output for the scrollback viewer
+                    if let Ok(mut buffer) = output_buffer_clone.lock() {
+                        buffer.push(data);
                     }
 
-                    // Print to stdout only if not in chat mode
-                    if !output_paused_clone.load(Ordering::Relaxed) {
-                        std::io::stdout().write_all(data).ok();
-                        std::io::stdout().flush().ok();
+                    // Print to stdout, or hold it while an overlay is open
+                    output_gate_clone.write(data, &mut std::io::stdout());
+
+                    if let Some(ref mut recorder) = cast
+                        && let Err(e) = recorder.write_output(data)
+                    {
+                        warn!("Session recording stopped: {:#}", e);
+                        cast = None;
                     }
                 }
+                Err(e) if matches!(e.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock) => {
+                    // Transient (EINTR around signals, EAGAIN): retry instead of ending the session
+                    debug!("Transient PTY read error, retrying: {:?}", e);
+                    thread::sleep(Duration::from_millis(5));
+                }
                 Err(e) => {
                     error!("Error reading from PTY: {:?}", e);
                     running_clone1.store(false, Ordering::Relaxed);
@@ -226,20 +463,82 @@ fi
                 }
             }
         }
+
+        std::io::stdout().flush().ok();
+        output_done_tx.send(()).ok();
     });
 
+    // Kept for shutdown (pending command) and the end-of-session summary
+    let summary_capture = command_capture.clone();
+
+    let chat_screen = config.chat_screen.resolve(std::env::var("TERM").ok().as_deref());
+    debug!("Chat screen: {:?}", chat_screen);
+
+    let overlays = Overlays {
+        screen: chat_screen,
+        chat_state: chat_state_clone,
+        scrollback: output_buffer,
+        scrollback_key: config.scrollback_key,
+        complete_key: config.complete_key,
+        capture_debug_key: config.capture_debug_key,
+        capture: command_capture.clone(),
+        chat_trigger: config.chat_trigger,
+        chat_keys: config.chat_keys.clone(),
+        chord_timeout: Duration::from_millis(config.chord_timeout_ms),
+        // Override errors are reported once, by the chat
+        theme: Theme::from_config(&config.theme).0,
+    };
+    let capture_toggle = CaptureToggle {
+        key: config.capture_key,
+        capture: command_capture.clone(),
+    };
+
+    let pty_resize = PtyResize {
+        lead: &*pair.master,
+        followers: &followers,
+        initial: (cols, rows),
+    };
+
     // Main input loop (handles both terminal and chat mode)
-    let input_loop_result = input_loop(writer_clone, running_clone2, output_paused, chat_state_clone, command_capture);
+    let input_loop_result = input_loop(
+        writer_clone,
+        running_clone2,
+        output_gate,
+        &overlays,
+        &paste,
+        &capture_toggle,
+        &pty_resize,
+    );
 
     // Cleanup
     running.store(false, Ordering::Relaxed);
-    thread::sleep(Duration::from_millis(100));
 
+    // Let the output thread write the shell's last bytes (e.g. "logout") while still in raw mode
+    // It only finishes on EOF, so don't wait forever if the loop ended with the shell still alive
+    let output_drained = match output_done_rx.recv_timeout(OUTPUT_DRAIN_TIMEOUT) {
+        Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+        Err(RecvTimeoutError::Timeout) => {
+            warn!("Output thread still reading after {:?}, not waiting for it", OUTPUT_DRAIN_TIMEOUT);
+            false
+        }
+    };
+
+    execute!(std::io::stdout(), DisableBracketedPaste).ok();
     disable_raw_mode().context("Failed to disable raw mode")?;
 
-    output_thread.join().ok();
+    if output_drained {
+        output_thread.join().ok();
+    }
+
+    // A command interrupted by the shell exiting never got its end marker
+    if let Ok(mut capture) = summary_capture.lock() {
+        capture.finalize_pending();
+    }
 
     let exit_status = child.wait()?;
+    for follower in followers {
+        follower.stop();
+    }
 
     // Cleanup temporary directory
     if let Err(e) = fs::remove_dir_all(&temp_dir) {
@@ -251,41 +550,314 @@ fi
     info!("Shell exited with status: {:?}", exit_status);
     println!("\n🐚 Shell exited with status: {:?}", exit_status);
 
+    // The backend active at exit, /backend may have switched it during the session
+    if config.summary_on_exit
+        && let Some(backend) = chat_state.lock().ok().map(|state| state.active_backend().clone())
+    {
+        print_session_summary(&summary_capture, &backend, &config);
+    }
+
     input_loop_result
 }
 
+/// Settings given on the command line win over the config file and the environment
+fn apply_cli_overrides(config: &mut Config, cli: &Cli) {
+    if let Some(ref addr) = cli.agent_addr {
+        config.override_agent_addr(addr);
+    }
+    if let Some(record) = cli.session.record() {
+        config.capture_enabled = record;
+    }
+    if cli.session.debug_capture {
+        config.capture_debug_key.get_or_insert(KeyBinding::function_key(5));
+    }
+}
+
+/// `petoncle ask`: one question to the agent, without a session or any shell context
+fn ask(question: &str, agent_addr: Option<&str>) -> Result<()> {
+    let mut config = Config::load()?;
+    if let Some(addr) = agent_addr {
+        config.override_agent_addr(addr);
+    }
+    let backend = config.backends().remove(0);
+    let response = transport::for_backend(&backend, config.mock).send(question.to_string(), Vec::new())?;
+    println!("{}", response.message);
+    Ok(())
+}
+
+/// Ask the agent to summarize the session and print the result
+/// Best effort: any failure is reported in one line and never affects the exit status
+fn print_session_summary(
+    command_capture: &Arc<Mutex<CommandCapture>>,
+    backend: &BackendConfig,
+    config: &Config,
+) {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let context = match command_capture.lock() {
+        Ok(capture) => capture.recent_context(
+            config.context_strategy,
+            capture.current_dir().unwrap_or(&cwd),
+            config.context_commands,
+            config.context_budget,
+        ),
+        Err(_) => return,
+    };
+    if context.is_empty() {
+        debug!("No captured commands, skipping session summary");
+        return;
+    }
+
+    println!("📝 Résumé de session en cours...");
+    let prompt = "Résume ce que j'ai fait pendant cette session de terminal \
+                  et signale tout ce qui semble préoccupant (erreurs, commandes risquées)."
+        .to_string();
+
+    let result = tokio::runtime::Runtime::new()
+        .map_err(anyhow::Error::from)
+        .and_then(|runtime| {
+            let mut client = AgentClient::for_backend(backend).with_max_retries(0);
+            runtime.block_on(client.send_message(prompt, context))
+        });
+
+    match result {
+        Ok(response) => println!("\n{}\n", response.message),
+        Err(e) => {
+            warn!("Session summary failed: {}", e);
+            println!("⚠️ Résumé indisponible: {}", e);
+        }
+    }
+}
+
+/// Sink logging every finished command to the session log
+struct LogSink;
+
+impl CommandSink for LogSink {
+    fn on_command(&mut self, cmd: &CapturedCommand) {
+        info!(
+            "Command finished: {:?} (exit: {:?}, {} bytes of output, cwd: {})",
+            cmd.command,
+            cmd.exit_code,
+            cmd.output.len(),
+            cmd.working_dir.display()
+        );
+    }
+}
+
+/// Full-screen views opened over the shell: the AI chat and the shell output viewer
+struct Overlays {
+    screen: ChatScreen,
+    chat_state: Arc<Mutex<ChatState>>,
+    scrollback: Arc<Mutex<Scrollback>>,
+    scrollback_key: KeyBinding,
+    complete_key: KeyBinding,
+    capture_debug_key: Option<KeyBinding>,
+    capture: Arc<Mutex<CommandCapture>>,
+    chat_trigger: ChatTrigger,
+    chat_keys: Vec<KeyChord>,
+    chord_timeout: Duration,
+    theme: Theme,
+}
+
+/// Hotkey pausing and resuming the command capture
+struct CaptureToggle {
+    key: KeyBinding,
+    capture: Arc<Mutex<CommandCapture>>,
+}
+
+impl CaptureToggle {
+    /// Flip the capture and show the new state in the terminal title, which stays visible
+    /// without drawing over the shell
+    fn toggle(&self) {
+        let Ok(mut capture) = self.capture.lock() else {
+            return;
+        };
+        let recording = !capture.is_recording();
+        capture.set_recording(recording);
+        info!("Command capture {}", if recording { "resumed" } else { "paused" });
+
+        let mut stdout = std::io::stdout();
+        write!(stdout, "\x1b]2;🐚 Petoncle {}\x07", capture::recording_indicator(recording)).ok();
+        stdout.flush().ok();
+    }
+}
+
+/// Whether a program started from the prompt is running: its function keys (htop, mc, vim)
+/// go to it rather than to Petoncle's hotkeys
+fn command_running(capture: &Mutex<CommandCapture>) -> bool {
+    capture.lock().is_ok_and(|capture| capture.current().is_some_and(|cmd| !cmd.is_complete()))
+}
+
+/// Pastes: the dangerous ones are confirmed, and they're wrapped in bracketed paste markers
+/// when the shell enabled them
+struct PasteInput {
+    guard: PasteGuard,
+    bracketed: Arc<AtomicBool>,
+}
+
+/// PTYs following the terminal size: the lead shell's and the followers' (`--split`)
+struct PtyResize<'a> {
+    lead: &'a dyn MasterPty,
+    followers: &'a [Follower],
+    initial: (u16, u16),
+}
+
+impl PtyResize<'_> {
+    fn apply(&self, (cols, rows): (u16, u16)) {
+        let size = PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        };
+        if let Err(e) = self.lead.resize(size) {
+            warn!("Failed to resize the PTY to {}x{}: {}", cols, rows, e);
+            return;
+        }
+        for follower in self.followers {
+            follower.resize(size);
+        }
+        debug!("PTY resized to {}x{}", cols, rows);
+    }
+}
+
 /// Main input loop that handles terminal mode and chat mode
 fn input_loop(
     writer: Arc<Mutex<Box<dyn Write + Send>>>,
     running: Arc<AtomicBool>,
-    output_paused: Arc<AtomicBool>,
-    chat_state: Arc<Mutex<ChatState>>,
-    _command_capture: Arc<Mutex<CommandCapture>>,
+    output_gate: Arc<OutputGate>,
+    overlays: &Overlays,
+    paste: &PasteInput,
+    capture_toggle: &CaptureToggle,
+    pty_resize: &PtyResize,
 ) -> Result<()> {
-    // Note: Command capture now happens via zsh hooks (preexec/precmd)
-    // No need to track keystrokes manually
+    // Note: Command capture happens via zsh hooks (preexec/precmd), keystrokes are only
+    // followed to know the line being typed for the AI completion
+    let mut typed_line = LineTracker::default();
+    let mut resize = ResizeDebounce::new(resize::RESIZE_SETTLE, pty_resize.initial);
+    let mut trigger = TriggerState::new(overlays.chat_trigger);
+    let mut chords = ChordState::new(overlays.chat_keys.clone(), overlays.chord_timeout);
 
     loop {
         if !running.load(Ordering::Relaxed) {
             break;
         }
 
-        // Poll for events with timeout
-        if event::poll(Duration::from_millis(100))? {
+        let now = Instant::now();
+        if let Some(size) = resize.take_settled(now) {
+            pty_resize.apply(size);
+        }
+        // A lone '!' in double tap mode: it was meant for the shell
+        if trigger.release_expired(now) && !send_to_shell(&writer, &running, &mut typed_line, b"!") {
+            break;
+        }
+        // Same for the first key of a chord that wasn't completed in time
+        if let Some(first) = chords.release_expired(now)
+            && !send_to_shell(&writer, &running, &mut typed_line, &key_event_to_bytes(first))
+        {
+            break;
+        }
+
+        // Poll for events with timeout, shorter while a resize, a '!' or a chord is pending
+        let idle = chords.poll_timeout(now, Duration::from_millis(100));
+        let timeout = resize.poll_timeout(now, trigger.poll_timeout(now, idle));
+        if event::poll(timeout)? {
             match event::read()? {
-                Event::Key(key_event) => {
+                Event::Key(key_event) if is_key_input(&key_event) => {
+                    // Configured chat keys and chords (a tmux-like prefix, then a key)
+                    let chord = chords.key(&key_event, Instant::now());
+                    if matches!(chord, ChordAction::Hold | ChordAction::OpenChat)
+                        && trigger.release()
+                        && !send_to_shell(&writer, &running, &mut typed_line, b"!")
+                    {
+                        break;
+                    }
+                    match chord {
+                        ChordAction::Hold => continue,
+                        ChordAction::OpenChat => {
+                            // Holding the key mustn't reopen the chat as soon as it's closed
+                            if is_key_press(&key_event) {
+                                open_chat(&output_gate, overlays, &mut resize);
+                            }
+                            continue;
+                        }
+                        ChordAction::Forward(Some(first)) => {
+                            if !send_to_shell(&writer, &running, &mut typed_line, &key_event_to_bytes(first)) {
+                                break;
+                            }
+                        }
+                        ChordAction::Forward(None) => {}
+                    }
+
                     // Check for '!' to trigger chat mode
                     if key_event.code == KeyCode::Char('!')
                         && !key_event.modifiers.contains(KeyModifiers::CONTROL)
                     {
-                        // Enter chat mode
-                        match enter_chat_mode(&output_paused, &chat_state) {
-                            Ok(ChatLoopResult::Closed) => {
-                                // Just closed, do nothing
+                        // A held '!' would reopen the chat as soon as it's closed
+                        if !is_key_press(&key_event) {
+                            continue;
+                        }
+
+                        match trigger.bang(Instant::now()) {
+                            BangAction::Hold => continue,
+                            // Sent below like any other key
+                            BangAction::Literal => {}
+                            BangAction::OpenChat => {
+                                open_chat(&output_gate, overlays, &mut resize);
+                                continue;
                             }
-                            Err(e) => {
-                                eprintln!("Chat error: {}", e);
+                        }
+                    } else if trigger.release() && !send_to_shell(&writer, &running, &mut typed_line, b"!") {
+                        break;
+                    }
+
+                    // Browse the shell output, e.g. what scrolled off before a full-screen program ran
+                    if overlays.scrollback_key.matches(&key_event) {
+                        if !is_key_press(&key_event) {
+                            continue;
+                        }
+                        if let Err(e) = open_scrollback_viewer(&output_gate, overlays) {
+                            error!("Scrollback viewer failed: {}", e);
+                        }
+                        resync_size(&mut resize);
+                        continue;
+                    }
+
+                    // Watch the shell markers arrive, when commands don't show up in the chat
+                    if overlays.capture_debug_key.is_some_and(|key| key.matches(&key_event)) {
+                        if !is_key_press(&key_event) {
+                            continue;
+                        }
+                        if let Err(e) = open_capture_debug(&output_gate, overlays) {
+                            error!("Capture debug panel failed: {}", e);
+                        }
+                        resync_size(&mut resize);
+                        continue;
+                    }
+
+                    // Let the agent finish the command being typed (a running program keeps the key)
+                    if overlays.complete_key.matches(&key_event) && !command_running(&overlays.capture) {
+                        if !is_key_press(&key_event) {
+                            continue;
+                        }
+                        let completion = complete_typed_line(&output_gate, overlays, &typed_line);
+                        resync_size(&mut resize);
+                        match completion {
+                            Ok(Some(completion)) => {
+                                let bytes = completion.to_bytes();
+                                if !send_to_shell(&writer, &running, &mut typed_line, &bytes) {
+                                    break;
+                                }
                             }
+                            Ok(None) => {}
+                            Err(e) => error!("Command completion failed: {}", e),
+                        }
+                        continue;
+                    }
+
+                    // Sensitive work: stop recording commands (and sending them to the agent)
+                    if capture_toggle.key.matches(&key_event) && !command_running(&capture_toggle.capture) {
+                        if is_key_press(&key_event) {
+                            capture_toggle.toggle();
                         }
                         continue;
                     }
@@ -294,9 +866,8 @@ fn input_loop(
                     if key_event.code == KeyCode::Char('d')
                         && key_event.modifiers.contains(KeyModifiers::CONTROL)
                     {
-                        if let Ok(mut w) = writer.lock() {
-                            w.write_all(&[4]).ok();
-                            w.flush().ok();
+                        if !send_to_shell(&writer, &running, &mut typed_line, &[4]) {
+                            break;
                         }
                         continue;
                     }
@@ -305,18 +876,49 @@ fn input_loop(
                     // Command tracking is now done via zsh hooks (preexec/precmd)
                     let bytes = key_event_to_bytes(key_event);
                     if !bytes.is_empty() {
-                        if let Ok(mut w) = writer.lock() {
-                            if w.write_all(&bytes).is_err() {
-                                break;
-                            }
-                            w.flush().ok();
+                        if !send_to_shell(&writer, &running, &mut typed_line, &bytes) {
+                            break;
                         }
+                        trigger.forwarded(&bytes);
                     }
                 }
-                Event::Resize(_w, _h) => {
-                    // Handle terminal resize
-                    // We'll implement this later when we add proper PTY resize support
+                Event::Paste(text) => {
+                    if let Some(first) = chords.release()
+                        && !send_to_shell(&writer, &running, &mut typed_line, &key_event_to_bytes(first))
+                    {
+                        break;
+                    }
+                    if trigger.release() && !send_to_shell(&writer, &running, &mut typed_line, b"!") {
+                        break;
+                    }
+
+                    // Dangerous pastes need an explicit confirmation before reaching the shell
+                    let matches = paste.guard.scan(&text);
+                    if !matches.is_empty() {
+                        warn!("Dangerous paste intercepted (matched: {:?})", matches);
+                        let confirmed = confirm_dangerous_paste(&output_gate, overlays, &text, &matches);
+                        resync_size(&mut resize);
+                        match confirmed {
+                            Ok(true) => info!("Dangerous paste confirmed by user"),
+                            Ok(false) => {
+                                info!("Dangerous paste discarded");
+                                continue;
+                            }
+                            Err(e) => {
+                                error!("Paste confirmation failed: {}", e);
+                                continue;
+                            }
+                        }
+                    }
+
+                    let bytes = paste_guard::paste_to_bytes(&text, paste.bracketed.load(Ordering::Relaxed));
+                    if let Err(e) = write_to_shell(&writer, &bytes) {
+                        stop_after_write_error(&running, &e);
+                        break;
+                    }
+                    typed_line.paste(&text);
                 }
+                Event::Resize(cols, rows) => resize.push((cols, rows), Instant::now()),
                 _ => {}
             }
         }
@@ -324,79 +926,220 @@ fn input_loop(
     Ok(())
 }
 
-/// Enter chat mode with ratatui overlay
-fn enter_chat_mode(
-    output_paused: &Arc<AtomicBool>,
-    chat_state: &Arc<Mutex<ChatState>>,
-) -> Result<ChatLoopResult> {
-    // Pause shell output
-    output_paused.store(true, Ordering::Relaxed);
+/// Open the chat over the shell, a failure is reported on the shell's screen
+fn open_chat(output_gate: &Arc<OutputGate>, overlays: &Overlays, resize: &mut ResizeDebounce) {
+    match enter_chat_mode(output_gate, overlays) {
+        Ok(ChatLoopResult::Closed) => {
+            // Just closed, do nothing
+        }
+        Err(e) => {
+            // Raw mode: the line must be returned to by hand
+            error!("Chat failed: {:#}", e);
+            eprint!("\r\n❌ Chat indisponible: {}\r\n", e);
+        }
+    }
+    resync_size(resize);
+}
 
-    // Setup terminal for ratatui
-    execute!(std::io::stdout(), EnterAlternateScreen)?;
+/// Overlays read the resize events themselves: pick up the size the terminal ended with
+fn resync_size(resize: &mut ResizeDebounce) {
+    if let Ok(size) = crossterm::terminal::size() {
+        resize.push(size, Instant::now());
+    }
+}
 
-    let backend = CrosstermBackend::new(std::io::stdout());
-    let mut terminal = Terminal::new(backend)?;
+/// Send keys to the shell and follow the typed line
+/// Returns false when the write failed and the session is stopping
+fn send_to_shell(
+    writer: &Mutex<Box<dyn Write + Send>>,
+    running: &AtomicBool,
+    typed_line: &mut LineTracker,
+    bytes: &[u8],
+) -> bool {
+    if let Err(e) = write_to_shell(writer, bytes) {
+        stop_after_write_error(running, &e);
+        return false;
+    }
+    typed_line.feed(bytes);
+    true
+}
+
+/// Forward input to the shell, a poisoned lock counts as a failed write
+fn write_to_shell(writer: &Mutex<Box<dyn Write + Send>>, bytes: &[u8]) -> io::Result<()> {
+    let mut w = writer.lock().map_err(|_| io::Error::other("PTY writer lock poisoned"))?;
+    w.write_all(bytes)?;
+    w.flush()
+}
 
-    terminal.clear()?;
+/// A failed write ends the session: the shell is gone (normal exit) or something actually broke
+fn stop_after_write_error(running: &AtomicBool, e: &io::Error) {
+    running.store(false, Ordering::Relaxed);
+    if e.kind() == ErrorKind::BrokenPipe || e.raw_os_error() == Some(EIO) {
+        info!("Shell input closed ({}), shutting down", e);
+    } else {
+        error!("Failed to write to the shell: {}", e);
+    }
+}
 
-    // Run chat loop with persistent state
-    let result = {
-        let mut state = chat_state.lock().unwrap();
-        chat::run_chat_loop(&mut terminal, &mut state)
+/// Enter chat mode with ratatui overlay
+fn enter_chat_mode(output_gate: &Arc<OutputGate>, overlays: &Overlays) -> Result<ChatLoopResult> {
+    // Restores the terminal and resumes shell output on every exit path, panics included
+    let _session = OverlayGuard::enter(output_gate, overlays.screen != ChatScreen::Inline)?;
+
+    run_in_viewport(overlay_viewport(overlays.screen), |terminal| {
+        // A panic elsewhere while holding the state is reported, the shell session goes on
+        let mut state = overlays.chat_state.lock().map_err(|_| anyhow!("chat state unavailable"))?;
+        chat::run_chat_loop(terminal, &mut state)
+    })
+}
+
+/// Open the read-only viewer on a snapshot of the recent shell output
+fn open_scrollback_viewer(output_gate: &Arc<OutputGate>, overlays: &Overlays) -> Result<()> {
+    // Copied out so the PTY reader isn't kept waiting while the lines are split
+    let snapshot = match overlays.scrollback.lock() {
+        Ok(buffer) => buffer.snapshot_recent(buffer.len()),
+        Err(_) => return Ok(()),
     };
+    let mut view = ScrollbackView::new(&snapshot);
 
-    // Cleanup and return to normal mode
-    execute!(std::io::stdout(), LeaveAlternateScreen)?;
+    let _session = OverlayGuard::enter(output_gate, overlays.screen != ChatScreen::Inline)?;
+    run_in_viewport(overlay_viewport(overlays.screen), |terminal| {
+        viewer::run_viewer(terminal, &mut view, &overlays.theme)
+    })
+}
 
-    // Resume shell output
-    output_paused.store(false, Ordering::Relaxed);
+/// Open the live panel of the shell integration markers
+fn open_capture_debug(output_gate: &Arc<OutputGate>, overlays: &Overlays) -> Result<()> {
+    let _session = OverlayGuard::enter(output_gate, overlays.screen != ChatScreen::Inline)?;
+    run_in_viewport(overlay_viewport(overlays.screen), |terminal| {
+        capture_debug::run_capture_debug(terminal, &overlays.capture, &overlays.theme)
+    })
+}
+
+/// Region overlays draw to for the configured screen mode
+fn overlay_viewport(screen: ChatScreen) -> Viewport {
+    match screen {
+        ChatScreen::Inline => {
+            // The shell output scrolls up out of the way instead of being overwritten
+            let (_, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+            Viewport::Inline(rows.saturating_sub(1).max(10))
+        }
+        _ => Viewport::Fullscreen,
+    }
+}
+
+/// Terminal state of an open overlay: shell output held, alternate screen entered
+/// Dropping it leaves the alternate screen and resumes output, after what was held meanwhile
+struct OverlayGuard {
+    output_gate: Arc<OutputGate>,
+    alternate_screen: bool,
+}
+
+impl OverlayGuard {
+    fn enter(output_gate: &Arc<OutputGate>, alternate_screen: bool) -> Result<Self> {
+        output_gate.pause();
+        // Built before entering, so a failure below still restores everything
+        let guard = Self {
+            output_gate: output_gate.clone(),
+            alternate_screen,
+        };
+        if alternate_screen {
+            execute!(std::io::stdout(), EnterAlternateScreen)?;
+        }
+        Ok(guard)
+    }
+}
+
+impl Drop for OverlayGuard {
+    fn drop(&mut self) {
+        if self.alternate_screen {
+            execute!(std::io::stdout(), LeaveAlternateScreen).ok();
+        }
+        self.output_gate.resume(&mut std::io::stdout());
+    }
+}
+
+/// Run an overlay in a ratatui terminal drawing to `viewport`
+/// An inline region (no alternate screen) is erased afterwards so the shell can redraw its prompt
+fn run_in_viewport<T>(
+    viewport: Viewport,
+    run: impl FnOnce(&mut Terminal<CrosstermBackend<Stdout>>) -> Result<T>,
+) -> Result<T> {
+    let inline = matches!(viewport, Viewport::Inline(_));
+    let backend = CrosstermBackend::new(std::io::stdout());
+    let mut terminal = Terminal::with_options(backend, TerminalOptions { viewport })?;
+    if !inline {
+        terminal.clear()?;
+    }
+
+    let result = run(&mut terminal);
+
+    if inline {
+        terminal.clear().ok();
+    }
 
     result
 }
 
-/// Convert crossterm KeyEvent to bytes to send to PTY
-fn key_event_to_bytes(key_event: event::KeyEvent) -> Vec<u8> {
-    match key_event.code {
-        KeyCode::Char(c) => {
-            if key_event.modifiers.contains(KeyModifiers::CONTROL) {
-                // Handle Ctrl+ combinations
-                match c {
-                    'a'..='z' => vec![c as u8 - b'a' + 1],
-                    '@' => vec![0],
-                    '[' => vec![27],
-                    '\\' => vec![28],
-                    ']' => vec![29],
-                    '^' => vec![30],
-                    '_' => vec![31],
-                    _ => c.to_string().into_bytes(),
-                }
-            } else {
-                c.to_string().into_bytes()
-            }
+/// Ask the agent to finish the typed command line and let the user confirm the suggestion
+/// Returns the completion to write to the shell, None when there's nothing to insert
+fn complete_typed_line(
+    output_gate: &Arc<OutputGate>,
+    overlays: &Overlays,
+    typed_line: &LineTracker,
+) -> Result<Option<Completion>> {
+    let context = match overlays.capture.lock() {
+        Ok(capture) => {
+            let commands = capture.get_commands();
+            let recent = &commands[commands.len().saturating_sub(complete::COMPLETION_CONTEXT_COMMANDS)..];
+            recent.iter().map(|cmd| format!("$ {}", cmd.command)).collect()
         }
-        KeyCode::Enter => vec![b'\r'],
-        KeyCode::Backspace => vec![127],
-        KeyCode::Tab => vec![b'\t'],
-        KeyCode::Esc => vec![27],
-        KeyCode::Up => vec![27, 91, 65],
-        KeyCode::Down => vec![27, 91, 66],
-        KeyCode::Right => vec![27, 91, 67],
-        KeyCode::Left => vec![27, 91, 68],
-        KeyCode::Home => vec![27, 91, 72],
-        KeyCode::End => vec![27, 91, 70],
-        KeyCode::PageUp => vec![27, 91, 53, 126],
-        KeyCode::PageDown => vec![27, 91, 54, 126],
-        KeyCode::Delete => vec![27, 91, 51, 126],
-        KeyCode::Insert => vec![27, 91, 50, 126],
-        KeyCode::F(n) => match n {
-            1 => vec![27, 79, 80],
-            2 => vec![27, 79, 81],
-            3 => vec![27, 79, 82],
-            4 => vec![27, 79, 83],
-            5..=12 => vec![27, 91, b'0' + (n - 5) as u8, 126],
-            _ => vec![],
-        },
-        _ => vec![],
+        Err(_) => Vec::new(),
+    };
+    let Some(line) = typed_line.current().filter(|line| !line.trim().is_empty()) else {
+        info!("Completion skipped: typed line unknown or empty");
+        return Ok(None);
+    };
+    let transport = match overlays.chat_state.lock() {
+        Ok(state) => state.transport(),
+        Err(_) => return Ok(None),
+    };
+
+    let prompt = complete::completion_prompt(line, &std::env::current_dir().unwrap_or_default());
+    let (reply_tx, reply_rx) = mpsc::channel();
+    let span = Span::current();
+    thread::spawn(move || {
+        let _entered = span.enter();
+        let reply = transport.send(prompt, context).map(|response| response.message);
+        reply_tx.send(reply).ok();
+    });
+
+    let _session = OverlayGuard::enter(output_gate, overlays.screen != ChatScreen::Inline)?;
+    run_in_viewport(overlay_viewport(overlays.screen), |terminal| {
+        complete::confirm_completion(terminal, line, reply_rx, &overlays.theme)
+    })
+}
+
+/// Show the dangerous paste confirmation with shell output held
+fn confirm_dangerous_paste(
+    output_gate: &Arc<OutputGate>,
+    overlays: &Overlays,
+    text: &str,
+    matches: &[&str],
+) -> Result<bool> {
+    let _session = OverlayGuard::enter(output_gate, overlays.screen != ChatScreen::Inline)?;
+    run_in_viewport(overlay_viewport(overlays.screen), |terminal| {
+        paste_guard::confirm_paste(terminal, text, matches)
+    })
+}
+
+/// Detect the shell toggling bracketed paste in an output chunk (last toggle wins)
+fn bracketed_paste_mode(data: &[u8]) -> Option<bool> {
+    let find_last = |needle: &[u8]| data.windows(needle.len()).rposition(|w| w == needle);
+    match (find_last(b"\x1b[?2004h"), find_last(b"\x1b[?2004l")) {
+        (Some(on), Some(off)) => Some(on > off),
+        (Some(_), None) => Some(true),
+        (None, Some(_)) => Some(false),
+        (None, None) => None,
     }
 }
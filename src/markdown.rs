@@ -0,0 +1,139 @@
+/// A fenced code block (``` or ~~~) in a message
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeBlock {
+    pub lang: Option<String>,
+    pub code: String, // Raw text between the fences, without them
+    pub fence_line: usize, // Line of the opening fence in the message
+}
+
+/// Fenced code blocks of a markdown text, in order
+/// A block left open (reply still streaming) runs to the end of the text
+pub fn code_blocks(text: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut open: Option<(&str, CodeBlock)> = None;
+
+    for (i, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        match open.take() {
+            Some((fence, block)) if trimmed.trim_end() == fence => blocks.push(block),
+            Some((fence, mut block)) => {
+                block.code.push_str(line);
+                block.code.push('\n');
+                open = Some((fence, block));
+            }
+            None => {
+                let Some(fence) = ["```", "~~~"].into_iter().find(|fence| trimmed.starts_with(fence)) else {
+                    continue;
+                };
+                let lang = trimmed[fence.len()..].split_whitespace().next().map(str::to_string);
+                open = Some((fence, CodeBlock { lang, code: String::new(), fence_line: i }));
+            }
+        }
+    }
+    blocks.extend(open.map(|(_, block)| block));
+    blocks
+}
+
+/// What a line of a markdown text is, for styling
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind<'a> {
+    Text,
+
+    /// Opening or closing fence of a code block
+    Fence,
+
+    /// Inside a code block, with the block's language
+    Code(Option<&'a str>),
+}
+
+/// Kind of each line of a markdown text, with the same fence rules as `code_blocks`
+/// A line's kind only depends on the lines before it: an unclosed fence makes everything after
+/// it code until it's closed
+pub fn line_kinds(text: &str) -> Vec<LineKind<'_>> {
+    let mut kinds = Vec::new();
+    let mut open: Option<(&str, Option<&str>)> = None;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        let kind = match open {
+            Some((fence, _)) if trimmed.trim_end() == fence => {
+                open = None;
+                LineKind::Fence
+            }
+            Some((_, lang)) => LineKind::Code(lang),
+            None => match ["```", "~~~"].into_iter().find(|fence| trimmed.starts_with(fence)) {
+                Some(fence) => {
+                    open = Some((fence, trimmed[fence.len()..].split_whitespace().next()));
+                    LineKind::Fence
+                }
+                None => LineKind::Text,
+            },
+        };
+        kinds.push(kind);
+    }
+    kinds
+}
+
+/// Badge shown next to the nth block (1-based): ①..⑳, then [21]...
+pub fn block_badge(n: usize) -> String {
+    match n {
+        1..=20 => char::from_u32(0x2460 + n as u32 - 1).map(String::from).unwrap_or_default(),
+        _ => format!("[{}]", n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_blocks_keep_raw_text_and_position() {
+        let text = "Installez-le:\n```bash\nsudo apt install nmap\n```\nPuis:\n~~~\nnmap -sV host\n  # indenté\n~~~\n";
+        let blocks = code_blocks(text);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].lang.as_deref(), Some("bash"));
+        assert_eq!(blocks[0].code, "sudo apt install nmap\n");
+        assert_eq!(blocks[0].fence_line, 1);
+        assert_eq!(blocks[1].lang, None);
+        assert_eq!(blocks[1].code, "nmap -sV host\n  # indenté\n");
+        assert_eq!(blocks[1].fence_line, 5);
+    }
+
+    #[test]
+    fn test_unclosed_block_runs_to_the_end() {
+        let blocks = code_blocks("```sh\nls -la\n");
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].code, "ls -la\n");
+    }
+
+    #[test]
+    fn test_line_kinds_are_stable_while_streaming() {
+        let full = "Voici:\n```diff\n-a\n+b\n```\nFin";
+        let kinds = line_kinds(full);
+        assert_eq!(
+            kinds,
+            [
+                LineKind::Text,
+                LineKind::Fence,
+                LineKind::Code(Some("diff")),
+                LineKind::Code(Some("diff")),
+                LineKind::Fence,
+                LineKind::Text
+            ]
+        );
+
+        // Every prefix of the stream styles its complete lines the way the full text does
+        for end in (0..=full.len()).filter(|&end| full.is_char_boundary(end)) {
+            let partial = &full[..end];
+            let complete_lines = partial.matches('\n').count();
+            assert_eq!(line_kinds(partial)[..complete_lines], kinds[..complete_lines], "{:?}", partial);
+        }
+    }
+
+    #[test]
+    fn test_block_badges() {
+        assert_eq!(block_badge(1), "①");
+        assert_eq!(block_badge(3), "③");
+        assert_eq!(block_badge(21), "[21]");
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::grpc_client::chat::ChatResponse;
+use crate::transport::ChatTransport;
+
+/// Agents the mock answers as, in turn
+const MOCK_AGENTS: &[&str] = &["general", "toolsmith", "researcher", "scribe"];
+
+/// Delay before answering, so the loading spinner can be seen
+const MOCK_DELAY: Duration = Duration::from_millis(400);
+
+/// Canned responder used with PETONCLE_MOCK=1, to work on the TUI without the Python service
+#[derive(Default)]
+pub struct MockTransport {
+    turn: AtomicUsize, // Messages answered so far, picks the agent and reply kind
+}
+
+impl MockTransport {
+    fn reply(turn: usize, message: &str, context: &[String]) -> String {
+        match turn % 3 {
+            0 => format!("Vous avez dit: {}", message),
+            1 => "## Exemple de réponse\n\n\
+                  Voici une liste:\n\
+                  - un élément **important**\n\
+                  - un élément avec du `code`\n\n\
+                  ```bash\nls -la | grep petoncle\n```"
+                .to_string(),
+            _ => format!(
+                "Contexte reçu: {} élément(s), {} octets au total",
+                context.len(),
+                context.iter().map(String::len).sum::<usize>()
+            ),
+        }
+    }
+}
+
+impl ChatTransport for MockTransport {
+    fn send(&self, message: String, context: Vec<String>) -> Result<ChatResponse> {
+        let turn = self.turn.fetch_add(1, Ordering::Relaxed);
+        thread::sleep(MOCK_DELAY);
+        Ok(ChatResponse {
+            message: Self::reply(turn, &message, &context),
+            agent: MOCK_AGENTS[turn % MOCK_AGENTS.len()].to_string(),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_cycles_agents_and_replies() {
+        assert_eq!(MockTransport::reply(0, "bonjour", &[]), "Vous avez dit: bonjour");
+        assert!(MockTransport::reply(1, "x", &[]).contains("```bash"));
+        assert_eq!(
+            MockTransport::reply(2, "x", &["abc".to_string()]),
+            "Contexte reçu: 1 élément(s), 3 octets au total"
+        );
+        assert_eq!(MOCK_AGENTS[5 % MOCK_AGENTS.len()], "toolsmith");
+    }
+
+    #[test]
+    fn test_mock_turns_advance_per_message() {
+        let mock = MockTransport::default();
+        assert_eq!(mock.send("a".to_string(), vec![]).unwrap().agent, "general");
+        // Nothing to report: the mock answers at once
+        let mut progress = Vec::new();
+        let second = mock
+            .send_reporting("b".to_string(), vec![], &mut |event| progress.push(event))
+            .unwrap();
+        assert_eq!(second.agent, "toolsmith");
+        assert!(progress.is_empty());
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::Alignment,
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Wrap},
+    Terminal,
+};
+
+use crate::config::PasteGuardConfig;
+use crate::keys;
+
+/// Scans pasted text for dangerous commands before it reaches the shell
+pub struct PasteGuard {
+    enabled: bool,
+    patterns: Vec<String>,
+}
+
+impl PasteGuard {
+    pub fn new(config: &PasteGuardConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            patterns: config.patterns.clone(),
+        }
+    }
+
+    /// Dangerous patterns found in the pasted text (always empty when disabled)
+    pub fn scan(&self, text: &str) -> Vec<&str> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        self.patterns
+            .iter()
+            .filter(|pattern| !pattern.is_empty() && text.contains(pattern.as_str()))
+            .map(|pattern| pattern.as_str())
+            .collect()
+    }
+}
+
+/// Bracketed paste markers, around the pasted text the shell must not run as typed
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+
+/// Encode pasted text for the PTY, wrapped in bracketed paste markers when the shell asked for them
+/// Markers inside the text are removed: an end marker would let the rest run as if typed
+pub fn paste_to_bytes(text: &str, bracketed: bool) -> Vec<u8> {
+    // Terminals send pasted newlines as carriage returns
+    let mut text = text.replace("\r\n", "\r").replace('\n', "\r");
+    if !bracketed {
+        return text.into_bytes();
+    }
+    // Removing a marker can join the pieces of another one around it
+    while text.contains(PASTE_START) || text.contains(PASTE_END) {
+        text = text.replace(PASTE_START, "").replace(PASTE_END, "");
+    }
+    format!("{}{}{}", PASTE_START, text, PASTE_END).into_bytes()
+}
+
+/// Show a confirmation overlay for a dangerous paste
+/// Returns true if the user accepts forwarding it to the shell
+pub fn confirm_paste(
+    terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>,
+    text: &str,
+    matches: &[&str],
+) -> Result<bool> {
+    loop {
+        terminal.draw(|frame| {
+            let mut lines = vec![
+                Line::from(Span::styled(
+                    format!("Motifs détectés: {}", matches.join(", ")),
+                    Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                )),
+                Line::from(""),
+            ];
+            for line in text.lines() {
+                lines.push(Line::from(line.to_string()));
+            }
+            lines.push(Line::from(""));
+            lines.push(Line::from(Span::styled(
+                "[o/y] Coller quand même   [n/Esc] Annuler",
+                Style::default().fg(Color::Yellow),
+            )));
+
+            let paragraph = Paragraph::new(lines)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(Color::Red))
+                        .title("⚠️ Collage potentiellement dangereux")
+                        .title_alignment(Alignment::Center),
+                )
+                .wrap(Wrap { trim: false });
+            frame.render_widget(paragraph, frame.area());
+        })?;
+
+        if let Event::Key(key_event) = event::read()? {
+            if !keys::is_key_input(&key_event) {
+                continue;
+            }
+            match key_event.code {
+                KeyCode::Char('o') | KeyCode::Char('y') => return Ok(true),
+                KeyCode::Char('n') | KeyCode::Esc => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard(enabled: bool) -> PasteGuard {
+        PasteGuard::new(&PasteGuardConfig {
+            enabled,
+            ..PasteGuardConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_scan_detects_dangerous_paste() {
+        assert_eq!(guard(true).scan("cd /tmp && rm -rf / --no-preserve-root"), vec!["rm -rf /"]);
+        assert!(guard(true).scan("ls -la").is_empty());
+    }
+
+    #[test]
+    fn test_scan_disabled() {
+        assert!(guard(false).scan("rm -rf /").is_empty());
+    }
+
+    #[test]
+    fn test_paste_cannot_end_bracketed_paste_early() {
+        let bytes = String::from_utf8(paste_to_bytes("ls\x1b[201~rm -rf ~\r", true)).unwrap();
+        assert_eq!(bytes, "\x1b[200~lsrm -rf ~\r\x1b[201~");
+        assert_eq!(bytes.matches(PASTE_END).count(), 1);
+
+        // Nor once the markers around it are removed
+        let bytes = String::from_utf8(paste_to_bytes("a\x1b[20\x1b[200~1~b", true)).unwrap();
+        assert_eq!(bytes.matches(PASTE_END).count(), 1);
+        assert!(bytes.ends_with(PASTE_END));
+
+        assert_eq!(paste_to_bytes("a\nb", false), b"a\rb");
+    }
+}
@@ -0,0 +1,198 @@
+use serde::Deserialize;
+use std::io::Write;
+use std::sync::Mutex;
+
+/// Most shell output replayed when an overlay closes, past it the oldest lines are left to the viewer
+/// Up to twice as much is buffered meanwhile: the held output is trimmed back to it once it doubles
+pub const MAX_HELD_BYTES: usize = 256 * 1024;
+
+/// What happens to shell output arriving while the chat (or another overlay) covers the shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PendingOutputMode {
+    /// Badge in the chat, the output is written to the terminal when the overlay closes
+    #[default]
+    Replay,
+
+    /// Badge in the chat and a one-line note on exit, the output stays in the viewer
+    Notify,
+
+    /// Nothing: the output only reaches the viewer and the capture
+    Off,
+}
+
+/// Shell output while an overlay covers the shell
+#[derive(Debug, Default)]
+struct GateState {
+    paused: bool,
+    held: Vec<u8>,
+    received: usize,
+}
+
+/// Between the PTY output thread and the terminal: output is written right away normally, held
+/// while an overlay is open and dealt with (per `PendingOutputMode`) when it closes
+/// One lock around the check and the write, so nothing slips through between pause and resume
+#[derive(Debug)]
+pub struct OutputGate {
+    mode: PendingOutputMode,
+    state: Mutex<GateState>,
+}
+
+impl OutputGate {
+    pub fn new(mode: PendingOutputMode) -> Self {
+        Self {
+            mode,
+            state: Mutex::new(GateState::default()),
+        }
+    }
+
+    /// Output read from the shell: written to `out`, or held while paused
+    pub fn write(&self, data: &[u8], out: &mut impl Write) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if !state.paused {
+            out.write_all(data).ok();
+            out.flush().ok();
+            return;
+        }
+        state.received += data.len();
+        if self.mode == PendingOutputMode::Replay {
+            state.held.extend_from_slice(data);
+            // Trimmed once it doubles rather than on every chunk, so the drain cost is amortized
+            if state.held.len() > 2 * MAX_HELD_BYTES {
+                keep_last_lines(&mut state.held);
+            }
+        }
+    }
+
+    /// Stop writing to the terminal, an overlay is about to draw
+    pub fn pause(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.paused = true;
+            state.held.clear();
+            state.received = 0;
+        }
+    }
+
+    /// Write again, after replaying (or noting) what arrived in the meantime
+    pub fn resume(&self, out: &mut impl Write) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let received = std::mem::take(&mut state.received);
+        let mut held = std::mem::take(&mut state.held);
+        state.paused = false;
+        if received == 0 || self.mode == PendingOutputMode::Off {
+            return;
+        }
+
+        if held.len() == received && received <= MAX_HELD_BYTES {
+            out.write_all(&held).ok();
+        } else {
+            keep_last_lines(&mut held);
+            let shown = if held.is_empty() {
+                " (visualiseur pour les voir)"
+            } else {
+                ", la fin ci-dessous (visualiseur pour le début)"
+            };
+            let note = format!(
+                "\r\n📥 {} de sortie du shell pendant le chat{}\r\n",
+                format_bytes(received),
+                shown
+            );
+            out.write_all(note.as_bytes()).ok();
+            out.write_all(&held).ok();
+        }
+        out.flush().ok();
+    }
+
+    /// Bytes the shell printed since the overlay opened, for the chat badge (0 when the badge is off)
+    pub fn pending_bytes(&self) -> usize {
+        match self.state.lock() {
+            Ok(state) if state.paused && self.mode != PendingOutputMode::Off => state.received,
+            _ => 0,
+        }
+    }
+}
+
+/// Badge shown in the chat title while shell output is pending
+pub fn pending_badge(bytes: usize) -> String {
+    if bytes == 0 {
+        String::new()
+    } else {
+        format!("📥 sortie du shell en attente ({})", format_bytes(bytes))
+    }
+}
+
+/// Cut `held` down to its last `MAX_HELD_BYTES`, starting on a line so the replay doesn't begin
+/// in the middle of an escape sequence or a character
+fn keep_last_lines(held: &mut Vec<u8>) {
+    if held.len() <= MAX_HELD_BYTES {
+        return;
+    }
+    let cut = held.len() - MAX_HELD_BYTES;
+    let start = held[cut..].iter().position(|&b| b == b'\n').map_or(held.len(), |pos| cut + pos + 1);
+    held.drain(..start);
+}
+
+fn format_bytes(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} o", bytes)
+    } else {
+        format!("{} Ko", bytes.div_ceil(1024))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_held_while_paused() {
+        let gate = OutputGate::new(PendingOutputMode::Replay);
+        let mut out = Vec::new();
+        gate.write(b"$ make\r\n", &mut out);
+        assert_eq!(gate.pending_bytes(), 0);
+
+        gate.pause();
+        gate.write(b"done\r\n", &mut out);
+        assert_eq!(out, b"$ make\r\n");
+        assert_eq!(gate.pending_bytes(), 6);
+        assert_eq!(pending_badge(gate.pending_bytes()), "📥 sortie du shell en attente (6 o)");
+
+        gate.resume(&mut out);
+        assert_eq!(out, b"$ make\r\ndone\r\n");
+        assert_eq!(gate.pending_bytes(), 0);
+
+        // Too much to replay: a note and the last lines, in order
+        gate.pause();
+        gate.write(&vec![b'x'; MAX_HELD_BYTES + 1], &mut out);
+        let mut out = Vec::new();
+        gate.resume(&mut out);
+        assert!(String::from_utf8_lossy(&out).contains("257 Ko de sortie du shell pendant le chat (vis"));
+
+        let lines = 3 * MAX_HELD_BYTES / 9;
+        gate.pause();
+        for i in 0..lines {
+            gate.write(format!("{:08}\n", i).as_bytes(), &mut out);
+        }
+        let mut out = Vec::new();
+        gate.resume(&mut out);
+        let out = String::from_utf8(out).unwrap();
+        let (note, tail) = out.split_at(out.find("\r\n0").unwrap() + 2);
+        assert!(note.contains("768 Ko de sortie du shell pendant le chat, la fin ci-dessous"));
+        assert!(tail.len() <= MAX_HELD_BYTES);
+        assert!(tail.ends_with(&format!("{:08}\n", lines - 1)));
+        let numbers: Vec<usize> = tail.lines().map(|line| line.parse().unwrap()).collect();
+        assert!(numbers.windows(2).all(|pair| pair[1] == pair[0] + 1));
+
+        let gate = OutputGate::new(PendingOutputMode::Off);
+        let mut out = Vec::new();
+        gate.pause();
+        gate.write(b"done\r\n", &mut out);
+        assert_eq!(gate.pending_bytes(), 0);
+        gate.resume(&mut out);
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::{Duration, Instant};
+
+/// Quiet time after the last resize event before the shells get the new size
+pub const RESIZE_SETTLE: Duration = Duration::from_millis(50);
+
+/// Terminal size changes waiting to reach the PTYs
+/// Dragging a window corner fires dozens of resize events per second: applying each one means
+/// a SIGWINCH and a full redraw in full-screen programs. Only the latest size is kept, and it's
+/// applied once no new event came for `settle`.
+#[derive(Debug)]
+pub struct ResizeDebounce {
+    settle: Duration,
+    pending: Option<((u16, u16), Instant)>,
+    applied: (u16, u16),
+}
+
+impl ResizeDebounce {
+    /// `applied` is the size the PTYs were created with (columns, rows)
+    pub fn new(settle: Duration, applied: (u16, u16)) -> Self {
+        Self {
+            settle,
+            pending: None,
+            applied,
+        }
+    }
+
+    /// Record a new terminal size (columns, rows), restarting the quiet period
+    pub fn push(&mut self, size: (u16, u16), now: Instant) {
+        self.pending = Some((size, now));
+    }
+
+    /// The size to apply now that the burst has settled, None while waiting or when the size
+    /// ends up unchanged (e.g. a drag back to where it started)
+    pub fn take_settled(&mut self, now: Instant) -> Option<(u16, u16)> {
+        let (size, last_event) = self.pending?;
+        if now.duration_since(last_event) < self.settle {
+            return None;
+        }
+        self.pending = None;
+        if size == self.applied {
+            return None;
+        }
+        self.applied = size;
+        Some(size)
+    }
+
+    /// How long to wait for input: up to `idle`, less when a pending size is about to settle
+    pub fn poll_timeout(&self, now: Instant, idle: Duration) -> Duration {
+        match self.pending {
+            Some((_, last_event)) => (last_event + self.settle).saturating_duration_since(now).min(idle),
+            None => idle,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_applies_latest_size_once() {
+        let start = Instant::now();
+        let mut debounce = ResizeDebounce::new(RESIZE_SETTLE, (80, 24));
+        for (i, cols) in (81..=120).enumerate() {
+            let now = start + Duration::from_millis(10 * i as u64);
+            debounce.push((cols, 30), now);
+            assert_eq!(debounce.take_settled(now), None);
+        }
+
+        let last = start + Duration::from_millis(390);
+        let idle = Duration::from_millis(100);
+        assert_eq!(debounce.poll_timeout(last + Duration::from_millis(20), idle), Duration::from_millis(30));
+        assert_eq!(debounce.take_settled(last + Duration::from_millis(49)), None);
+        assert_eq!(debounce.take_settled(last + RESIZE_SETTLE), Some((120, 30)));
+        assert_eq!(debounce.take_settled(last + Duration::from_secs(1)), None);
+
+        // Back to the applied size: nothing to do
+        debounce.push((120, 30), last + Duration::from_secs(2));
+        assert_eq!(debounce.take_settled(last + Duration::from_secs(3)), None);
+    }
+}
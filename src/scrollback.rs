@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+
+/// Most recent shell output, bounded to a fixed number of bytes
+///
+/// After any sequence of writes the buffer holds exactly the last
+/// `min(total bytes written, capacity)` bytes, in order.
+///
+/// A ring: once full, each new byte evicts one old byte in place, so a push costs O(chunk)
+/// whatever the capacity and the PTY reader holds the lock only that long.
+pub struct Scrollback {
+    data: VecDeque<u8>,
+    capacity: usize,
+}
+
+impl Scrollback {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            data: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Append output, evicting the oldest bytes beyond capacity
+    pub fn push(&mut self, bytes: &[u8]) {
+        // Only the tail of an oversized chunk can survive
+        let bytes = &bytes[bytes.len().saturating_sub(self.capacity)..];
+        let excess = (self.data.len() + bytes.len()).saturating_sub(self.capacity);
+        self.data.drain(..excess);
+        self.data.extend(bytes);
+    }
+
+    /// Copy of the last `n` bytes of output (fewer if less was retained)
+    pub fn snapshot_recent(&self, n: usize) -> Vec<u8> {
+        let start = self.data.len().saturating_sub(n);
+        self.data.range(start..).copied().collect()
+    }
+
+    /// Number of bytes currently retained
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    #[cfg(test)]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retains_most_recent_window() {
+        let mut scrollback = Scrollback::new(10);
+        scrollback.push(b"hello ");
+        scrollback.push(b"world!");
+
+        assert_eq!(scrollback.len(), 10);
+        assert_eq!(scrollback.snapshot_recent(100), b"llo world!");
+        assert_eq!(scrollback.snapshot_recent(6), b"world!");
+    }
+
+    #[test]
+    fn test_oversized_chunk_keeps_tail() {
+        let mut scrollback = Scrollback::new(4);
+        scrollback.push(b"0123456789");
+        assert_eq!(scrollback.snapshot_recent(4), b"6789");
+        assert_eq!(scrollback.capacity(), 4);
+    }
+
+    #[test]
+    fn test_exact_retention_across_many_writes() {
+        let mut scrollback = Scrollback::new(1000);
+        let mut written = Vec::new();
+        for i in 0..500u32 {
+            let chunk = format!("line {}\n", i);
+            scrollback.push(chunk.as_bytes());
+            written.extend_from_slice(chunk.as_bytes());
+        }
+        assert_eq!(scrollback.snapshot_recent(usize::MAX), &written[written.len() - 1000..]);
+    }
+
+    #[test]
+    fn test_eviction_wraps_around_the_ring() {
+        let mut scrollback = Scrollback::new(8);
+        scrollback.push(b"abcdef");
+        // Evicts "a" then "bc": the retained bytes now wrap past the end of the storage
+        scrollback.push(b"ghi");
+        scrollback.push(b"jk");
+        assert_eq!(scrollback.len(), 8);
+        assert_eq!(scrollback.snapshot_recent(8), b"defghijk");
+        assert_eq!(scrollback.snapshot_recent(3), b"ijk");
+        assert_eq!(Scrollback::new(8).snapshot_recent(3), b"");
+    }
+}
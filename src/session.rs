@@ -0,0 +1,28 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Environment variable giving the session ID to the wrapped shell (and `petoncle doctor` run in it)
+pub const SESSION_ID_VAR: &str = "PETONCLE_SESSION_ID";
+
+/// Short random ID of a session, in the log file name, on every log line and in exports,
+/// so a bug report ("session 3f9a0c21") can be matched with its log
+pub fn new_session_id() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+    // RandomState is seeded from the OS, enough to tell sessions apart without a uuid crate
+    let hash = RandomState::new().hash_one((std::process::id(), nanos));
+    format!("{:08x}", hash as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_ids() {
+        let id = new_session_id();
+        assert_eq!(id.len(), 8);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(id, new_session_id());
+    }
+}
@@ -0,0 +1,197 @@
+use ratatui::style::Color;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Built-in color sets, the starting point of the `[theme]` config section
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemePreset {
+    #[default]
+    Dark,
+    Light,
+    HighContrast,
+}
+
+/// `[theme]` config section: a preset, with individual colors overridden in `[theme.colors]`
+/// e.g. `user = "blue"` or `background = "#1e1e2e"`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    pub preset: ThemePreset,
+    pub colors: HashMap<String, String>,
+}
+
+/// Colors of the agent badges shown in message headers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BadgeColors {
+    pub toolsmith: Color,
+    pub researcher: Color,
+    pub scribe: Color,
+    pub general: Color,
+    pub error: Color,
+    pub system: Color,
+    pub unknown: Color,
+}
+
+/// Colors used by the chat UI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub background: Color,
+    pub text: Color,
+    pub user: Color,
+    pub assistant: Color,
+    pub chat_border: Color,
+    pub input_border: Color,
+    pub logs_border: Color,
+    pub logs_text: Color,
+    pub confirm_border: Color,
+    pub highlight: Color,
+    pub code: Color,
+    pub badges: BadgeColors,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::preset(ThemePreset::Dark)
+    }
+}
+
+impl Theme {
+    pub fn preset(preset: ThemePreset) -> Self {
+        match preset {
+            ThemePreset::Dark => Self {
+                background: Color::Black,
+                text: Color::White,
+                user: Color::Cyan,
+                assistant: Color::Green,
+                chat_border: Color::Cyan,
+                input_border: Color::Magenta,
+                logs_border: Color::Yellow,
+                logs_text: Color::Gray,
+                confirm_border: Color::Red,
+                highlight: Color::Yellow,
+                code: Color::LightBlue,
+                badges: BadgeColors {
+                    toolsmith: Color::Yellow,
+                    researcher: Color::Blue,
+                    scribe: Color::Magenta,
+                    general: Color::Cyan,
+                    error: Color::Red,
+                    system: Color::Gray,
+                    unknown: Color::White,
+                },
+            },
+            ThemePreset::Light => Self {
+                background: Color::White,
+                text: Color::Black,
+                user: Color::Blue,
+                assistant: Color::Green,
+                chat_border: Color::Blue,
+                input_border: Color::Magenta,
+                logs_border: Color::Blue,
+                logs_text: Color::DarkGray,
+                confirm_border: Color::Red,
+                highlight: Color::Magenta,
+                code: Color::DarkGray,
+                badges: BadgeColors {
+                    toolsmith: Color::Magenta,
+                    researcher: Color::Blue,
+                    scribe: Color::Magenta,
+                    general: Color::Blue,
+                    error: Color::Red,
+                    system: Color::DarkGray,
+                    unknown: Color::Black,
+                },
+            },
+            ThemePreset::HighContrast => Self {
+                background: Color::Black,
+                text: Color::White,
+                user: Color::LightCyan,
+                assistant: Color::LightGreen,
+                chat_border: Color::White,
+                input_border: Color::White,
+                logs_border: Color::White,
+                logs_text: Color::White,
+                confirm_border: Color::LightRed,
+                highlight: Color::LightYellow,
+                code: Color::LightBlue,
+                badges: BadgeColors {
+                    toolsmith: Color::LightYellow,
+                    researcher: Color::LightBlue,
+                    scribe: Color::LightMagenta,
+                    general: Color::LightCyan,
+                    error: Color::LightRed,
+                    system: Color::White,
+                    unknown: Color::White,
+                },
+            },
+        }
+    }
+
+    /// Preset with the configured overrides applied
+    /// Errors list the overrides that were ignored (unknown name or invalid color)
+    pub fn from_config(config: &ThemeConfig) -> (Self, Vec<String>) {
+        let mut theme = Self::preset(config.preset);
+        let mut errors = Vec::new();
+        for (name, value) in &config.colors {
+            if let Err(e) = theme.set(name, value) {
+                errors.push(e);
+            }
+        }
+        (theme, errors)
+    }
+
+    /// Override one color by name, the value being a color name, "#rrggbb" or a 0-255 index
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let color = Color::from_str(value).map_err(|_| format!("Couleur invalide pour {}: {}", name, value))?;
+        let slot = match name {
+            "background" => &mut self.background,
+            "text" => &mut self.text,
+            "user" => &mut self.user,
+            "assistant" => &mut self.assistant,
+            "chat_border" => &mut self.chat_border,
+            "input_border" => &mut self.input_border,
+            "logs_border" => &mut self.logs_border,
+            "logs_text" => &mut self.logs_text,
+            "confirm_border" => &mut self.confirm_border,
+            "highlight" => &mut self.highlight,
+            "code" => &mut self.code,
+            "toolsmith" => &mut self.badges.toolsmith,
+            "researcher" => &mut self.badges.researcher,
+            "scribe" => &mut self.badges.scribe,
+            "general" => &mut self.badges.general,
+            "error" => &mut self.badges.error,
+            "system" => &mut self.badges.system,
+            _ => return Err(format!("Couleur de thème inconnue: {}", name)),
+        };
+        *slot = color;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preset_from_toml() {
+        let config: ThemeConfig = toml::from_str("preset = \"high-contrast\"").unwrap();
+        assert_eq!(config.preset, ThemePreset::HighContrast);
+        assert_eq!(Theme::from_config(&config).0, Theme::preset(ThemePreset::HighContrast));
+    }
+
+    #[test]
+    fn test_color_overrides() {
+        let config: ThemeConfig =
+            toml::from_str("preset = \"light\"\n[colors]\nuser = \"red\"\nbackground = \"#102030\"\nfoo = \"blue\"\nscribe = \"nope\"\n")
+                .unwrap();
+        let (theme, errors) = Theme::from_config(&config);
+
+        assert_eq!(theme.user, Color::Red);
+        assert_eq!(theme.background, Color::Rgb(0x10, 0x20, 0x30));
+        assert_eq!(theme.text, Color::Black);
+        assert_eq!(theme.badges.scribe, Theme::preset(ThemePreset::Light).badges.scribe);
+        assert_eq!(errors.len(), 2);
+    }
+}
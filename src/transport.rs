@@ -0,0 +1,76 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+use crate::config::BackendConfig;
+use crate::grpc_client::chat::ChatResponse;
+use crate::grpc_client::AgentClient;
+use crate::mock::MockTransport;
+
+/// What a transport reports while a request is in flight
+/// The answer itself always arrives whole: the agent service has no streaming method
+#[derive(Debug, Clone, PartialEq)]
+pub enum Progress {
+    /// The service couldn't be reached, `attempt` of `attempts` starts after `delay`
+    Retrying { attempt: u32, attempts: u32, delay: Duration },
+}
+
+/// Something chat messages can be sent to (the gRPC agent service, the mock, later HTTP/SSE...)
+/// Called from the chat's worker threads, so implementations may block and must be shareable
+pub trait ChatTransport: Send + Sync {
+    /// Send a message with its context and wait for the answer
+    fn send(&self, message: String, context: Vec<String>) -> Result<ChatResponse>;
+
+    /// Same as `send`, calling `on_progress` with the retries along the way
+    /// Transports that never retry report nothing
+    fn send_reporting(
+        &self,
+        message: String,
+        context: Vec<String>,
+        _on_progress: &mut dyn FnMut(Progress),
+    ) -> Result<ChatResponse> {
+        self.send(message, context)
+    }
+
+    /// Whether the service is up and accepting requests, without sending anything
+    fn check_ready(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ChatTransport for AgentClient {
+    fn send(&self, message: String, context: Vec<String>) -> Result<ChatResponse> {
+        // Each request works on its own copy, the channel (if connected) is shared by the clone
+        let mut client = self.clone();
+        let runtime = Runtime::new()?;
+        runtime.block_on(client.send_message(message, context))
+    }
+
+    fn send_reporting(
+        &self,
+        message: String,
+        context: Vec<String>,
+        on_progress: &mut dyn FnMut(Progress),
+    ) -> Result<ChatResponse> {
+        let mut client = self.clone();
+        let runtime = Runtime::new()?;
+        let mut on_retry = |attempt, attempts, delay| on_progress(Progress::Retrying { attempt, attempts, delay });
+        runtime.block_on(client.send_message_reporting(message, context, &mut on_retry))
+    }
+
+    fn check_ready(&self) -> Result<()> {
+        let mut client = self.clone();
+        let runtime = Runtime::new()?;
+        runtime.block_on(client.connect())
+    }
+}
+
+/// Transport for a backend: the canned responder in mock mode, the gRPC agent service otherwise
+pub fn for_backend(backend: &BackendConfig, mock: bool) -> Arc<dyn ChatTransport> {
+    if mock {
+        Arc::new(MockTransport::default())
+    } else {
+        Arc::new(AgentClient::for_backend(backend))
+    }
+}
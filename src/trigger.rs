@@ -0,0 +1,255 @@
+use crossterm::event::KeyEvent;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+use crate::keys::KeyChord;
+
+/// Longest gap between the two '!' of a double tap
+pub const DOUBLE_TAP_WINDOW: Duration = Duration::from_millis(400);
+
+/// How '!' opens the chat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatTrigger {
+    /// A single '!' opens the chat, "\!" sends a literal '!' to the shell
+    #[default]
+    Single,
+
+    /// Two quick '!' open the chat, a lone '!' reaches the shell (history expansion, `[ ! -f x ]`)
+    Double,
+
+    /// '!' always goes to the shell, the chat can't be opened
+    Off,
+}
+
+/// What to do with a '!' press
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BangAction {
+    /// Open the chat
+    OpenChat,
+
+    /// Send '!' to the shell
+    Literal,
+
+    /// Keep it until the next key or the end of the double tap window
+    Hold,
+}
+
+/// Decides between a '!' opening the chat and a literal one
+/// In double tap mode the first '!' is held back: a second one in time opens the chat, anything
+/// else (another key, a paste, the window running out) releases it to the shell first
+#[derive(Debug)]
+pub struct TriggerState {
+    mode: ChatTrigger,
+    held: Option<Instant>,
+    after_backslash: bool,
+}
+
+impl TriggerState {
+    pub fn new(mode: ChatTrigger) -> Self {
+        Self {
+            mode,
+            held: None,
+            after_backslash: false,
+        }
+    }
+
+    /// A '!' was pressed
+    pub fn bang(&mut self, now: Instant) -> BangAction {
+        let action = match self.mode {
+            ChatTrigger::Off => BangAction::Literal,
+            ChatTrigger::Single if self.after_backslash => BangAction::Literal,
+            ChatTrigger::Single => BangAction::OpenChat,
+            ChatTrigger::Double => match self.held.take() {
+                Some(first) if now.duration_since(first) < DOUBLE_TAP_WINDOW => BangAction::OpenChat,
+                _ => {
+                    self.held = Some(now);
+                    BangAction::Hold
+                }
+            },
+        };
+        self.after_backslash = false;
+        action
+    }
+
+    /// Other input is about to reach the shell: whether a held '!' must be sent before it
+    pub fn release(&mut self) -> bool {
+        self.after_backslash = false;
+        self.held.take().is_some()
+    }
+
+    /// Whether a held '!' ran out of time and must be sent now
+    pub fn release_expired(&mut self, now: Instant) -> bool {
+        match self.held {
+            Some(first) if now.duration_since(first) >= DOUBLE_TAP_WINDOW => {
+                self.held = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Bytes of a key just sent to the shell, a backslash escapes the next '!'
+    pub fn forwarded(&mut self, bytes: &[u8]) {
+        self.after_backslash = bytes == b"\\";
+    }
+
+    /// How long to wait for input: up to `idle`, less while a '!' is held
+    pub fn poll_timeout(&self, now: Instant, idle: Duration) -> Duration {
+        match self.held {
+            Some(first) => (first + DOUBLE_TAP_WINDOW).saturating_duration_since(now).min(idle),
+            None => idle,
+        }
+    }
+}
+
+/// What to do with a key press, as far as the chat key chords go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChordAction {
+    /// A chat key, or the end of a chord: open the chat
+    OpenChat,
+
+    /// First key of a chord: keep it until the next key or the timeout
+    Hold,
+
+    /// Not a chat key: it goes to the shell, after the held first key of a chord that didn't
+    /// complete
+    Forward(Option<KeyEvent>),
+}
+
+/// Opens the chat on configured keys (`chat_keys`), single ones or two-key chords
+/// The first key of a chord is held back: the right second key in time opens the chat, anything
+/// else (another key, a paste, the timeout) releases both to the shell, first key first
+#[derive(Debug)]
+pub struct ChordState {
+    chords: Vec<KeyChord>,
+    timeout: Duration,
+    held: Option<(KeyEvent, Instant)>,
+}
+
+impl ChordState {
+    pub fn new(chords: Vec<KeyChord>, timeout: Duration) -> Self {
+        Self {
+            chords,
+            timeout,
+            held: None,
+        }
+    }
+
+    /// A key was pressed
+    pub fn key(&mut self, key_event: &KeyEvent, now: Instant) -> ChordAction {
+        if let Some((first, at)) = self.held.take() {
+            let completes = now.duration_since(at) < self.timeout
+                && self.chords.iter().any(|chord| {
+                    chord.first.matches(&first) && chord.second.is_some_and(|second| second.matches(key_event))
+                });
+            return if completes { ChordAction::OpenChat } else { ChordAction::Forward(Some(first)) };
+        }
+
+        let mut starts = self.chords.iter().filter(|chord| chord.first.matches(key_event));
+        match starts.clone().find(|chord| chord.second.is_none()) {
+            Some(_) => ChordAction::OpenChat,
+            None if starts.next().is_some() => {
+                self.held = Some((*key_event, now));
+                ChordAction::Hold
+            }
+            None => ChordAction::Forward(None),
+        }
+    }
+
+    /// Other input is about to reach the shell: the held first key, to be sent before it
+    pub fn release(&mut self) -> Option<KeyEvent> {
+        self.held.take().map(|(first, _)| first)
+    }
+
+    /// The held first key, once the chord timed out
+    pub fn release_expired(&mut self, now: Instant) -> Option<KeyEvent> {
+        match self.held {
+            Some((_, at)) if now.duration_since(at) >= self.timeout => self.release(),
+            _ => None,
+        }
+    }
+
+    /// How long to wait for input: up to `idle`, less while the first key of a chord is held
+    pub fn poll_timeout(&self, now: Instant, idle: Duration) -> Duration {
+        match self.held {
+            Some((_, at)) => (at + self.timeout).saturating_duration_since(now).min(idle),
+            None => idle,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyModifiers};
+
+    #[test]
+    fn test_single_trigger_with_escape() {
+        let now = Instant::now();
+        let mut trigger = TriggerState::new(ChatTrigger::Single);
+        assert_eq!(trigger.bang(now), BangAction::OpenChat);
+
+        trigger.forwarded(b"\\");
+        assert_eq!(trigger.bang(now), BangAction::Literal);
+        assert_eq!(trigger.bang(now), BangAction::OpenChat);
+
+        assert_eq!(TriggerState::new(ChatTrigger::Off).bang(now), BangAction::Literal);
+    }
+
+    #[test]
+    fn test_double_tap() {
+        let start = Instant::now();
+        let mut trigger = TriggerState::new(ChatTrigger::Double);
+        assert_eq!(trigger.bang(start), BangAction::Hold);
+        assert_eq!(trigger.bang(start + Duration::from_millis(150)), BangAction::OpenChat);
+        assert!(!trigger.release());
+
+        // A lone '!' followed by another key goes to the shell before it
+        assert_eq!(trigger.bang(start), BangAction::Hold);
+        assert!(trigger.release());
+        assert!(!trigger.release());
+
+        // Or once the window is over, a late second '!' starts a new tap
+        assert_eq!(trigger.bang(start), BangAction::Hold);
+        let timeout = trigger.poll_timeout(start + Duration::from_millis(300), Duration::from_secs(1));
+        assert_eq!(timeout, Duration::from_millis(100));
+        assert!(!trigger.release_expired(start + Duration::from_millis(399)));
+        assert!(trigger.release_expired(start + DOUBLE_TAP_WINDOW));
+        assert_eq!(trigger.bang(start + Duration::from_secs(1)), BangAction::Hold);
+    }
+
+    #[test]
+    fn test_key_chords() {
+        let chords = ["ctrl+a c", "f6"].map(|spec| KeyChord::try_from(spec.to_string()).unwrap());
+        let mut chords = ChordState::new(chords.to_vec(), Duration::from_millis(800));
+        let ctrl_a = KeyEvent::new(KeyCode::Char('a'), KeyModifiers::CONTROL);
+        let c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::NONE);
+        let start = Instant::now();
+
+        let f6 = KeyEvent::new(KeyCode::F(6), KeyModifiers::NONE);
+        assert_eq!(chords.key(&f6, start), ChordAction::OpenChat);
+        assert_eq!(chords.key(&c, start), ChordAction::Forward(None));
+
+        assert_eq!(chords.key(&ctrl_a, start), ChordAction::Hold);
+        assert_eq!(chords.key(&c, start + Duration::from_millis(300)), ChordAction::OpenChat);
+
+        // tmux's own Ctrl+A x: both keys reach the shell, in order
+        let x = KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE);
+        assert_eq!(chords.key(&ctrl_a, start), ChordAction::Hold);
+        assert_eq!(chords.key(&x, start), ChordAction::Forward(Some(ctrl_a)));
+
+        // Too slow: the first key goes on its own, the late 'c' is typed
+        assert_eq!(chords.key(&ctrl_a, start), ChordAction::Hold);
+        let timeout = chords.poll_timeout(start + Duration::from_millis(500), Duration::from_secs(1));
+        assert_eq!(timeout, Duration::from_millis(300));
+        assert_eq!(chords.release_expired(start + Duration::from_millis(799)), None);
+        assert_eq!(chords.release_expired(start + Duration::from_millis(800)), Some(ctrl_a));
+        assert_eq!(chords.key(&c, start + Duration::from_secs(1)), ChordAction::Forward(None));
+
+        // A paste releases it too
+        assert_eq!(chords.key(&ctrl_a, start), ChordAction::Hold);
+        assert_eq!(chords.release(), Some(ctrl_a));
+        assert_eq!(chords.release(), None);
+    }
+}
@@ -0,0 +1,245 @@
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Alignment, Constraint, Direction, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, Paragraph},
+    Frame, Terminal,
+};
+use std::io::Stdout;
+
+use crate::capture::strip_ansi;
+use crate::keys;
+use crate::theme::Theme;
+
+/// Recent shell output, browsed read-only outside of the AI chat
+pub struct ScrollbackView {
+    lines: Vec<String>,
+    top: usize, // Index of the first visible line
+    height: usize, // Visible lines, updated on every render
+    query: String,
+    editing_query: bool, // Typing after '/', keys go to the query
+    current_match: Option<usize>,
+    status: Option<String>,
+}
+
+impl ScrollbackView {
+    /// Viewer over raw output bytes, opened at the bottom
+    pub fn new(output: &[u8]) -> Self {
+        let lines = output_lines(output);
+        Self {
+            top: lines.len(),
+            lines,
+            height: 0,
+            query: String::new(),
+            editing_query: false,
+            current_match: None,
+            status: None,
+        }
+    }
+
+    fn max_top(&self) -> usize {
+        self.lines.len().saturating_sub(self.height)
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        self.top = self.top.min(self.max_top()).saturating_sub(n);
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        self.top = (self.top + n).min(self.max_top());
+    }
+
+    /// Jump to the next match of the query, towards older output unless `newer`
+    /// Wraps around at either end, like `less`
+    pub fn search(&mut self, newer: bool) {
+        if self.query.is_empty() {
+            return;
+        }
+        let from = self.current_match.unwrap_or(self.top.min(self.lines.len()));
+        match find_line(&self.lines, &self.query, from, newer) {
+            Some(index) => {
+                self.current_match = Some(index);
+                self.status = None;
+                // Show the match in the middle of the screen
+                self.top = index.saturating_sub(self.height / 2).min(self.max_top());
+            }
+            None => {
+                self.current_match = None;
+                self.status = Some(format!("Motif introuvable: {}", self.query));
+            }
+        }
+    }
+
+    /// Handle a key press, false once the viewer should close
+    pub fn handle_key(&mut self, key_event: KeyEvent) -> bool {
+        if self.editing_query {
+            match key_event.code {
+                KeyCode::Enter => {
+                    self.editing_query = false;
+                    self.current_match = None;
+                    self.search(false);
+                }
+                KeyCode::Esc => self.editing_query = false,
+                KeyCode::Backspace => {
+                    self.query.pop();
+                }
+                KeyCode::Char(c) if !key_event.modifiers.contains(KeyModifiers::CONTROL) => self.query.push(c),
+                _ => {}
+            }
+            return true;
+        }
+
+        let page = self.height.max(1);
+        match key_event.code {
+            KeyCode::Esc | KeyCode::Char('q') => return false,
+            KeyCode::Char('c') if key_event.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Up | KeyCode::Char('k') => self.scroll_up(1),
+            KeyCode::Down | KeyCode::Char('j') => self.scroll_down(1),
+            KeyCode::PageUp => self.scroll_up(page),
+            KeyCode::PageDown | KeyCode::Char(' ') => self.scroll_down(page),
+            KeyCode::Home | KeyCode::Char('g') => self.top = 0,
+            KeyCode::End | KeyCode::Char('G') => self.top = self.max_top(),
+            KeyCode::Char('/') => {
+                self.query.clear();
+                self.editing_query = true;
+                self.status = None;
+            }
+            KeyCode::Char('n') => self.search(false),
+            KeyCode::Char('N') => self.search(true),
+            _ => {}
+        }
+        true
+    }
+}
+
+/// Lines of output as the viewer shows them: escapes removed, carriage returns resolved
+pub fn output_lines(output: &[u8]) -> Vec<String> {
+    let text = strip_ansi(&String::from_utf8_lossy(output));
+    let text = text.strip_suffix('\n').unwrap_or(&text);
+    text.split('\n')
+        .map(|line| {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            // A bare carriage return redraws the line (progress bars), keep what was drawn last
+            let line = line.rsplit('\r').next().unwrap_or_default();
+            line.replace('\t', "    ").chars().filter(|c| !c.is_control()).collect()
+        })
+        .collect()
+}
+
+/// Closest line containing `query` (case-insensitive) before `from`, or after it when `newer`
+fn find_line(lines: &[String], query: &str, from: usize, newer: bool) -> Option<usize> {
+    let query = query.to_lowercase();
+    let len = lines.len();
+    (1..=len)
+        .map(|step| if newer { (from + step) % len } else { (from + len - step % len) % len })
+        .find(|&index| lines[index].to_lowercase().contains(&query))
+}
+
+/// Show the viewer until the user closes it
+pub fn run_viewer(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    view: &mut ScrollbackView,
+    theme: &Theme,
+) -> Result<()> {
+    loop {
+        terminal.draw(|frame| render(frame, view, theme))?;
+
+        match event::read()? {
+            Event::Key(key_event) if keys::is_key_input(&key_event) && !view.handle_key(key_event) => {
+                return Ok(());
+            }
+            Event::Resize(_, _) => terminal.autoresize()?,
+            _ => {}
+        }
+    }
+}
+
+fn render(frame: &mut Frame, view: &mut ScrollbackView, theme: &Theme) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(1)])
+        .split(frame.area());
+
+    view.height = chunks[0].height.saturating_sub(2) as usize;
+    view.top = view.top.min(view.max_top());
+
+    let end = (view.top + view.height).min(view.lines.len());
+    let lines: Vec<Line> = (view.top..end)
+        .map(|index| {
+            let line = Line::raw(view.lines[index].as_str());
+            if view.current_match == Some(index) {
+                line.style(Style::default().add_modifier(Modifier::REVERSED))
+            } else {
+                line
+            }
+        })
+        .collect();
+
+    let title = format!(
+        "📜 Sortie du shell [{}-{}/{}] (↑↓ PgUp/PgDn défiler | / rechercher | n/N plus ancien/récent | q quitter)",
+        (view.top + 1).min(end),
+        end,
+        view.lines.len()
+    );
+    let paragraph = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(theme.chat_border))
+            .title(title)
+            .title_alignment(Alignment::Center),
+    );
+    frame.render_widget(paragraph, chunks[0]);
+
+    let footer = if view.editing_query {
+        format!("/{}", view.query)
+    } else {
+        view.status.clone().unwrap_or_default()
+    };
+    frame.render_widget(Paragraph::new(footer).style(Style::default().fg(theme.highlight)), chunks[1]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_lines_cleans_terminal_output() {
+        let output = b"\x1b[32mok\x1b[0m\r\n10%\r50%\r100%\r\nfin\tbis\x07\n";
+        assert_eq!(output_lines(output), vec!["ok", "100%", "fin    bis"]);
+    }
+
+    #[test]
+    fn test_search_wraps_in_both_directions() {
+        let mut view = ScrollbackView::new(b"error one\nok\nERROR two\nok\n");
+        view.height = 1;
+        view.query = "error".to_string();
+
+        // From the bottom, the closest older match comes first
+        view.search(false);
+        assert_eq!(view.current_match, Some(2));
+        view.search(false);
+        assert_eq!(view.current_match, Some(0));
+        view.search(false);
+        assert_eq!(view.current_match, Some(2));
+        view.search(true);
+        assert_eq!(view.current_match, Some(0));
+
+        view.query = "absent".to_string();
+        view.search(false);
+        assert_eq!(view.current_match, None);
+        assert!(view.status.as_deref().unwrap().contains("absent"));
+    }
+
+    #[test]
+    fn test_scroll_is_clamped() {
+        let mut view = ScrollbackView::new(b"a\nb\nc\nd\n");
+        view.height = 2;
+        view.scroll_down(10);
+        assert_eq!(view.top, 2);
+        view.scroll_up(10);
+        assert_eq!(view.top, 0);
+    }
+}